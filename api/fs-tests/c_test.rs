@@ -1,4 +1,5 @@
-use super::FSName;
+use super::{CustomDirFileSystemError, FSName};
+use crate::b_inode_support::CustomInodeFileSystemError;
 use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport};
 use cplfs_api::types::{FType, InodeLike, SuperBlock, DIRENTRY_SIZE};
 use std::path::PathBuf;
@@ -49,10 +50,10 @@ fn root() {
     let path = disk_prep_path("root");
     let mut my_fs = FSName::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
     assert_eq!(my_fs.i_get(1).unwrap().get_ft(), FType::TDir);
-    assert_eq!(my_fs.i_get(0).unwrap().get_ft(), FType::TFree);
+    assert!(matches!(my_fs.i_get(0), Err(CustomDirFileSystemError::GivenError(CustomInodeFileSystemError::ReservedInode)))); //inode 0 is reserved
     my_fs.i_free(1).unwrap(); //inode has been allocated, but should not be deallocated, as the root references itself
     my_fs.i_free(1).unwrap(); //so this should work twice
-    assert!(my_fs.i_free(0).is_err()); //inode has not been allocated
+    assert!(matches!(my_fs.i_free(0), Err(CustomDirFileSystemError::GivenError(CustomInodeFileSystemError::ReservedInode)))); //inode 0 is reserved
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
 }
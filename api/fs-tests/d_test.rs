@@ -68,10 +68,10 @@ fn root() {
     //The root should have been allocated a block for its data
     my_fs.b_free(0).unwrap();
 
-    assert_eq!(my_fs.i_get(0).unwrap().get_ft(), FType::TFree);
+    assert!(my_fs.i_get(0).is_err()); //inode 0 is reserved
     my_fs.i_free(1).unwrap(); //inode has been allocated, but should not be deallocated, as the root references itself
     my_fs.i_free(1).unwrap(); //so this should work twice
-    assert!(my_fs.i_free(0).is_err()); //inode has not been allocated
+    assert!(my_fs.i_free(0).is_err()); //inode 0 is reserved
 
     let dev = my_fs.unmountfs();
     utils::disk_destruct(dev);
@@ -3,6 +3,7 @@
 //! When initializing the controller, you have to provide it with either a path to a non-existing file, which will then be created and used as the contents of your device, or to an existing file, which will be opened and the contents of which will be checked.
 //! Provides a basic block read and write operation on a device at a given offset.
 //! The memory-mapped file is what the read and write functions operate on.
+//! Alternatively, a device can be kept entirely in memory, without any file backing it, see `Device::new_in_memory`.
 //!
 //! *EXTRA*: Note that this explicit block-level abstraction is not required for a file system at this level of abstraction, but added it to make our model a more realistic representation of a real-life file system.
 //! No provisions have been made to properly lock and unlock the file that is used to back the file system, so do not fiddle with it while a file system is running, as this leads to undefined behavior. (e.g. the fs2 crate could be used to explicitly implement locking, if so desired)
@@ -31,10 +32,36 @@ pub struct Device {
     pub block_size: u64,
     /// Total number of blocks this disk consists of
     pub nblocks: u64,
-    /// Path to the file in your file system that is used as a storage area to emulate the disk
+    /// Path to the file in your file system that is used as a storage area to emulate the disk.
+    /// Empty for a device that is kept in memory.
     path: PathBuf,
     /// Memory-mapped contents of the above file. This is what is manipulated in the read and write functions.
-    contents: MmapMut,
+    contents: Contents,
+}
+
+/// Storage area of a device
+#[derive(Debug)]
+enum Contents {
+    /// Memory-mapped file
+    Mapped(MmapMut),
+    /// Plain buffer in memory, without a file backing it
+    Memory(Vec<u8>),
+}
+
+impl Contents {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Contents::Mapped(mmap) => &mmap[..],
+            Contents::Memory(bytes) => &bytes[..],
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Contents::Mapped(mmap) => &mut mmap[..],
+            Contents::Memory(bytes) => &mut bytes[..],
+        }
+    }
 }
 
 /// Small enum, used to specify whether we expect to open a new file system
@@ -62,8 +89,10 @@ impl Drop for Device {
     /// This implementation of drop makes sure all writes are persisted at the end, before we release ownership of our device and its controller
     /// We only need to persist these writes if the file backing this disk actually still exists
     fn drop(&mut self) {
-        if self.path.exists() {
-            self.contents.flush().unwrap();
+        if let Contents::Mapped(mmap) = &self.contents {
+            if self.path.exists() {
+                mmap.flush().unwrap();
+            }
         }
     }
 }
@@ -79,13 +108,54 @@ impl Device {
         let path_buf = path.as_ref().to_path_buf();
         let mmapf = mmap_path(path, block_size * nblocks, ds)?;
         Ok(Device {
-            block_size: block_size,
-            nblocks: nblocks,
+            block_size,
+            nblocks,
             path: path_buf,
-            contents: mmapf,
+            contents: Contents::Mapped(mmapf),
         })
     }
 
+    /// Create a *new* disk device that is kept in memory only, given its `block_size` and number of blocks.
+    /// This new device will have contents 0 at each address. No file is created, so the device does not need to be destructed,
+    /// and its contents are lost when it is dropped, unless they are saved through `to_bytes` first.
+    pub fn new_in_memory(block_size: u64, nblocks: u64) -> Device {
+        Device {
+            block_size,
+            nblocks,
+            path: PathBuf::new(),
+            contents: Contents::Memory(vec![0; (block_size * nblocks) as usize]),
+        }
+    }
+
+    /// Load a disk device that is kept in memory only from the contents `bytes`, as returned by `to_bytes`.
+    /// This function will return an error, if the number of bytes does not match `block_size` and `nblocks`.
+    pub fn from_bytes(block_size: u64, nblocks: u64, bytes: Vec<u8>) -> error_given::Result<Device> {
+        if bytes.len() as u64 != block_size * nblocks {
+            return Err(APIError::ControllerInput(
+                "Device size does not match provided size",
+            ));
+        }
+        Ok(Device {
+            block_size,
+            nblocks,
+            path: PathBuf::new(),
+            contents: Contents::Memory(bytes),
+        })
+    }
+
+    /// Copy of the entire contents of this device, e.g. to save an image of a device kept in memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.contents.as_slice().to_vec()
+    }
+
+    /// Whether this device is kept in memory only, rather than backed by a file
+    pub fn is_in_memory(&self) -> bool {
+        match self.contents {
+            Contents::Mapped(_) => false,
+            Contents::Memory(_) => true,
+        }
+    }
+
     /// Create a *new* disk device, given:
     /// - A `path` to store its image
     /// - A `block_size` to define the size of each unit to be read or written, in bytes
@@ -116,8 +186,11 @@ impl Device {
     /// End the lifetime of this disk, and remove the file backing it on disk
     /// Assumes that you have not made any other links to the backing file
     /// Panics if removing the file fails
+    /// A device kept in memory is simply dropped
     pub fn destruct(self) {
-        remove_file(&self.path).unwrap();
+        if !self.is_in_memory() {
+            remove_file(&self.path).unwrap();
+        }
     }
 
     /// Size of this device in bytes
@@ -125,7 +198,7 @@ impl Device {
         self.block_size * self.nblocks
     }
 
-    /// Path of the file backing this device
+    /// Path of the file backing this device, empty for a device kept in memory
     pub fn device_path(&self) -> &Path {
         &self.path
     }
@@ -144,7 +217,7 @@ impl Device {
         }
         let start = addr as usize;
         let end = (addr + nb) as usize;
-        Ok(self.contents[start..end].into()) //Note: this can theoretically still cause runtime errors
+        Ok(self.contents.as_slice()[start..end].into()) //Note: this can theoretically still cause runtime errors
     }
 
     /// Read the block with index `index` from the device
@@ -168,7 +241,7 @@ impl Device {
         }
         let start = addr as usize;
         let end = (addr as usize) + b.len();
-        self.contents[start..end].copy_from_slice(b);
+        self.contents.as_mut_slice()[start..end].copy_from_slice(b);
         Ok(())
     }
 
@@ -387,4 +460,25 @@ mod tests {
         //Make sure the file has actually been destroyed
         assert!(!path.exists());
     }
+
+    // Here we test a device that is kept in memory, and its round trip through a byte buffer.
+    #[test]
+    fn in_memory_disk_test() {
+        let mut dev = Device::new_in_memory(BLOCK_SIZE, NBBLOCKS);
+        assert!(dev.is_in_memory());
+        assert_eq!(dev.read_block(4).unwrap(), Block::new_zero(4, BLOCK_SIZE));
+        assert!(dev.read_block(NBBLOCKS).is_err());
+        assert!(dev.write_block(&Block::new_zero(1, BLOCK_SIZE + 1)).is_err());
+
+        let bw = Block::new(4, (0..10).collect());
+        dev.write_block(&bw).unwrap();
+        let bytes = dev.to_bytes();
+        assert_eq!(bytes.len() as u64, dev.device_size());
+        dev.destruct();
+
+        assert!(Device::from_bytes(BLOCK_SIZE, NBBLOCKS + 1, bytes.clone()).is_err());
+        let dev = Device::from_bytes(BLOCK_SIZE, NBBLOCKS, bytes).unwrap();
+        assert_eq!(dev.read_block(4).unwrap(), bw);
+        assert_eq!(dev.read_block(3).unwrap(), Block::new_zero(3, BLOCK_SIZE));
+    }
 }
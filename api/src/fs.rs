@@ -232,7 +232,7 @@ pub trait DirectorySupport: InodeSupport {
 
     /// Set the name of this directory entry to the given `name`, if the given name is valid, i.e. it is
     ///- non-empty
-    ///- consists of alphanumeric characters only, or is equal to "." or ".."
+    ///- consists of alphanumeric characters, `_`, `-` and `.` only (which includes "." and "..")
     ///- is sufficiently short when converted to characters
     /// If the `name` is shorter than `DIRNAME_SIZE`, insert a '\0' at the end so you can still correctly read it after.
     /// Returns `None` in case of an invalid name
@@ -232,7 +232,7 @@ lazy_static! {
 pub const DIRECT_POINTERS: u64 = 12;
 
 /// Enum describing file types
-/// Currently, either a file `T_FILE`, a directory `T_DIR` or a free inode `T_Free`
+/// Currently, either a file `T_FILE`, a directory `T_DIR`, a symbolic link `T_SYMLINK` or a free inode `T_Free`
 /// The file type `T_FREE` is used to signify a free inode, that can be used to allocate a new file or directory.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
 pub enum FType {
@@ -242,6 +242,8 @@ pub enum FType {
     TFile,
     /// Free file type
     TFree,
+    /// Symbolic link, whose contents are the path it refers to
+    TSymlink,
 }
 impl Default for FType {
     fn default() -> FType {
@@ -255,7 +257,7 @@ impl Default for FType {
 /// *EXTRA*: In real-life file systems, files also contain a field pointing to a data block containing more data blocks, called an indirect pointer.
 /// For simplicity reasons, we do not support this in the current file system.
 /// In other words, files are made up of a total of at most `DIRECT_POINTERS` blocks.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
 pub struct DInode {
     /// Registers the file type
     pub ft: FType,
@@ -265,6 +267,19 @@ pub struct DInode {
     pub size: u64,
     /// A list of up to `DIRECT_POINTERS` valid block addresses (counting from 0, *not* from the start of the data block region), to specify where the contents of this file are stored.
     pub direct_blocks: [u64; DIRECT_POINTERS as usize],
+    /// Maximum size in bytes this file is allowed to grow to, or 0 if the file is only limited by the number of blocks it can point to.
+    pub max_size: u64,
+    /// Number of entries in use if this inode is a directory, kept up to date by the directory layer. Always 0 for other file types.
+    pub entry_count: u32,
+    /// Bit flags with extra information about how the file system treats this inode. 0 when no flags are set.
+    pub flags: u8,
+    /// Address of the indirect block, holding the addresses of the blocks of the file that follow the `DIRECT_POINTERS` direct ones, or 0 if the file has no indirect block.
+    /// Only file systems supporting indirect blocks allocate one.
+    pub indirect_block: u64,
+    /// Time the contents of the file were last modified, in seconds since the Unix epoch, or 0 if this is not kept track of.
+    pub mtime: u64,
+    /// Time the file was last changed in any way, contents or metadata, in seconds since the Unix epoch, or 0 if this is not kept track of.
+    pub ctime: u64,
 }
 
 lazy_static! {
@@ -282,7 +297,7 @@ pub const ROOT_INUM: u64 = 1;
 /// Additionally contains the number of the inode `inum`.
 /// This information is not required as long as the inode is stored on disk, as it is implicit from the block in which the inode is stored.
 /// This is analogous to a [`Block`](../block/struct.Block.html) explicitly keeping track of its block number
-#[derive(Debug, Default, PartialEq, Eq)]
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct Inode {
     /// inode number
     pub inum: u64,
@@ -323,13 +338,19 @@ impl InodeLike for Inode {
         if nlink > u16::MAX as u64 {
             return None;
         }
-        if blocks.len() > DIRECT_POINTERS as usize {
+        // one block more than the direct ones is taken as the address of the indirect block
+        if blocks.len() > DIRECT_POINTERS as usize + 1 {
             return None;
         }
 
         let mut db = [0; DIRECT_POINTERS as usize];
+        let mut indirect_block = 0;
         for i in 0..blocks.len() {
-            db[i] = blocks[i];
+            if i == DIRECT_POINTERS as usize {
+                indirect_block = blocks[i];
+            } else {
+                db[i] = blocks[i];
+            }
         }
 
         let di = DInode {
@@ -337,6 +358,12 @@ impl InodeLike for Inode {
             nlink: nlink as u16,
             size,
             direct_blocks: db,
+            max_size: 0,
+            entry_count: 0,
+            flags: 0,
+            indirect_block,
+            mtime: 0,
+            ctime: 0,
         };
         Some(Inode::new(inum, di))
     }
@@ -351,7 +378,11 @@ impl InodeLike for Inode {
         self.disk_node.size
     }
     fn get_block(&self, i: u64) -> u64 {
-        if DIRECT_POINTERS <= i {
+        // the addresses held by the indirect block can only be read from the device, so return the indirect block itself
+        if i == DIRECT_POINTERS {
+            return self.disk_node.indirect_block;
+        }
+        if DIRECT_POINTERS < i {
             return 0;
         }
         self.disk_node.direct_blocks[i as usize]
@@ -442,6 +473,12 @@ mod block_tests {
             nlink: 13,
             size: 142,
             direct_blocks: [1000; DIRECT_POINTERS as usize],
+            max_size: 0,
+            entry_count: 0,
+            flags: 0,
+            indirect_block: 0,
+            mtime: 0,
+            ctime: 0,
         };
 
         //Testing some length consistency, and the global variable DINODE_SIZE
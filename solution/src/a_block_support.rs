@@ -16,7 +16,11 @@
 //! ...
 //!
 
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
 use std::path::Path;
+use std::sync::Arc;
 
 // If you want to import things from the API crate, do so as follows:
 use cplfs_api::{controller::Device, error_given, fs::FileSysSupport, types::{DINODE_SIZE}};
@@ -26,312 +30,2593 @@ use cplfs_api::types::SuperBlock;
 use cplfs_api::fs::BlockSupport;
 use cplfs_api::types::{Block};
 
+use crate::checksum::{crc32, Crc64};
+
 // use auxiliary package thiserror to make the definition of errors easier
 use thiserror::Error;
 
 /// Custom type 
 pub type FSName = CustomBlockFileSystem;
 
+/// Storage a file system can live on, which allows plugging in backends other than `Device`, e.g. a network-backed or compressed one.
+/// Blocks are numbered from 0 up to `nblocks`, and every block holds `block_size` bytes.
+pub trait BlockDevice {
+    /// Read the block with number `i`
+    fn read_block(&self, i: u64) -> error_given::Result<Block>;
+
+    /// Write the block `b`, at the position given by its block number
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()>;
+
+    /// Size of every block in bytes
+    fn block_size(&self) -> u64;
+
+    /// Number of blocks on the device
+    fn nblocks(&self) -> u64;
+}
+
+impl BlockDevice for Device {
+    fn read_block(&self, i: u64) -> error_given::Result<Block> {
+        return Device::read_block(self, i)
+    }
+
+    fn write_block(&mut self, b: &Block) -> error_given::Result<()> {
+        return Device::write_block(self, b)
+    }
+
+    fn block_size(&self) -> u64 {
+        return self.block_size
+    }
+
+    fn nblocks(&self) -> u64 {
+        return self.nblocks
+    }
+}
+
 /// Custom block file system data type
-pub struct CustomBlockFileSystem {
+/// Dropping a file system flushes it, but prefer handing back its device explicitly through `unmountfs`
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
+pub struct CustomBlockFileSystem<D: BlockDevice = Device> {
     /// Device type representing the state of the hard drive disk 
     /// allows to read disk blocks from the disk, and write disk blocks to the disk
-    pub device: Device, 
+    /// Only `None` once the device has been handed back by `unmountfs`
+    device: Option<D>,
     /// Cached SuperBlock
-    pub superblock: SuperBlock
+    pub superblock: SuperBlock,
+    // the order in which free data blocks are handed out
+    alloc_mode: AllocMode,
+    // data blocks with an index below this one are never handed out by the allocator
+    reserved_data_blocks: u64,
+    // index right after the data block `b_alloc` handed out last, where it continues scanning in `AllocMode::NextFit`
+    last_alloc: u64,
+    // callback invoked on every block access, in a RefCell since reads only borrow the file system
+    io_hook: RefCell<Option<IoHook>>,
+    // write-through cache of recently used blocks, in a RefCell since reads only borrow the file system
+    block_cache: RefCell<BlockCache>,
+    // whether every block has a CRC-32 in the checksum table at the end of the disk, which `b_get` verifies
+    checksums: bool,
+    // whether the file system was mounted through `mountfs_ro`, in which case nothing may be written to the device
+    readonly: bool,
+    // number of calls to the block operations, in a Cell since reads only borrow the file system
+    op_stats: Cell<OpStats>,
+    // number of free data blocks, kept up to date on every write to the bitmap; `None` until the bitmap has been scanned
+    free_blocks: Cell<Option<u64>>,
+    // whether `free_blocks` or `reserved_data_blocks` changed since they were last written to block 0
+    header_dirty: bool,
+    // the count of free data blocks found on disk when mounting, if it did not match the bitmap
+    stale_free_count: Option<u64>
 }
 
+/// Marker stored in the superblock block, right after the superblock itself, on file systems created through `mkfs_checksummed`
+const CHECKSUM_MAGIC: u64 = 0x4353_554D_4D45_4431;
 
-impl CustomBlockFileSystem {
-    /// Create a new CustomBlockFileSystem given a Device dev
-    pub fn new(dev: Device, sb: SuperBlock) -> CustomBlockFileSystem {
-        CustomBlockFileSystem { device: dev, superblock: sb }
-    }  
+/// Offset of `CHECKSUM_MAGIC` within block 0, right after the serialized superblock
+const CHECKSUM_MAGIC_OFFSET: u64 = std::mem::size_of::<SuperBlock>() as u64;
+
+/// Marker stored in the superblock block by `mkfs`, after `CHECKSUM_MAGIC`, so that `mountfs` can tell our disks from other files
+const FS_MAGIC: u64 = 0x4350_4C46_5344_534B;
+
+/// Offset of `FS_MAGIC` within block 0
+const FS_MAGIC_OFFSET: u64 = CHECKSUM_MAGIC_OFFSET + 8;
+
+/// Version of the on-disk format written by `mkfs`, stored right after `FS_MAGIC`
+const FORMAT_VERSION: u32 = 1;
+
+/// Offset of the format version within block 0
+const FORMAT_VERSION_OFFSET: u64 = FS_MAGIC_OFFSET + 8;
+
+/// Offset within block 0 of the number of free data blocks, right after the format version
+const FREE_BLOCKS_OFFSET: u64 = FORMAT_VERSION_OFFSET + 4;
+
+/// Offset within block 0 of the number of reserved data blocks, right after the number of free data blocks
+const RESERVED_BLOCKS_OFFSET: u64 = FREE_BLOCKS_OFFSET + 8;
+
+/// Size in bytes of a single entry in the checksum table
+const CHECKSUM_SIZE: u64 = 4;
+
+/// Number of blocks the block cache holds, unless configured otherwise
+pub const DEFAULT_CACHE_CAPACITY: usize = 32;
+
+/// Effectiveness of the block cache, as returned by `cache_stats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of block reads served from the cache
+    pub hits: u64,
+    /// Number of block reads that had to go to the device
+    pub misses: u64
 }
 
-#[derive(Error, Debug)]
-/// Custom type for errors in CustomBlockFileSystem
-pub enum CustomBlockFileSystemError {
-    #[error("invalid SuperBlock was detected")]
-    /// Error thrown when an invalid superblock is encountered
-    InvalidSuperBlock,
-    #[error("SuperBlock and device are not compatible")]
-    /// Error thrown when the device and superblock don't agree
-    IncompatibleDeviceSuperBlock,
-    #[error("The data index is out of bounds for this device")]
-    /// Thrown when the block index provided is larger than ndatablocks - 1
-    DataIndexOutOfBounds,
-    #[error("The block that was tried to be freed is already free")]
-    /// Thrown when the block that is trying to be freed is already free
-    BlockIsAlreadyFree,
-    #[error("There is no free data block")]
-    /// Thrown when there is no free data block available and one is requested 
-    NoFreeDataBlock,
-    /// The input provided to some method in the controller layer was invalid
-    #[error("API error")]
-    GivenError(#[from] error_given::APIError)
+/// Number of calls to the basic operations of a file system, as returned by `stats`.
+/// Every layer ends up calling these, so they show how much work an operation of a higher layer takes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpStats {
+    /// Number of blocks read, through `b_get` or `b_get_ref`
+    pub b_get: u64,
+    /// Number of blocks written, through `b_put` or `b_put_partial`
+    pub b_put: u64,
+    /// Number of calls to `b_alloc`
+    pub b_alloc: u64,
+    /// Number of calls to `b_free`, plus the number of blocks freed by `b_free_range`
+    pub b_free: u64,
+    /// Number of calls to `i_get`, only counted by file systems supporting inodes
+    pub i_get: u64,
+    /// Number of calls to `i_put`, only counted by file systems supporting inodes
+    pub i_put: u64
 }
 
-impl FileSysSupport for CustomBlockFileSystem {
-    fn sb_valid(sb: &SuperBlock) -> bool {
-        // the bitmap starts after the inodes
-        if !(sb.inodestart < sb.bmapstart) {
-            return false
+/// Least recently used cache of blocks, keyed by block number
+struct BlockCache {
+    // maximal number of blocks kept, 0 disables the cache
+    capacity: usize,
+    // cached blocks, from least to most recently used
+    blocks: VecDeque<Arc<Block>>,
+    stats: CacheStats
+}
+
+impl BlockCache {
+    fn new(capacity: usize) -> BlockCache {
+        BlockCache { capacity, blocks: VecDeque::new(), stats: CacheStats::default() }
+    }
+
+    /// Look up block `i`, marking it as most recently used, and count the hit or miss
+    fn get(&mut self, i: u64) -> Option<Arc<Block>> {
+        match self.blocks.iter().position(|block| block.block_no == i) {
+            Some(position) => {
+                let block = self.blocks.remove(position).unwrap();
+                self.blocks.push_back(Arc::clone(&block));
+                self.stats.hits += 1;
+                return Some(block)
+            },
+            None => {
+                self.stats.misses += 1;
+                return None
+            }
+        }
+    }
+
+    /// Store `block` as the most recently used one, replacing an older copy and evicting the least recently used block if needed
+    fn insert(&mut self, block: Arc<Block>) {
+        if let Some(position) = self.blocks.iter().position(|cached| cached.block_no == block.block_no) {
+            self.blocks.remove(position);
         }
-        // at least one block for the bit map
-        if !(sb.bmapstart < sb.datastart) {
-            return false
+        if self.capacity == 0 {
+            return
         }
-        // One block for the Superblock
-        let order_cond3 =  sb.inodestart > 0;  
-        // The inode region has to be sufficiently large to hold ninodes inodes 
-        let inode_cond =  *DINODE_SIZE * sb.ninodes <= (sb.bmapstart - sb.inodestart) * sb.block_size;
-        // The bitmap needs to provide place for 1 bit for every datablock
-        let hold_cond1 = (sb.datastart - sb.bmapstart) * sb.block_size * 8 >= sb.ndatablocks;
-        // There needs to be enough space for the datablocks
-        let hold_cond2 = sb.datastart + sb.ndatablocks <= sb.nblocks;
-        // The regions have to physically fit on the disk together, i.e. fall within the first nblocks blocks
-        let fit_cond1 = 1 + (sb.bmapstart - sb.inodestart) + (sb.datastart - sb.bmapstart) + sb.ndatablocks <= sb.nblocks;
-        if order_cond3 && hold_cond1 && hold_cond2 && inode_cond && fit_cond1 {
-            return true
+        if self.blocks.len() == self.capacity {
+            self.blocks.pop_front();
         }
-        else {
-            return false
-        }  
+        self.blocks.push_back(block);
     }
 
-    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>{
-        // Check if the given superblock is a valid file system superblock
-        let sb_cond = Self::sb_valid(sb);
-        if !sb_cond {
-            return Err(CustomBlockFileSystemError::InvalidSuperBlock);
-        } else  {
-           //Create a new Device at the given path, to allow the file system to communicate with it
-           let mut device = Device::new(path, sb.block_size, sb.nblocks)?;
-           // A super block containing the file system metadata at block index 0
-           let mut block = device.read_block(0)?;
-           block.serialize_into(sb, 0)?;
-           // write this block to the device
-           device.write_block(&block)?;
-           return Ok(CustomBlockFileSystem::new(device, *sb));
-        }     
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.blocks.len() > capacity {
+            self.blocks.pop_front();
+        }
     }
+}
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        // The superblock is a valid superblock 
-        let sb_block = dev.read_block( 0)?;
-        let superblock = sb_block.deserialize_from::<SuperBlock>(0)?;
-        if Self::sb_valid(&superblock) {
-            // The block size and number of blocks of the device and superblock agree
-            if dev.block_size == superblock.block_size && dev.nblocks == superblock.nblocks {
-                return Ok(CustomBlockFileSystem::new(dev, superblock))
-            }
-            else {
-                return Err(CustomBlockFileSystemError::IncompatibleDeviceSuperBlock);
-            }            
-        }
-        else {
+/// Kind of access to a block on the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockIoOp {
+    /// The block was read through `b_get`
+    Get,
+    /// The block was written through `b_put`
+    Put,
+    /// The block was written through `b_put_partial`, which only changed `len` bytes starting at `start`
+    PutPartial {
+        /// Offset of the first changed byte in the block
+        start: u64,
+        /// Number of changed bytes
+        len: u64
+    }
+}
+
+/// Callback invoked on every block access, see `CustomBlockFileSystem::set_io_hook`.
+/// It has to be `Send`, so that the file system can still be moved to another thread.
+pub type IoHook = Box<dyn FnMut(BlockIoEvent) + Send>;
+
+/// Access to a single block, as reported to the I/O hook of a file system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockIoEvent {
+    /// Number of the block on disk
+    pub block: u64,
+    /// What happened to the block
+    pub op: BlockIoOp
+}
+
+/// Order in which allocators hand out free entries.
+/// A mode is a guarantee, not a hint: whatever the allocators do internally to speed up their search,
+/// they always hand out exactly the entry their mode describes, so tests can rely on the allocation order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AllocMode {
+    /// Hand out the lowest free index first. This is the default, so a fresh file system allocates 0, 1, 2, ... in order.
+    #[default]
+    LowestFirst,
+    /// Hand out the highest free index first
+    HighestFirst,
+    /// Hand out the first free index after the one handed out last, wrapping around to the lowest index at the end.
+    /// Allocating many blocks one at a time then no longer rescans the blocks that were handed out before,
+    /// but blocks that are freed are only handed out again once the allocator has wrapped around.
+    /// Only data blocks are allocated this way, inodes are handed out lowest first in this mode.
+    NextFit
+}
+
+// Constructors specific to `Device`, and the functions that do not involve a device at all.
+// Only defining the latter for the default device type keeps calls like `CustomBlockFileSystem::sb_validate` unambiguous.
+impl CustomBlockFileSystem {
+    /// Create a new file system like `mkfs`, on a device that is kept in memory instead of backed by a file.
+    /// Unmounting it hands back the in-memory device, whose contents can be saved through `Device::to_bytes`,
+    /// and mounted again through `Device::from_bytes` and `mountfs`.
+    pub fn mkfs_in_memory(sb: &SuperBlock) -> Result<Self, CustomBlockFileSystemError> {
+        if !Self::sb_valid(sb) {
             return Err(CustomBlockFileSystemError::InvalidSuperBlock);
         }
+        let device = Device::new_in_memory(sb.block_size, sb.nblocks);
+        return Self::mkfs_on_device(device, sb)
     }
 
-    fn unmountfs(self) -> Device {
-        return self.device
+    /// Create a new file system like `mkfs`, where every block gets a CRC-32 stored in a checksum table.
+    /// `b_put` keeps the table up to date and `b_get` verifies blocks read from the device against it, failing with `ChecksumMismatch`.
+    /// The table takes up the last `checksum_blocks(sb)` blocks of the disk, which have to lie after the data region.
+    pub fn mkfs_checksummed<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, CustomBlockFileSystemError> {
+        CustomBlockFileSystem::sb_validate_checksummed(sb).map_err(|_| CustomBlockFileSystemError::InvalidSuperBlock)?;
+        let mut fs = Self::mkfs(path, sb)?;
+        let mut sb_block = fs.b_get(0)?;
+        sb_block.serialize_into(&CHECKSUM_MAGIC, CHECKSUM_MAGIC_OFFSET)?;
+        fs.b_put(&sb_block)?;
+        // the table starts out zeroed, so record the checksum of every block before verifying any of them
+        for i in 0..CustomBlockFileSystem::checksum_table_start(sb) {
+            let block = fs.device().read_block(i)?;
+            fs.record_checksum(&block)?;
+        }
+        fs.checksums = true;
+        return Ok(fs)
     }
 
-    type Error = CustomBlockFileSystemError;
-}
+    /// Number of blocks at the end of the disk that a checksummed file system with superblock `sb` needs for its checksum table
+    pub fn checksum_blocks(sb: &SuperBlock) -> u64 {
+        return (sb.nblocks * CHECKSUM_SIZE + sb.block_size - 1) / sb.block_size;
+    }
 
-impl BlockSupport for CustomBlockFileSystem {
-    //Read the nth block of the entire disk and return it
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
-        let block = self.device.read_block(i)?;
-        return Ok(block)
+    /// Number of the first block of the checksum table
+    fn checksum_table_start(sb: &SuperBlock) -> u64 {
+        return sb.nblocks - CustomBlockFileSystem::checksum_blocks(sb);
     }
 
-    //Write the nth block of the entire disk and return it
-    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
-        let block = self.device.write_block(b)?;
-        return Ok(block);
+    /// Block number and offset within that block of the checksum of block `i`
+    fn checksum_location(sb: &SuperBlock, i: u64) -> (u64, u64) {
+        let per_block = sb.block_size / CHECKSUM_SIZE;
+        return (CustomBlockFileSystem::checksum_table_start(sb) + i / per_block, (i % per_block) * CHECKSUM_SIZE)
     }
 
-    // Free the ith block in the block data region, by setting the ith bit in the free bit map region to zero.
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
-        let superblock = self.sup_get()?;
-        // Index i is out of bounds, if it's higher than the number of data blocks
-        if i > superblock.ndatablocks - 1 {
+    /// Create a new file system like `mkfs`, but reserve the first `reserved` data blocks for special purposes.
+    /// The bitmap bits of the reserved blocks are set right away, and the allocator never hands out these blocks.
+    pub fn mkfs_reserved<P: AsRef<Path>>(path: P, sb: &SuperBlock, reserved: u64) -> Result<Self, CustomBlockFileSystemError> {
+        if reserved > sb.ndatablocks {
             return Err(CustomBlockFileSystemError::DataIndexOutOfBounds);
         }
-        // bitmap can be mutiple blocks large, we have to select the right one
-        let bitmapblockcapacity = superblock.block_size * 8;
-        let block_offset = i / bitmapblockcapacity;
-        let mut bitmap_block = self.b_get(superblock.bmapstart + block_offset)?;
-        // one byte of data
-        let mut byte: [u8; 1] = [0];
-        // the byte we want to read from the bitmap block
-        let byte_offset =  (i % bitmapblockcapacity) / 8;
-        bitmap_block.read_data(&mut byte, byte_offset)?;
-        // because << adds zeros we should do this and invert later
-        let bit_offset =  (i % bitmapblockcapacity) % 8;
-        let set_byte = 0b0000_0001 << bit_offset;
-        // we define the order of the bits within each byte you read from right to left
-        let or = byte[0] | !set_byte;
-        if or == !set_byte {
-            // ith block is already a free block
-            return Err(CustomBlockFileSystemError::BlockIsAlreadyFree);
+        let mut fs = Self::mkfs(path, sb)?;
+        // on a fresh file system, allocating from the low end hands out exactly the reserved blocks
+        for _ in 0..reserved {
+            fs.b_alloc()?;
         }
-        else{
-            let and = byte[0] & !set_byte;
-            let res = bitmap_block.write_data(&[and], byte_offset)?;
-            self.b_put(&bitmap_block)?;
-            return Ok(res)
-        }    
+        fs.set_reserved_data_blocks(reserved);
+        return Ok(fs)
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
-        let superblock = self.sup_get()?;
-        // Index i is out of bounds, if it is higher than the number of data blocks
-        if i > superblock.ndatablocks - 1 {
-            return Err(CustomBlockFileSystemError::DataIndexOutOfBounds)
+    /// Check whether `sb` is a valid superblock like `sb_valid`, but return the reason why it is not.
+    /// When several conditions fail, the first one that is checked is reported: the order of the regions, the bitmap,
+    /// the data region, the overlap of the regions, the inode region and finally the size of the disk.
+    pub fn sb_validate(sb: &SuperBlock) -> Result<(), SbInvalidReason> {
+        // the bitmap starts after the inodes, and
+        // at least one block for the bit map, and
+        // One block for the Superblock
+        if !(sb.inodestart < sb.bmapstart && sb.bmapstart < sb.datastart && sb.inodestart > 0) {
+            return Err(SbInvalidReason::RegionsOutOfOrder)
         }
-        self.b_put(&Block::new_zero(superblock.datastart + i, superblock.block_size))
-        
+        // The bitmap needs to provide place for 1 bit for every datablock
+        if !((sb.datastart - sb.bmapstart).saturating_mul(sb.block_size).saturating_mul(8) >= sb.ndatablocks) {
+            return Err(SbInvalidReason::BitmapTooSmall)
+        }
+        // There needs to be enough space for the datablocks, also when adding them up would overflow
+        if !(sb.datastart.checked_add(sb.ndatablocks).map_or(false, |end| end <= sb.nblocks)) {
+            return Err(SbInvalidReason::DataDoesNotFit)
+        }
+        // The regions are non-empty, do not share any block and lie between the superblock and the end of the disk
+        if !CustomBlockFileSystem::regions_disjoint(sb) {
+            return Err(SbInvalidReason::RegionsOverlap)
+        }
+        // The inode region has to be sufficiently large to hold ninodes inodes
+        if !(*DINODE_SIZE * sb.ninodes <= (sb.bmapstart - sb.inodestart) * sb.block_size) {
+            return Err(SbInvalidReason::InodeRegionTooSmall)
+        }
+        // The regions have to physically fit on the disk together, i.e. fall within the first nblocks blocks
+        if !(1 + (sb.bmapstart - sb.inodestart) + (sb.datastart - sb.bmapstart) + sb.ndatablocks <= sb.nblocks) {
+            return Err(SbInvalidReason::RegionsOverflowDisk)
+        }
+        return Ok(())
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
-        let superblock = self.sup_get()?;
-        let nbbitmapblocks = superblock.datastart - superblock.bmapstart;
-        for x in 0..nbbitmapblocks {
-            let mut bitmap_block = self.b_get(superblock.bmapstart + x)?;
-            for y in 0..superblock.block_size {
-                let mut byte: [u8; 1] = [0];
-                bitmap_block.read_data(&mut byte, y)?;
-                for z in 0..8 {
-                    let set_byte = 0b0000_0001 << z;
-                    let and = byte[0] & set_byte;
-                    // This spot is free so we can use it
-                    if !(and == set_byte) {
-                        let index = (x*superblock.block_size*8) + (y*8) + z;
-                        // The bitmap only consists of ndatablock bits,
-                        // if we go past this we are looking in a part of the last
-                        // bitmap block that is not allocated for the bitmap
-                        if index > superblock.ndatablocks - 1{
-                            return Err(CustomBlockFileSystemError::NoFreeDataBlock);  
-                        } 
-                        let new_byte = byte[0] | set_byte;
-                        bitmap_block.write_data(&[new_byte], y)?;
-                        self.b_put(&bitmap_block)?;
-                        self.b_zero(index)?;
-                        return Ok(index)
-                    }
-                }    
+    /// Whether the half-open regions `[inodestart, bmapstart)`, `[bmapstart, datastart)` and `[datastart, datastart + ndatablocks)`
+    /// are each non-empty, pairwise disjoint and within `[1, nblocks)`
+    fn regions_disjoint(sb: &SuperBlock) -> bool {
+        let data_end = match sb.datastart.checked_add(sb.ndatablocks) {
+            Some(end) => end,
+            None => return false
+        };
+        let regions = [(sb.inodestart, sb.bmapstart), (sb.bmapstart, sb.datastart), (sb.datastart, data_end)];
+        for (i, (start, end)) in regions.iter().enumerate() {
+            if !(1 <= *start && start < end && *end <= sb.nblocks) {
+                return false
+            }
+            for (other_start, other_end) in regions[..i].iter() {
+                if start < other_end && other_start < end {
+                    return false
+                }
             }
         }
-        // nothing changed
-        return Err(CustomBlockFileSystemError::NoFreeDataBlock);     
+        return true
     }
 
-    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
-        return Ok(self.superblock);
+    /// Check whether `sb` is a valid superblock for a file system created through `mkfs_checksummed`.
+    /// Besides the checks of `sb_validate`, the checksum table has to fit between the end of the data region and the end of the disk.
+    pub fn sb_validate_checksummed(sb: &SuperBlock) -> Result<(), SbInvalidReason> {
+        CustomBlockFileSystem::sb_validate(sb)?;
+        if !(sb.datastart + sb.ndatablocks + CustomBlockFileSystem::checksum_blocks(sb) <= sb.nblocks) {
+            return Err(SbInvalidReason::NoRoomForChecksums)
+        }
+        return Ok(())
     }
 
-    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let mut block = self.b_get(0)?;
-        block.serialize_into( sup, 0)?;
-        self.b_put(&block)?;
-        self.superblock = *sup;
+    /// Lay out a superblock for a disk of `nblocks` blocks of `block_size` bytes, holding `ninodes` inodes.
+    /// The inode region starts right after the superblock and is just large enough for the inodes,
+    /// after which the remaining blocks are split between the bitmap and as many data blocks as possible.
+    /// The result always satisfies `sb_valid`. If the disk is too small to hold a single data block, an error is returned.
+    pub fn sb_layout(block_size: u64, nblocks: u64, ninodes: u64) -> Result<SuperBlock, CustomBlockFileSystemError> {
+        if block_size == 0 {
+            return Err(CustomBlockFileSystemError::DiskTooSmall);
+        }
+        let inodestart = 1;
+        // at least one block for the inodes, so the regions stay in order
+        let inode_blocks = ((*DINODE_SIZE * ninodes + block_size - 1) / block_size).max(1);
+        let bmapstart = inodestart + inode_blocks;
+        // at least one bitmap block and one data block
+        if !(bmapstart + 2 <= nblocks) {
+            return Err(CustomBlockFileSystemError::DiskTooSmall);
+        }
+        let remaining = nblocks - bmapstart;
+        let bitmapblockcapacity = block_size * 8;
+        // the smallest bitmap that can hold a bit for every block left after it
+        let mut bitmap_blocks = 1;
+        while (remaining - bitmap_blocks + bitmapblockcapacity - 1) / bitmapblockcapacity > bitmap_blocks {
+            bitmap_blocks += 1;
+        }
+        let datastart = bmapstart + bitmap_blocks;
+        return Ok(SuperBlock { block_size, nblocks, ninodes, inodestart, ndatablocks: nblocks - datastart, bmapstart, datastart })
+    }
+
+    /// Stricter variant of `sb_valid`, that additionally requires the bitmap region to consist of
+    /// exactly the number of blocks needed to hold one bit per data block.
+    /// Returns the reason why the superblock was rejected.
+    pub fn sb_valid_strict(sb: &SuperBlock) -> Result<(), CustomBlockFileSystemError> {
+        if !CustomBlockFileSystem::sb_valid(sb) {
+            return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+        }
+        let bitmapblockcapacity = sb.block_size * 8;
+        let needed = (sb.ndatablocks + bitmapblockcapacity - 1) / bitmapblockcapacity;
+        if sb.datastart - sb.bmapstart > needed {
+            return Err(CustomBlockFileSystemError::BitmapOverProvisioned);
+        }
+        return Ok(())
+    }
+
+    /// Check that `i` is a valid index within the data region of `superblock`.
+    /// Data indices start at 0 for the block at `datastart`, so passing a block number on disk by mistake
+    /// (which is at least `datastart`) is caught for all but the first few data blocks.
+    fn check_data_index(superblock: &SuperBlock, i: u64) -> Result<(), CustomBlockFileSystemError> {
+        // Index i is out of bounds, if it is not lower than the number of data blocks
+        if !(i < superblock.ndatablocks) {
+            return Err(CustomBlockFileSystemError::DataIndexOutOfBounds);
+        }
         return Ok(())
     }
 }
 
-// If you want to write more complicated tests that create actual files on your system, take a look at `utils.rs` in the assignment, and how it is used in the `fs_tests` folder to perform the tests. I have imported it below to show you how it can be used.
-// The `utils` folder has a few other useful methods too (nothing too crazy though, you might want to write your own utility functions, or use a testing framework in rust, if you want more advanced features)
-#[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
+impl<D: BlockDevice> CustomBlockFileSystem<D> {
+    /// Create a new CustomBlockFileSystem given a Device dev
+    pub fn new(dev: D, sb: SuperBlock) -> Self {
+        return Self::new_with_cache_capacity(dev, sb, DEFAULT_CACHE_CAPACITY)
+    }
 
-    use cplfs_api::{fs::{BlockSupport, FileSysSupport}, types::SuperBlock};
-    use super::CustomBlockFileSystem;
-    //use a_block_support::CustomBlockFileSystem;
-    fn disk_prep_path(name: &str) -> PathBuf {
-        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    /// Create a new CustomBlockFileSystem given a Device dev, whose block cache holds up to `capacity` blocks.
+    /// A capacity of 0 disables the cache.
+    pub fn new_with_cache_capacity(dev: D, sb: SuperBlock, capacity: usize) -> Self {
+        CustomBlockFileSystem { device: Some(dev), superblock: sb, alloc_mode: AllocMode::default(), reserved_data_blocks: 0, last_alloc: 0, io_hook: RefCell::new(None), block_cache: RefCell::new(BlockCache::new(capacity)), checksums: false, readonly: false, op_stats: Cell::new(OpStats::default()), free_blocks: Cell::new(None), header_dirty: false, stale_free_count: None }
     }
-    
 
-    #[path = "utils.rs"]
-    mod utils;
+    /// Create a new file system like `mkfs`, on the fresh device `device` instead of one backed by a file.
+    /// This works for any `BlockDevice`, so also for backends other than `Device`.
+    pub fn mkfs_on_device(mut device: D, sb: &SuperBlock) -> Result<Self, CustomBlockFileSystemError> {
+        if !CustomBlockFileSystem::sb_validate(sb).is_ok() {
+            return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+        }
+        if !(device.block_size() == sb.block_size && device.nblocks() == sb.nblocks) {
+            return Err(CustomBlockFileSystemError::IncompatibleDeviceSuperBlock);
+        }
+        // A super block containing the file system metadata at block index 0, followed by the magic number and format version
+        let mut block = device.read_block(0)?;
+        block.serialize_into(sb, 0)?;
+        block.serialize_into(&FS_MAGIC, FS_MAGIC_OFFSET)?;
+        block.serialize_into(&FORMAT_VERSION, FORMAT_VERSION_OFFSET)?;
+        // write this block to the device
+        device.write_block(&block)?;
+        let mut fs = Self::new(device, *sb);
+        // the count of free blocks gets written to block 0 when the file system is flushed
+        fs.free_blocks.set(Some(fs.scan_free_blocks()?));
+        fs.header_dirty = true;
+        return Ok(fs)
+    }
 
-    #[test]
-    fn sb_valid() {
-        static BLOCK_SIZE: u64 = 1000;
-        static NBLOCKS: u64 = 10;
-        static SUPERBLOCK_BAD: SuperBlock = SuperBlock {
-            block_size: BLOCK_SIZE, //Note; assumes at least 2 inodes fit in one block. 
-            nblocks: NBLOCKS,
-            ninodes: 6,
-            inodestart: 1,
-            // too many data blocks
-            ndatablocks: 20,
-            bmapstart: 4,
-            datastart: 5,
-        };
+    /// Whether this file system keeps a checksum for every block, i.e. was created through `mkfs_checksummed`
+    pub fn is_checksummed(&self) -> bool {
+        return self.checksums;
+    }
 
-        // Too many data blocks for the bitmap to store
-        static SUPERBLOCK_BAD_2: SuperBlock = SuperBlock {
-            block_size: 120, //Note; assumes at least 1  inodes fit in one block.
-            nblocks: 1000,
-            ninodes: 3,
-            inodestart: 1,
-            // too many data blocks
-            ndatablocks: 995,
-            bmapstart: 4,
-            datastart: 5,
-        };
+    /// Store the checksum of `b` in the checksum table. Blocks of the table itself are not checksummed.
+    fn record_checksum(&mut self, b: &Block) -> Result<(), CustomBlockFileSystemError> {
+        if b.block_no >= CustomBlockFileSystem::checksum_table_start(&self.superblock) {
+            return Ok(())
+        }
+        let (table_no, offset) = CustomBlockFileSystem::checksum_location(&self.superblock, b.block_no);
+        let table = self.read_cached(table_no)?;
+        let mut table = Block::new(table_no, Box::from(table.contents_as_ref()));
+        table.write_data(&crc32(b.contents_as_ref()).to_le_bytes(), offset)?;
+        self.write_through(&table)?;
+        return Ok(())
+    }
 
-        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_BAD), false);
-        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_BAD_2), false);
+    /// Check `b`, just read from the device, against its checksum in the checksum table
+    fn verify_checksum(&self, b: &Block) -> Result<(), CustomBlockFileSystemError> {
+        if b.block_no >= CustomBlockFileSystem::checksum_table_start(&self.superblock) {
+            return Ok(())
+        }
+        let (table_no, offset) = CustomBlockFileSystem::checksum_location(&self.superblock, b.block_no);
+        let mut stored = [0u8; CHECKSUM_SIZE as usize];
+        self.read_cached(table_no)?.read_data(&mut stored, offset)?;
+        if u32::from_le_bytes(stored) != crc32(b.contents_as_ref()) {
+            return Err(CustomBlockFileSystemError::ChecksumMismatch { block: b.block_no });
+        }
+        return Ok(())
     }
 
-    #[test]
-    fn free_alloc_multiple_bblocks() {
-        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
-            block_size: 300, //Note; assumes at least 1 inodes fit in one block.
-            nblocks: 2500,
-            ninodes: 3,
-            inodestart: 1,
-            ndatablocks: 2494,
-            bmapstart: 4,
-            datastart: 6,
-        };
+    /// Mount the file system on `dev` like `mountfs`, but read-only: every operation that would write to the device fails
+    /// with `ReadOnlyFilesystem` instead, before anything is written. Reading blocks and the superblock works as usual.
+    pub fn mountfs_ro(dev: D) -> Result<Self, CustomBlockFileSystemError> {
+        let mut fs = Self::mountfs_legacy(dev)?;
+        fs.readonly = true;
+        return Ok(fs)
+    }
 
-        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_GOOD), true);   
-        let path = disk_prep_path("free_alloc_multiple_blocks");
-        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
-        let mut byte: [u8; 1] = [0];
-        for i in 0..SUPERBLOCK_GOOD.ndatablocks {
-            assert_eq!(my_fs.b_alloc().unwrap(), i); //Fill up all data blocks
+    /// Whether this file system was mounted read-only, through `mountfs_ro`
+    pub fn is_readonly(&self) -> bool {
+        return self.readonly;
+    }
+
+    /// Fail with `ReadOnlyFilesystem` if this file system was mounted read-only.
+    /// Every write to the device is checked, the higher layers use this to reject an operation before they start on it.
+    pub fn check_writable(&self) -> Result<(), CustomBlockFileSystemError> {
+        if self.readonly {
+            return Err(CustomBlockFileSystemError::ReadOnlyFilesystem);
         }
-        assert!(my_fs.b_alloc().is_err()); //No more blocks
+        return Ok(())
+    }
 
-        //Check the bitmap in second block
-        let bb = my_fs.b_get(5).unwrap();
-        bb.read_data(&mut byte, 0).unwrap();
-        // everything here should be allocated in first byte
-        assert_eq!(byte[0], 0b1111_1111);
-        
-        //Deallocate fist block
-        my_fs.b_free(2400).unwrap();
-        assert!(my_fs.b_free(2400).is_err());
+    /// Mount the file system on `dev` like `mountfs`, with a block cache that holds up to `capacity` blocks
+    pub fn mountfs_with_cache_capacity(dev: D, capacity: usize) -> Result<Self, CustomBlockFileSystemError> {
+        let mut fs = Self::mountfs_legacy(dev)?;
+        fs.set_cache_capacity(capacity);
+        return Ok(fs)
+    }
 
-        //Check the bitmap in second block
-        let bb = my_fs.b_get(5).unwrap();
-        bb.read_data(&mut byte, 0).unwrap();
-        assert_eq!(byte[0], 0b1111_1110);
+    /// Never hand out data blocks with an index below `reserved` from now on.
+    /// The number is stored in block 0 when the file system is flushed, so it is restored by the next mount.
+    pub fn set_reserved_data_blocks(&mut self, reserved: u64) {
+        self.reserved_data_blocks = reserved;
+        self.header_dirty = true;
+    }
+
+    /// Number of data blocks at the start of the data region that are reserved, and never handed out by the allocator
+    pub fn reserved_data_blocks(&self) -> u64 {
+        return self.reserved_data_blocks;
+    }
+
+    /// Add `extra` data blocks to the end of the data region, so that the allocator can hand them out.
+    /// Fails with `CannotGrow` if the bitmap region has no bits left for them, or the disk has no blocks left after the data region.
+    /// Blocks that are already allocated stay allocated.
+    pub fn grow_datablocks(&mut self, extra: u64) -> Result<(), CustomBlockFileSystemError> {
+        self.check_writable()?;
+        let mut superblock = self.sup_get()?;
+        let old = superblock.ndatablocks;
+        let new = old.checked_add(extra).ok_or(CustomBlockFileSystemError::CannotGrow)?;
+        if !(new <= (superblock.datastart - superblock.bmapstart) * superblock.block_size * 8) {
+            return Err(CustomBlockFileSystemError::CannotGrow);
+        }
+        // a checksummed file system keeps its checksum table in the last blocks of the disk
+        let disk_end = if self.checksums { CustomBlockFileSystem::checksum_table_start(&superblock) } else { superblock.nblocks };
+        if !(superblock.datastart + new <= disk_end) {
+            return Err(CustomBlockFileSystemError::CannotGrow);
+        }
+        superblock.ndatablocks = new;
+        self.sup_put(&superblock)?;
+        // the bits past the old end of the bitmap were never used, but clear them in case they hold garbage
+        for i in old..new {
+            self.bitmap_set(i, false)?;
+        }
+        return Ok(())
+    }
+
+    /// Allocate `n` data blocks at once, and return their indices in the order they were allocated.
+    /// Either all `n` blocks are allocated, or, if there is not enough room, none are and an error is returned.
+    pub fn b_alloc_many(&mut self, n: u64) -> Result<Vec<u64>, CustomBlockFileSystemError> {
+        self.check_writable()?;
+        let mut indices = Vec::new();
+        for _ in 0..n {
+            match self.b_alloc() {
+                Ok(index) => indices.push(index),
+                Err(e) => {
+                    for index in indices {
+                        self.b_free(index)?;
+                    }
+                    return Err(e)
+                }
+            }
+        }
+        return Ok(indices)
+    }
+
+    /// Allocate the first run of `n` consecutive free data blocks, zero them, and return the index of the first one.
+    /// Reserved blocks are never handed out. If there is no such run, or `n` is 0, an error is returned and nothing changes.
+    pub fn b_alloc_contiguous(&mut self, n: u64) -> Result<u64, CustomBlockFileSystemError> {
+        self.check_writable()?;
+        let superblock = self.sup_get()?;
+        if n == 0 {
+            return Err(CustomBlockFileSystemError::NoContiguousRun);
+        }
+        let mut start = self.reserved_data_blocks;
+        let mut run = 0;
+        for i in self.reserved_data_blocks..superblock.ndatablocks {
+            if self.bitmap_get(i)? {
+                start = i + 1;
+                run = 0;
+                continue
+            }
+            run += 1;
+            if run == n {
+                for index in start..start + n {
+                    self.bitmap_set(index, true)?;
+                    self.b_zero(index)?;
+                }
+                return Ok(start)
+            }
+        }
+        // nothing changed
+        return Err(CustomBlockFileSystemError::NoContiguousRun);
+    }
+
+    /// Free the `count` data blocks with indices `start` up to `start + count`, reading and writing every bitmap block involved only once.
+    /// If any of the blocks is out of bounds or already free, an error is returned and nothing changes.
+    pub fn b_free_range(&mut self, start: u64, count: u64) -> Result<(), CustomBlockFileSystemError> {
+        self.check_writable()?;
+        let superblock = self.sup_get()?;
+        if count == 0 {
+            return Ok(())
+        }
+        let end = start.checked_add(count).ok_or(CustomBlockFileSystemError::DataIndexOutOfBounds)?;
+        CustomBlockFileSystem::check_data_index(&superblock, end - 1)?;
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let mut bitmap_blocks = Vec::new();
+        for block_offset in start / bitmapblockcapacity..=(end - 1) / bitmapblockcapacity {
+            bitmap_blocks.push(self.b_get(superblock.bmapstart + block_offset)?);
+        }
+        // clear the bits in memory first, so nothing is written when one of them turns out to be free
+        let mut byte: [u8; 1] = [0];
+        for i in start..end {
+            let bitmap_block = &mut bitmap_blocks[(i / bitmapblockcapacity - start / bitmapblockcapacity) as usize];
+            let byte_offset = (i % bitmapblockcapacity) / 8;
+            bitmap_block.read_data(&mut byte, byte_offset)?;
+            let set_byte = 0b0000_0001 << (i % 8);
+            if !(byte[0] & set_byte == set_byte) {
+                return Err(CustomBlockFileSystemError::BlockIsAlreadyFree);
+            }
+            bitmap_block.write_data(&[byte[0] & !set_byte], byte_offset)?;
+        }
+        for bitmap_block in bitmap_blocks.iter() {
+            self.b_put(bitmap_block)?;
+        }
+        self.count_op(|stats| stats.b_free += count);
+        return Ok(())
+    }
+
+    /// Check whether the block with index `i` in the data region is currently allocated, according to the bitmap
+    pub fn b_is_allocated(&self, i: u64) -> Result<bool, CustomBlockFileSystemError> {
+        let allocated = self.bitmap_get(i)?;
+        return Ok(allocated)
+    }
+
+    /// Check whether the block with index `i` in the data region is currently free, according to the bitmap
+    pub fn b_is_free(&self, i: u64) -> Result<bool, CustomBlockFileSystemError> {
+        let allocated = self.bitmap_get(i)?;
+        return Ok(!allocated)
+    }
+
+    /// Read the bit of the bitmap that belongs to the block with index `data_index` in the data region
+    pub fn bitmap_get(&self, data_index: u64) -> Result<bool, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        CustomBlockFileSystem::check_data_index(&superblock, data_index)?;
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let bitmap_block = self.b_get(superblock.bmapstart + data_index / bitmapblockcapacity)?;
+        let mut byte: [u8; 1] = [0];
+        bitmap_block.read_data(&mut byte, (data_index % bitmapblockcapacity) / 8)?;
+        let set_byte = 0b0000_0001 << (data_index % 8);
+        return Ok(byte[0] & set_byte == set_byte)
+    }
+
+    /// Set the bit of the bitmap that belongs to the block with index `data_index` in the data region to `allocated`.
+    /// Unlike `b_alloc` and `b_free`, this does not zero the block, and setting a bit to the value it already has is not an error.
+    /// Meant for repair tools and tests, that need to put the bitmap in a given state.
+    pub fn bitmap_set(&mut self, data_index: u64, allocated: bool) -> Result<(), CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        CustomBlockFileSystem::check_data_index(&superblock, data_index)?;
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let mut bitmap_block = self.b_get(superblock.bmapstart + data_index / bitmapblockcapacity)?;
+        let mut byte: [u8; 1] = [0];
+        let byte_offset = (data_index % bitmapblockcapacity) / 8;
+        bitmap_block.read_data(&mut byte, byte_offset)?;
+        let set_byte = 0b0000_0001 << (data_index % 8);
+        let new_byte = if allocated { byte[0] | set_byte } else { byte[0] & !set_byte };
+        bitmap_block.write_data(&[new_byte], byte_offset)?;
+        self.b_put(&bitmap_block)?;
+        return Ok(())
+    }
+
+    /// Number of data blocks that are free according to the bitmap.
+    /// The count is stored in block 0 and kept up to date on every write to the bitmap, so the bitmap is not scanned.
+    pub fn count_free_blocks(&self) -> Result<u64, CustomBlockFileSystemError> {
+        if let Some(free) = self.free_blocks.get() {
+            return Ok(free);
+        }
+        let free = self.scan_free_blocks()?;
+        self.free_blocks.set(Some(free));
+        return Ok(free)
+    }
+
+    /// The number of free data blocks that was stored on disk when the file system was mounted, if it did not match the bitmap.
+    /// In that case the count was corrected to the one found by scanning the bitmap, which is written back on the next flush.
+    pub fn stale_free_count(&self) -> Option<u64> {
+        return self.stale_free_count;
+    }
+
+    /// Count the free data blocks by reading every bitmap block once. Changes nothing, not even the block cache:
+    /// the cache is write-through, so the device holds the same bitmap.
+    fn scan_free_blocks(&self) -> Result<u64, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut allocated = 0;
+        for block_no in superblock.bmapstart..superblock.datastart {
+            let block = self.device().read_block(block_no)?;
+            if self.checksums {
+                self.verify_checksum(&block)?;
+            }
+            allocated += Self::allocated_bits(&superblock, &block);
+        }
+        return Ok(superblock.ndatablocks - allocated)
+    }
+
+    /// Number of bits set in the bitmap block `b` that belong to a data block, i.e. not counting the bits past the end of the data region
+    fn allocated_bits(superblock: &SuperBlock, b: &Block) -> u64 {
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let first = (b.block_no - superblock.bmapstart) * bitmapblockcapacity;
+        let nbits = superblock.ndatablocks.saturating_sub(first).min(bitmapblockcapacity);
+        let mut allocated = 0;
+        for (i, byte) in b.contents_as_ref().iter().take(((nbits + 7) / 8) as usize).enumerate() {
+            let bits_left = nbits - i as u64 * 8;
+            let mask = if bits_left < 8 { (1u8 << bits_left) - 1 } else { 0xFF };
+            allocated += (byte & mask).count_ones() as u64;
+        }
+        return allocated
+    }
+
+    /// Read every allocated data block and return the numbers (on disk) of the blocks that cannot be read back intact.
+    /// Every block is read straight from the device and checked against its checksum (if the disk has any),
+    /// so a block that got corrupted while a copy of it is in the block cache is reported as well.
+    /// Nothing is written to the device.
+    pub fn scrub(&self) -> Result<Vec<u64>, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut failed = Vec::new();
+        for i in 0..superblock.ndatablocks {
+            if !self.b_is_allocated(i)? {
+                continue
+            }
+            let intact = match self.device().read_block(superblock.datastart + i) {
+                Ok(block) => !self.checksums || self.verify_checksum(&block).is_ok(),
+                Err(_) => false
+            };
+            if !intact {
+                failed.push(superblock.datastart + i);
+            }
+        }
+        return Ok(failed)
+    }
+
+    /// Number of free data blocks following the highest allocated one, i.e. how many blocks the data region could shrink by.
+    /// Despite its name, this only reports the count and does not change the file system.
+    pub fn trim_trailing_free(&self) -> Result<u64, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut free = 0;
+        for i in (0..superblock.ndatablocks).rev() {
+            if self.b_is_allocated(i)? {
+                break
+            }
+            free += 1;
+        }
+        return Ok(free)
+    }
+
+    /// Fragmentation of the free space in the data region, as a ratio between 0 and 1.
+    /// Free blocks forming a single run count as not fragmented at all, free blocks that are all separated as fully fragmented.
+    /// A file system without any free blocks, or with a single one, is not fragmented.
+    pub fn fragmentation(&self) -> Result<f64, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut free = 0;
+        let mut runs = 0;
+        let mut previous_free = false;
+        for i in 0..superblock.ndatablocks {
+            let is_free = !self.b_is_allocated(i)?;
+            if is_free {
+                free += 1;
+                if !previous_free {
+                    runs += 1;
+                }
+            }
+            previous_free = is_free;
+        }
+        if free <= 1 {
+            return Ok(0.0)
+        }
+        return Ok((runs - 1) as f64 / (free - 1) as f64)
+    }
+
+    /// Install a callback that is invoked on every `b_get` and `b_put`, or remove it again by passing `None`.
+    /// Every operation of the higher layers goes through these two, so this traces every block the file system touches.
+    pub fn set_io_hook(&mut self, hook: Option<IoHook>) {
+        self.io_hook = RefCell::new(hook);
+    }
+
+    fn report_io(&self, block: u64, op: BlockIoOp) {
+        self.count_op(|stats| match op {
+            BlockIoOp::Get => stats.b_get += 1,
+            BlockIoOp::Put | BlockIoOp::PutPartial { .. } => stats.b_put += 1
+        });
+        if let Some(hook) = self.io_hook.borrow_mut().as_mut() {
+            hook(BlockIoEvent { block, op });
+        }
+    }
+
+    fn count_op<F: FnOnce(&mut OpStats)>(&self, update: F) {
+        let mut stats = self.op_stats.get();
+        update(&mut stats);
+        self.op_stats.set(stats);
+    }
+
+    /// Number of calls to the block operations since the file system was created or mounted, or since `reset_stats`
+    pub fn stats(&self) -> OpStats {
+        return self.op_stats.get();
+    }
+
+    /// Start counting the calls to the block operations from 0 again
+    pub fn reset_stats(&mut self) {
+        self.op_stats.set(OpStats::default());
+    }
+
+    /// Read the block with number `i` like `b_get`, but share the copy in the block cache instead of copying it.
+    /// Writing the block replaces the cached copy, so later calls see the new contents, while copies handed out earlier keep the old ones.
+    pub fn b_get_ref(&self, i: u64) -> Result<Arc<Block>, CustomBlockFileSystemError> {
+        self.report_io(i, BlockIoOp::Get);
+        let block = self.read_cached(i)?;
+        return Ok(block)
+    }
+
+    /// Check that `i` is the number of a block on the disk, before handing it to the device
+    fn check_block_index(&self, i: u64) -> Result<(), CustomBlockFileSystemError> {
+        if !(i < self.superblock.nblocks) {
+            return Err(CustomBlockFileSystemError::BlockIndexOutOfBounds);
+        }
+        return Ok(())
+    }
+
+    /// Read block `i` from the block cache, or from the device (adding it to the cache) if it is not cached
+    fn read_cached(&self, i: u64) -> Result<Arc<Block>, CustomBlockFileSystemError> {
+        self.check_block_index(i)?;
+        if let Some(block) = self.block_cache.borrow_mut().get(i) {
+            return Ok(block);
+        }
+        let block = Arc::new(self.device().read_block(i)?);
+        if self.checksums {
+            self.verify_checksum(&block)?;
+        }
+        self.block_cache.borrow_mut().insert(Arc::clone(&block));
+        return Ok(block)
+    }
+
+    /// Write `b` to the device, and keep a copy in the block cache
+    fn write_through(&mut self, b: &Block) -> Result<(), CustomBlockFileSystemError> {
+        self.check_writable()?;
+        self.check_block_index(b.block_no)?;
+        // keep the count of free blocks up to date with the bits that change in the bitmap
+        let superblock = self.superblock;
+        if b.block_no >= superblock.bmapstart && b.block_no < superblock.datastart {
+            if let Some(free) = self.free_blocks.get() {
+                let old = Self::allocated_bits(&superblock, &*self.read_cached(b.block_no)?);
+                let new = Self::allocated_bits(&superblock, b);
+                self.free_blocks.set(Some((free + old).saturating_sub(new)));
+                self.header_dirty = true;
+            }
+        }
+        self.device_mut().write_block(b)?;
+        let copy = Block::new(b.block_no, Box::from(b.contents_as_ref()));
+        self.block_cache.borrow_mut().insert(Arc::new(copy));
+        if self.checksums {
+            self.record_checksum(b)?;
+        }
+        return Ok(())
+    }
+
+    /// Number of block reads served from the block cache and from the device since the file system was created or mounted
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.block_cache.borrow().stats;
+    }
+
+    /// Change the number of blocks the block cache holds, dropping the least recently used ones if it shrinks. 0 disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.block_cache.borrow_mut().set_capacity(capacity);
+    }
+
+    /// Write the block `b` to the device, where only the `len` bytes starting at offset `start` changed compared to the block on disk.
+    /// The changed range is reported to the I/O hook, so that backends able to do so can write just that range.
+    /// The memory mapped device does not benefit from this, so the block is written as a whole, exactly like `b_put` does.
+    pub fn b_put_partial(&mut self, b: &Block, start: u64, len: u64) -> Result<(), CustomBlockFileSystemError> {
+        if start.checked_add(len).map_or(true, |end| end > b.len()) {
+            return Err(CustomBlockFileSystemError::ByteRangeOutOfBounds);
+        }
+        self.report_io(b.block_no, BlockIoOp::PutPartial { start, len });
+        self.write_through(b)?;
+        return Ok(())
+    }
+
+    /// Checksum (CRC-64) over the contents of every block on the device, in order.
+    /// Two file systems with identical contents have the same checksum, which makes it a fingerprint of the image.
+    pub fn fs_checksum(&self) -> Result<u64, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut crc = Crc64::new();
+        for i in 0..superblock.nblocks {
+            crc.update(self.b_get(i)?.contents_as_ref());
+        }
+        return Ok(crc.finish())
+    }
+
+    /// Allocate the first free data block whose index is a multiple of `alignment`, and return its index.
+    /// Reserved blocks are never handed out. If no such block is free, an error is returned and nothing changes.
+    pub fn b_alloc_aligned(&mut self, alignment: u64) -> Result<u64, CustomBlockFileSystemError> {
+        self.check_writable()?;
+        if alignment == 0 {
+            return Err(CustomBlockFileSystemError::InvalidAlignment);
+        }
+        let superblock = self.sup_get()?;
+        let start = (self.reserved_data_blocks + alignment - 1) / alignment * alignment;
+        let indices = Box::new((start..superblock.ndatablocks).step_by(alignment as usize));
+        let index = self.alloc_first_free(indices)?;
+        return Ok(index)
+    }
+
+    /// Allocate the first free data block among `indices`, scanned in the given order, and return its index
+    fn alloc_first_free(&mut self, indices: Box<dyn Iterator<Item = u64>>) -> Result<u64, CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let mut bitmap_block = self.b_get(superblock.bmapstart)?;
+        for index in indices {
+            // bitmap can be mutiple blocks large, only read a new one when we cross into it
+            let block_no = superblock.bmapstart + index / bitmapblockcapacity;
+            if !(bitmap_block.block_no == block_no) {
+                bitmap_block = self.b_get(block_no)?;
+            }
+            let mut byte: [u8; 1] = [0];
+            let y = (index % bitmapblockcapacity) / 8;
+            bitmap_block.read_data(&mut byte, y)?;
+            let set_byte = 0b0000_0001 << (index % 8);
+            let and = byte[0] & set_byte;
+            // This spot is free so we can use it
+            if !(and == set_byte) {
+                let new_byte = byte[0] | set_byte;
+                bitmap_block.write_data(&[new_byte], y)?;
+                self.b_put(&bitmap_block)?;
+                self.b_zero(index)?;
+                return Ok(index)
+            }
+        }
+        // nothing changed
+        return Err(CustomBlockFileSystemError::NoFreeDataBlock);
+    }
+
+    /// Set the order in which `b_alloc` scans the bitmap for a free data block.
+    /// `AllocMode::NextFit` continues after the block `b_alloc` handed out last, also when that block was allocated in another mode.
+    pub fn set_alloc_mode(&mut self, mode: AllocMode) {
+        self.alloc_mode = mode;
+    }
+
+    /// The device backing this file system
+    pub fn device(&self) -> &D {
+        return self.device.as_ref().expect("the device of this file system was already unmounted");
+    }
+
+    fn device_mut(&mut self) -> &mut D {
+        return self.device.as_mut().expect("the device of this file system was already unmounted");
+    }
+
+    /// Write back any state this file system only keeps in memory, so that the device image is complete.
+    /// Blocks go straight to the device when they are written, but the count of free blocks and the number of reserved blocks
+    /// are only kept in memory until they are written to block 0 here.
+    pub fn flush(&mut self) -> Result<(), CustomBlockFileSystemError> {
+        // these are only written to block 0 here, and by `sup_put`
+        if self.header_dirty && !self.readonly {
+            let superblock = self.superblock;
+            self.sup_put(&superblock)?;
+        }
+        return Ok(())
+    }
+}
+
+impl<D: BlockDevice> Drop for CustomBlockFileSystem<D> {
+    /// Flush the file system when it is dropped without being unmounted, so no in-memory state gets lost.
+    /// The higher layers all wrap this file system, so dropping any of them ends up here as well.
+    /// The `Device` itself persists its contents when it is dropped right after.
+    fn drop(&mut self) {
+        if self.device.is_some() {
+            // there is no way to report an error from a drop
+            let _ = self.flush();
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+/// Reason why `sb_validate` rejects a superblock
+pub enum SbInvalidReason {
+    #[error("the inode region cannot hold all inodes")]
+    /// The blocks between `inodestart` and `bmapstart` are too small to hold `ninodes` inodes
+    InodeRegionTooSmall,
+    #[error("the bitmap cannot hold a bit for every data block")]
+    /// The blocks between `bmapstart` and `datastart` hold fewer than `ndatablocks` bits
+    BitmapTooSmall,
+    #[error("the data region does not fit on the disk")]
+    /// The data region extends past the last block of the disk
+    DataDoesNotFit,
+    #[error("the regions are not in order")]
+    /// The regions do not follow the superblock in the order inodes, bitmap, data, each at least one block large
+    RegionsOutOfOrder,
+    #[error("the regions together are larger than the disk")]
+    /// The superblock and the regions together need more than `nblocks` blocks
+    RegionsOverflowDisk,
+    #[error("the regions overlap or are empty")]
+    /// The inode, bitmap and data regions are not non-empty, pairwise disjoint ranges of blocks between the superblock and the end of the disk
+    RegionsOverlap,
+    #[error("there is no room for the checksum table after the data region")]
+    /// The blocks after the data region are too few to hold a checksum for every block, on a checksummed file system
+    NoRoomForChecksums
+}
+
+#[derive(Error, Debug)]
+/// Custom type for errors in CustomBlockFileSystem
+pub enum CustomBlockFileSystemError {
+    #[error("invalid SuperBlock was detected")]
+    /// Error thrown when an invalid superblock is encountered
+    InvalidSuperBlock,
+    #[error("SuperBlock and device are not compatible")]
+    /// Error thrown when the device and superblock don't agree
+    IncompatibleDeviceSuperBlock,
+    #[error("The data index is out of bounds for this device")]
+    /// Thrown when the block index provided is larger than ndatablocks - 1
+    DataIndexOutOfBounds,
+    #[error("The block number is out of bounds for this device")]
+    /// Thrown when a block is read or written whose number is not smaller than nblocks
+    BlockIndexOutOfBounds,
+    #[error("The block that was tried to be freed is already free")]
+    /// Thrown when the block that is trying to be freed is already free
+    BlockIsAlreadyFree,
+    #[error("The bitmap region is larger than needed for the number of data blocks")]
+    /// Thrown by strict validation when the bitmap region has more blocks than required to hold ndatablocks bits
+    BitmapOverProvisioned,
+    #[error("The byte range does not fall within the block")]
+    /// Thrown when a range of bytes is given that extends past the end of the block
+    ByteRangeOutOfBounds,
+    #[error("The alignment must be at least 1")]
+    /// Thrown when an aligned allocation is requested with an alignment of 0
+    InvalidAlignment,
+    #[error("There is no free data block")]
+    /// Thrown when there is no free data block available and one is requested 
+    NoFreeDataBlock,
+    #[error("The disk is too small to hold a file system")]
+    /// Thrown when a superblock is laid out for a disk that cannot hold the superblock, the inodes, a bitmap block and a data block
+    DiskTooSmall,
+    #[error("There is no run of free data blocks of the requested length")]
+    /// Thrown when a number of consecutive data blocks is requested, but the free blocks do not form a long enough run
+    NoContiguousRun,
+    #[error("The checksum of block {block} does not match its contents")]
+    /// Thrown when a block read from a checksummed file system does not match the checksum stored for it
+    ChecksumMismatch {
+        /// Number of the block on disk
+        block: u64
+    },
+    #[error("The file system is mounted read-only")]
+    /// Thrown when something is written to a file system that was mounted through `mountfs_ro`
+    ReadOnlyFilesystem,
+    #[error("Block 0 does not hold the magic number of this file system")]
+    /// Thrown when mounting a device whose block 0 was not written by `mkfs`
+    BadMagic,
+    #[error("Unsupported on-disk format version {0}")]
+    /// Thrown when mounting a device written in a format version this file system does not know
+    UnsupportedVersion(u32),
+    #[error("The data region cannot grow by the requested number of blocks")]
+    /// Thrown when growing the data region would need more bits than the bitmap has, or more blocks than the disk has
+    CannotGrow,
+    /// The input provided to some method in the controller layer was invalid
+    #[error("API error")]
+    GivenError(#[from] error_given::APIError)
+}
+
+/// The kind of `io::Error` the given API error corresponds to
+pub(crate) fn api_error_kind(e: &error_given::APIError) -> ErrorKind {
+    return match e {
+        error_given::APIError::APIO(e) => e.kind(),
+        error_given::APIError::ControllerInput(_) | error_given::APIError::BlockInput(_) => ErrorKind::InvalidInput,
+        error_given::APIError::APISerialize(_) | error_given::APIError::Other(_) => ErrorKind::Other,
+    }
+}
+
+impl CustomBlockFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomBlockFileSystemError::InvalidSuperBlock
+            | CustomBlockFileSystemError::IncompatibleDeviceSuperBlock
+            | CustomBlockFileSystemError::BitmapOverProvisioned
+            | CustomBlockFileSystemError::ChecksumMismatch { .. }
+            | CustomBlockFileSystemError::BadMagic => ErrorKind::InvalidData,
+            CustomBlockFileSystemError::UnsupportedVersion(_) => ErrorKind::Unsupported,
+            CustomBlockFileSystemError::DataIndexOutOfBounds
+            | CustomBlockFileSystemError::BlockIndexOutOfBounds
+            | CustomBlockFileSystemError::BlockIsAlreadyFree
+            | CustomBlockFileSystemError::ByteRangeOutOfBounds
+            | CustomBlockFileSystemError::InvalidAlignment
+            | CustomBlockFileSystemError::DiskTooSmall
+            | CustomBlockFileSystemError::CannotGrow => ErrorKind::InvalidInput,
+            CustomBlockFileSystemError::NoFreeDataBlock | CustomBlockFileSystemError::NoContiguousRun => ErrorKind::StorageFull,
+            CustomBlockFileSystemError::ReadOnlyFilesystem => ErrorKind::ReadOnlyFilesystem,
+            CustomBlockFileSystemError::GivenError(e) => api_error_kind(e),
+        }
+    }
+}
+
+impl From<CustomBlockFileSystemError> for io::Error {
+    fn from(e: CustomBlockFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
+}
+
+impl FileSysSupport for CustomBlockFileSystem {
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return CustomBlockFileSystem::sb_validate(sb).is_ok()
+    }
+
+    fn mkfs<P: AsRef<Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error>{
+        // Check if the given superblock is a valid file system superblock
+        let sb_cond = Self::sb_valid(sb);
+        if !sb_cond {
+            return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+        } else  {
+           //Create a new Device at the given path, to allow the file system to communicate with it
+           let device = Device::new(path, sb.block_size, sb.nblocks)?;
+           return Self::mkfs_on_device(device, sb);
+        }     
+    }
+
+    // The assignment requires `mountfs` to accept any device holding a valid superblock, so disks without a magic number are still mounted here.
+    // `mountfs_ro` and `mountfs_with_cache_capacity` go through `mountfs_legacy` as well, so they accept the same disks.
+    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+        return Self::mountfs_legacy(dev)
+    }
+
+    fn unmountfs(self) -> Device {
+        return self.unmountfs_device()
+    }
+
+    type Error = CustomBlockFileSystemError;
+}
+
+// The operations of `FileSysSupport` and `BlockSupport` for any device.
+// The traits themselves are only implemented for `Device`, as `FileSysSupport` mounts and unmounts a `Device`.
+impl<D: BlockDevice> CustomBlockFileSystem<D> {
+    /// Mount the file system on the device `dev` like `mountfs`, for any `BlockDevice`, but stricter:
+    /// disks written before the magic number was introduced are refused. Fails with `BadMagic` unless block 0 holds the magic number `mkfs` writes, and with `UnsupportedVersion` for unknown format versions.
+    pub fn mountfs_on_device(dev: D) -> Result<Self, CustomBlockFileSystemError> {
+        return Self::mount_checked(dev, false)
+    }
+
+    /// Mount the file system on the device `dev` like `mountfs_on_device`, but also accept disks written before the magic number was introduced.
+    /// Such a disk holds nothing in block 0 after the superblock (and the checksum marker), so a disk of which only the magic number was wiped still fails with `BadMagic`.
+    pub fn mountfs_legacy(dev: D) -> Result<Self, CustomBlockFileSystemError> {
+        return Self::mount_checked(dev, true)
+    }
+
+    fn mount_checked(dev: D, allow_legacy: bool) -> Result<Self, CustomBlockFileSystemError> {
+        let sb_block = dev.read_block( 0)?;
+        let magic = sb_block.deserialize_from::<u64>(FS_MAGIC_OFFSET)?;
+        let legacy = allow_legacy && sb_block.contents_as_ref()[FS_MAGIC_OFFSET as usize..].iter().all(|&byte| byte == 0);
+        if !legacy {
+            if !(magic == FS_MAGIC) {
+                return Err(CustomBlockFileSystemError::BadMagic);
+            }
+            let version = sb_block.deserialize_from::<u32>(FORMAT_VERSION_OFFSET)?;
+            if !(version == FORMAT_VERSION) {
+                return Err(CustomBlockFileSystemError::UnsupportedVersion(version));
+            }
+        }
+        // The superblock is a valid superblock 
+        let superblock = sb_block.deserialize_from::<SuperBlock>(0)?;
+        if CustomBlockFileSystem::sb_validate(&superblock).is_ok() {
+            // The block size and number of blocks of the device and superblock agree
+            if dev.block_size() == superblock.block_size && dev.nblocks() == superblock.nblocks {
+                let mut fs = Self::new(dev, superblock);
+                if sb_block.deserialize_from::<u64>(CHECKSUM_MAGIC_OFFSET)? == CHECKSUM_MAGIC {
+                    if CustomBlockFileSystem::sb_validate_checksummed(&superblock).is_err() {
+                        return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+                    }
+                    fs.checksums = true;
+                }
+                let free = fs.scan_free_blocks()?;
+                fs.free_blocks.set(Some(free));
+                // disks without a magic number do not store a count, nor any reserved blocks
+                if magic == FS_MAGIC {
+                    let reserved = sb_block.deserialize_from::<u64>(RESERVED_BLOCKS_OFFSET)?;
+                    if reserved > superblock.ndatablocks {
+                        return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+                    }
+                    fs.reserved_data_blocks = reserved;
+                    let stored = sb_block.deserialize_from::<u64>(FREE_BLOCKS_OFFSET)?;
+                    if !(stored == free) {
+                        // e.g. the file system was not unmounted properly: trust the bitmap
+                        fs.stale_free_count = Some(stored);
+                        fs.header_dirty = true;
+                    }
+                }
+                return Ok(fs)
+            }
+            else {
+                return Err(CustomBlockFileSystemError::IncompatibleDeviceSuperBlock);
+            }            
+        }
+        else {
+            return Err(CustomBlockFileSystemError::InvalidSuperBlock);
+        }
+    }
+
+    /// Flush the file system and hand back its device, like `unmountfs`, for any `BlockDevice`.
+    /// Like dropping the file system, this cannot report an error: call `flush` first to find out whether it succeeds.
+    pub fn unmountfs_device(mut self) -> D {
+        // ignored as in `drop`, e.g. a read-only file system has nothing to write back anyway
+        let _ = self.flush();
+        return self.device.take().expect("the device of this file system was already unmounted")
+    }
+
+    /// Read the nth block of the entire disk and return it, see `BlockSupport::b_get`
+    pub fn b_get(&self, i: u64) -> Result<Block, CustomBlockFileSystemError> {
+        self.report_io(i, BlockIoOp::Get);
+        let block = self.read_cached(i)?;
+        return Ok(Block::new(i, Box::from(block.contents_as_ref())))
+    }
+
+    /// Write the nth block of the entire disk, see `BlockSupport::b_put`
+    pub fn b_put(&mut self, b: &Block) -> Result<(), CustomBlockFileSystemError> {
+        self.report_io(b.block_no, BlockIoOp::Put);
+        self.write_through(b)?;
+        return Ok(());
+    }
+
+    /// Free the ith block in the block data region, by setting the ith bit in the free bit map region to zero.
+    /// `i` is an index within the data region, like for `b_zero` and as returned by `b_alloc`, not a block number on disk.
+    pub fn b_free(&mut self, i: u64) -> Result<(), CustomBlockFileSystemError> {
+        self.check_writable()?;
+        self.count_op(|stats| stats.b_free += 1);
+        let superblock = self.sup_get()?;
+        CustomBlockFileSystem::check_data_index(&superblock, i)?;
+        // bitmap can be mutiple blocks large, we have to select the right one
+        let bitmapblockcapacity = superblock.block_size * 8;
+        let block_offset = i / bitmapblockcapacity;
+        let mut bitmap_block = self.b_get(superblock.bmapstart + block_offset)?;
+        // one byte of data
+        let mut byte: [u8; 1] = [0];
+        // the byte we want to read from the bitmap block
+        let byte_offset =  (i % bitmapblockcapacity) / 8;
+        bitmap_block.read_data(&mut byte, byte_offset)?;
+        // because << adds zeros we should do this and invert later
+        let bit_offset =  (i % bitmapblockcapacity) % 8;
+        let set_byte = 0b0000_0001 << bit_offset;
+        // we define the order of the bits within each byte you read from right to left
+        let or = byte[0] | !set_byte;
+        if or == !set_byte {
+            // ith block is already a free block
+            return Err(CustomBlockFileSystemError::BlockIsAlreadyFree);
+        }
+        else{
+            let and = byte[0] & !set_byte;
+            let res = bitmap_block.write_data(&[and], byte_offset)?;
+            self.b_put(&bitmap_block)?;
+            return Ok(res)
+        }    
+    }
+
+    /// Zero the ith block in the block data region, see `BlockSupport::b_zero`.
+    /// `i` is an index within the data region, like for `b_free` and as returned by `b_alloc`, not a block number on disk.
+    pub fn b_zero(&mut self, i: u64) -> Result<(), CustomBlockFileSystemError> {
+        let superblock = self.sup_get()?;
+        CustomBlockFileSystem::check_data_index(&superblock, i)?;
+        self.b_put(&Block::new_zero(superblock.datastart + i, superblock.block_size))
+        
+    }
+
+    /// Allocate a free data block and return its index within the data region, see `BlockSupport::b_alloc`
+    pub fn b_alloc(&mut self) -> Result<u64, CustomBlockFileSystemError> {
+        self.check_writable()?;
+        self.count_op(|stats| stats.b_alloc += 1);
+        let superblock = self.sup_get()?;
+        // The bitmap only consists of ndatablock bits,
+        // the rest of the last bitmap block is never handed out
+        let indices: Box<dyn Iterator<Item = u64>> = match self.alloc_mode {
+            AllocMode::LowestFirst => Box::new(self.reserved_data_blocks..superblock.ndatablocks),
+            AllocMode::HighestFirst => Box::new((self.reserved_data_blocks..superblock.ndatablocks).rev()),
+            AllocMode::NextFit => {
+                let mut start = self.last_alloc.max(self.reserved_data_blocks);
+                if start >= superblock.ndatablocks {
+                    start = self.reserved_data_blocks;
+                }
+                Box::new((start..superblock.ndatablocks).chain(self.reserved_data_blocks..start))
+            }
+        };
+        let index = self.alloc_first_free(indices)?;
+        self.last_alloc = index + 1;
+        return Ok(index)
+    }
+
+    /// The superblock of the file system, see `BlockSupport::sup_get`
+    pub fn sup_get(&self) -> Result<SuperBlock, CustomBlockFileSystemError> {
+        return Ok(self.superblock);
+    }
+
+    /// Write the superblock `sup` to the device and use it from now on, see `BlockSupport::sup_put`
+    /// The count of free blocks is written along with it, and recounted first if `sup` changes the bitmap or data region.
+    pub fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), CustomBlockFileSystemError> {
+        self.check_writable()?;
+        let layout_changed = !(sup.bmapstart == self.superblock.bmapstart && sup.datastart == self.superblock.datastart && sup.ndatablocks == self.superblock.ndatablocks);
+        let mut block = self.b_get(0)?;
+        block.serialize_into( sup, 0)?;
+        self.superblock = *sup;
+        if layout_changed {
+            self.free_blocks.set(Some(self.scan_free_blocks()?));
+        }
+        if let Some(free) = self.free_blocks.get() {
+            block.serialize_into(&free, FREE_BLOCKS_OFFSET)?;
+        }
+        block.serialize_into(&self.reserved_data_blocks, RESERVED_BLOCKS_OFFSET)?;
+        self.b_put(&block)?;
+        self.header_dirty = false;
+        return Ok(())
+    }
+}
+
+impl BlockSupport for CustomBlockFileSystem {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        return CustomBlockFileSystem::b_get(self, i)
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        return CustomBlockFileSystem::b_put(self, b)
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        return CustomBlockFileSystem::b_free(self, i)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        return CustomBlockFileSystem::b_zero(self, i)
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        return CustomBlockFileSystem::b_alloc(self)
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        return CustomBlockFileSystem::sup_get(self)
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        return CustomBlockFileSystem::sup_put(self, sup)
+    }
+}
+
+// If you want to write more complicated tests that create actual files on your system, take a look at `utils.rs` in the assignment, and how it is used in the `fs_tests` folder to perform the tests. I have imported it below to show you how it can be used.
+// The `utils` folder has a few other useful methods too (nothing too crazy though, you might want to write your own utility functions, or use a testing framework in rust, if you want more advanced features)
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::path::PathBuf;
+
+    use cplfs_api::{controller::Device, fs::FileSysSupport, types::{Block, DINODE_SIZE, SuperBlock}};
+    use std::sync::{Arc, Mutex};
+    use super::{FORMAT_VERSION, FORMAT_VERSION_OFFSET, FREE_BLOCKS_OFFSET, FS_MAGIC, FS_MAGIC_OFFSET, AllocMode, BlockDevice, BlockIoEvent, BlockIoOp, CacheStats, CustomBlockFileSystem, CustomBlockFileSystemError, SbInvalidReason};
+    //use a_block_support::CustomBlockFileSystem;
+    fn disk_prep_path(name: &str) -> PathBuf {
+        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    }
+    
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomBlockFileSystem>();
+    }
+
+    #[test]
+    fn sb_valid() {
+        static BLOCK_SIZE: u64 = 1000;
+        static NBLOCKS: u64 = 10;
+        static SUPERBLOCK_BAD: SuperBlock = SuperBlock {
+            block_size: BLOCK_SIZE, //Note; assumes at least 2 inodes fit in one block. 
+            nblocks: NBLOCKS,
+            ninodes: 6,
+            inodestart: 1,
+            // too many data blocks
+            ndatablocks: 20,
+            bmapstart: 4,
+            datastart: 5,
+        };
+
+        // Too many data blocks for the bitmap to store
+        static SUPERBLOCK_BAD_2: SuperBlock = SuperBlock {
+            block_size: 120, //Note; assumes at least 1  inodes fit in one block.
+            nblocks: 1000,
+            ninodes: 3,
+            inodestart: 1,
+            // too many data blocks
+            ndatablocks: 995,
+            bmapstart: 4,
+            datastart: 5,
+        };
+
+        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_BAD), false);
+        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_BAD_2), false);
+        assert_eq!(CustomBlockFileSystem::sb_validate(&SUPERBLOCK_BAD), Err(SbInvalidReason::DataDoesNotFit));
+        assert_eq!(CustomBlockFileSystem::sb_validate(&SUPERBLOCK_BAD_2), Err(SbInvalidReason::BitmapTooSmall));
+    }
+
+    #[test]
+    fn sb_layout() {
+        static SUPERBLOCK_SMALL: SuperBlock = SuperBlock {
+            block_size: 1000,
+            nblocks: 10,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        static SUPERBLOCK_LARGE: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        // the layouts fill the same disks at least as well as the hand-written superblocks
+        for fixture in [&SUPERBLOCK_SMALL, &SUPERBLOCK_LARGE].iter() {
+            let sb = CustomBlockFileSystem::sb_layout(fixture.block_size, fixture.nblocks, fixture.ninodes).unwrap();
+            assert!(CustomBlockFileSystem::sb_valid(&sb));
+            assert_eq!((sb.block_size, sb.nblocks, sb.ninodes, sb.inodestart), (fixture.block_size, fixture.nblocks, fixture.ninodes, 1));
+            assert!(sb.ndatablocks >= fixture.ndatablocks);
+            assert_eq!(sb.datastart + sb.ndatablocks, sb.nblocks);
+        }
+        // 6 inodes fit in a single block of 1000 bytes
+        let sb = CustomBlockFileSystem::sb_layout(1000, 10, 6).unwrap();
+        assert_eq!(sb, SuperBlock { bmapstart: 2, datastart: 3, ndatablocks: 7, ..SUPERBLOCK_SMALL });
+        // 2497 blocks are left after the inodes, which takes a second bitmap block to keep track of
+        let sb = CustomBlockFileSystem::sb_layout(300, 2500, 2).unwrap();
+        assert_eq!(sb, SuperBlock { ninodes: 2, bmapstart: 2, datastart: 4, ndatablocks: 2496, ..SUPERBLOCK_LARGE });
+
+        // a valid layout for every size of disk that is large enough
+        let inode_blocks = (*DINODE_SIZE * 7 + 299) / 300;
+        for nblocks in 0..1 + inode_blocks + 2 {
+            assert!(matches!(CustomBlockFileSystem::sb_layout(300, nblocks, 7), Err(CustomBlockFileSystemError::DiskTooSmall)));
+        }
+        for nblocks in 1 + inode_blocks + 2..5000 {
+            let sb = CustomBlockFileSystem::sb_layout(300, nblocks, 7).unwrap();
+            assert_eq!(CustomBlockFileSystem::sb_validate(&sb), Ok(()));
+            assert!(CustomBlockFileSystem::sb_validate(&SuperBlock { ndatablocks: sb.ndatablocks + 1, ..sb }).is_err());
+        }
+        assert!(CustomBlockFileSystem::sb_layout(0, 10, 1).is_err());
+    }
+
+    #[test]
+    fn sb_validate() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 1000,
+            nblocks: 10,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&SUPERBLOCK_GOOD), Ok(()));
+        let too_many_inodes = SuperBlock { ninodes: 1000, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&too_many_inodes), Err(SbInvalidReason::InodeRegionTooSmall));
+        let bitmap_after_data = SuperBlock { bmapstart: 6, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&bitmap_after_data), Err(SbInvalidReason::RegionsOutOfOrder));
+        let no_superblock = SuperBlock { inodestart: 0, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&no_superblock), Err(SbInvalidReason::RegionsOutOfOrder));
+        let past_the_end = SuperBlock { ndatablocks: 6, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&past_the_end), Err(SbInvalidReason::DataDoesNotFit));
+        assert!(!CustomBlockFileSystem::sb_valid(&past_the_end));
+        // used to pass: an empty data region starting right at the end of the disk
+        let no_data = SuperBlock { ndatablocks: 0, datastart: 10, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&no_data), Err(SbInvalidReason::RegionsOverlap));
+        // a data region so large that its end wraps around is rejected rather than overflowing
+        let wrapping = SuperBlock { block_size: 1 << 62, ndatablocks: u64::MAX - 2, ..SUPERBLOCK_GOOD };
+        assert_eq!(CustomBlockFileSystem::sb_validate(&wrapping), Err(SbInvalidReason::DataDoesNotFit));
+    }
+
+    #[test]
+    fn sb_valid_strict() {
+        static SUPERBLOCK_BIG_BITMAP: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 20,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 7,
+        };
+        assert!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_BIG_BITMAP));
+        assert!(matches!(
+            CustomBlockFileSystem::sb_valid_strict(&SUPERBLOCK_BIG_BITMAP),
+            Err(CustomBlockFileSystemError::BitmapOverProvisioned)
+        ));
+
+        let minimal = SuperBlock { datastart: 5, ..SUPERBLOCK_BIG_BITMAP };
+        assert!(CustomBlockFileSystem::sb_valid_strict(&minimal).is_ok());
+        let invalid = SuperBlock { ndatablocks: 100, ..SUPERBLOCK_BIG_BITMAP };
+        assert!(matches!(
+            CustomBlockFileSystem::sb_valid_strict(&invalid),
+            Err(CustomBlockFileSystemError::InvalidSuperBlock)
+        ));
+    }
+
+    #[test]
+    fn free_alloc_multiple_bblocks() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300, //Note; assumes at least 1 inodes fit in one block.
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+
+        assert_eq!(CustomBlockFileSystem::sb_valid(&SUPERBLOCK_GOOD), true);   
+        let path = disk_prep_path("free_alloc_multiple_blocks");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut byte: [u8; 1] = [0];
+        for i in 0..SUPERBLOCK_GOOD.ndatablocks {
+            assert_eq!(my_fs.b_alloc().unwrap(), i); //Fill up all data blocks
+        }
+        assert!(my_fs.b_alloc().is_err()); //No more blocks
+
+        //Check the bitmap in second block
+        let bb = my_fs.b_get(5).unwrap();
+        bb.read_data(&mut byte, 0).unwrap();
+        // everything here should be allocated in first byte
+        assert_eq!(byte[0], 0b1111_1111);
+        
+        //Deallocate fist block
+        my_fs.b_free(2400).unwrap();
+        assert!(my_fs.b_free(2400).is_err());
+
+        //Check the bitmap in second block
+        let bb = my_fs.b_get(5).unwrap();
+        bb.read_data(&mut byte, 0).unwrap();
+        assert_eq!(byte[0], 0b1111_1110);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_descending() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        let path = disk_prep_path("alloc_descending");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_alloc_mode(AllocMode::HighestFirst);
+        // the last valid data block lives in the padded last bitmap block
+        assert_eq!(my_fs.b_alloc().unwrap(), 2493);
+        assert_eq!(my_fs.b_alloc().unwrap(), 2492);
+        my_fs.b_free(2493).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 2493);
+        for i in (0..2492).rev() {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert!(my_fs.b_alloc().is_err());
+
+        // 2494 = 2400 + 94 bits in the second bitmap block, so its padding starts at bit 6 of byte 11
+        let mut byte: [u8; 1] = [0];
+        let bb = my_fs.b_get(5).unwrap();
+        bb.read_data(&mut byte, 11).unwrap();
+        assert_eq!(byte[0], 0b0011_1111);
+        for i in 12..300 {
+            bb.read_data(&mut byte, i).unwrap();
+            assert_eq!(byte[0], 0);
+        }
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_reserved() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("alloc_reserved");
+        assert!(CustomBlockFileSystem::mkfs_reserved(&path, &SUPERBLOCK_GOOD, 6).is_err());
+        let mut my_fs = CustomBlockFileSystem::mkfs_reserved(&path, &SUPERBLOCK_GOOD, 2).unwrap();
+        let mut byte: [u8; 1] = [0];
+        my_fs.b_get(4).unwrap().read_data(&mut byte, 0).unwrap();
+        assert_eq!(byte[0], 0b0000_0011);
+
+        assert_eq!(my_fs.b_alloc().unwrap(), 2);
+        // only 2 blocks left, so nothing gets allocated
+        assert!(my_fs.b_alloc_many(3).is_err());
+        my_fs.b_get(4).unwrap().read_data(&mut byte, 0).unwrap();
+        assert_eq!(byte[0], 0b0000_0111);
+        assert_eq!(my_fs.b_alloc_many(2).unwrap(), vec![3, 4]);
+
+        // even when reserved blocks are freed, they are not handed out
+        my_fs.b_free(0).unwrap();
+        assert!(my_fs.b_alloc().is_err());
+
+        // the reserved blocks are still reserved after mounting again
+        let dev = my_fs.unmountfs();
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.reserved_data_blocks(), 2);
+        assert!(my_fs.b_alloc().is_err());
+        my_fs.b_free(3).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 3);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn scrub_clean() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("scrub_clean");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.scrub().unwrap().is_empty());
+        my_fs.b_alloc_many(3).unwrap();
+        my_fs.b_put(&utils::n_block(6, 300, 7)).unwrap();
+        assert!(my_fs.scrub().unwrap().is_empty());
+        // scrubbing does not change anything
+        assert_eq!(my_fs.b_get(6).unwrap(), utils::n_block(6, 300, 7));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn free_fragmentation() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("free_fragmentation");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.fragmentation().unwrap(), 0.0);
+
+        my_fs.b_alloc_many(10).unwrap();
+        assert_eq!(my_fs.fragmentation().unwrap(), 0.0);
+        // free runs {1}, {3, 4} and {7}
+        let mut bitmap = my_fs.b_get(4).unwrap();
+        bitmap.write_data(&[0b0110_0101, 0b0000_0011], 0).unwrap();
+        my_fs.b_put(&bitmap).unwrap();
+        assert_eq!(my_fs.fragmentation().unwrap(), 2.0 / 3.0);
+
+        // a single run
+        bitmap.write_data(&[0b0000_0011, 0b0000_0000], 0).unwrap();
+        my_fs.b_put(&bitmap).unwrap();
+        assert_eq!(my_fs.fragmentation().unwrap(), 0.0);
+        // everything separated
+        bitmap.write_data(&[0b1010_1010, 0b0000_0010], 0).unwrap();
+        my_fs.b_put(&bitmap).unwrap();
+        assert_eq!(my_fs.fragmentation().unwrap(), 1.0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn zero_free_absolute_index() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("zero_free_absolute_index");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let index = my_fs.b_alloc().unwrap();
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap();
+        block.write_data(&[7], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+
+        // block numbers on disk are not data indices
+        for absolute in SUPERBLOCK_GOOD.datastart..SUPERBLOCK_GOOD.nblocks {
+            assert!(my_fs.b_zero(absolute).is_err());
+            assert!(my_fs.b_free(absolute).is_err());
+            assert!(my_fs.b_is_allocated(absolute).is_err());
+        }
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap(), block);
+        assert!(my_fs.b_is_allocated(index).unwrap());
+
+        my_fs.b_zero(index).unwrap();
+        my_fs.b_free(index).unwrap();
+        assert!(!my_fs.b_is_allocated(index).unwrap());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_aligned() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("alloc_aligned");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        for _ in 0..5 {
+            my_fs.b_alloc().unwrap();
+        }
+        my_fs.b_free(2).unwrap();
+        assert!(my_fs.b_alloc_aligned(0).is_err());
+
+        // blocks 0, 1, 3 and 4 are taken
+        assert_eq!(my_fs.b_alloc_aligned(4).unwrap(), 8);
+        assert!(my_fs.b_is_allocated(8).unwrap());
+        assert!(!my_fs.b_is_allocated(5).unwrap());
+        assert_eq!(my_fs.b_alloc_aligned(2).unwrap(), 2);
+        assert!(matches!(my_fs.b_alloc_aligned(4), Err(CustomBlockFileSystemError::NoFreeDataBlock)));
+        // a failed allocation leaves the bitmap alone
+        assert_eq!(my_fs.b_alloc().unwrap(), 5);
+        assert_eq!(my_fs.b_alloc_aligned(1).unwrap(), 6);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn get_ref_cached() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("get_ref_cached");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let reads = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&reads);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == BlockIoOp::Get {
+                *count.lock().unwrap() += 1;
+            }
+        })));
+
+        let misses = my_fs.cache_stats().misses;
+        let first = my_fs.b_get_ref(5).unwrap();
+        let second = my_fs.b_get_ref(5).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(*reads.lock().unwrap(), 2);
+        assert_eq!(my_fs.cache_stats().misses, misses + 1);
+        assert_eq!(*first, my_fs.b_get(5).unwrap());
+
+        // writing the block replaces the cached copy, earlier copies are left alone
+        let mut block = my_fs.b_get(5).unwrap();
+        block.write_data(&[9], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let third = my_fs.b_get_ref(5).unwrap();
+        assert!(!Arc::ptr_eq(&first, &third));
+        assert_eq!(*third, block);
+        assert_eq!(first.contents_as_ref()[0], 0);
+        assert!(matches!(my_fs.b_get_ref(SUPERBLOCK_GOOD.nblocks), Err(CustomBlockFileSystemError::BlockIndexOutOfBounds)));
+        my_fs.set_io_hook(None);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn block_cache() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("block_cache");
+        let my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let dev = my_fs.unmountfs();
+        let mut my_fs = CustomBlockFileSystem::mountfs_with_cache_capacity(dev, 2).unwrap();
+        assert_eq!(my_fs.cache_stats(), CacheStats { hits: 0, misses: 0 });
+
+        // repeated reads only go to the device once
+        let block = my_fs.b_get(5).unwrap();
+        assert_eq!(my_fs.b_get(5).unwrap(), block);
+        assert_eq!(my_fs.b_get(5).unwrap(), block);
+        assert_eq!(my_fs.cache_stats(), CacheStats { hits: 2, misses: 1 });
+
+        // writes go to both the cache and the device
+        let index = my_fs.b_alloc().unwrap();
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap();
+        block.write_data(&[1, 2, 3], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let stats = my_fs.cache_stats();
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap(), block);
+        assert_eq!(my_fs.cache_stats().hits, stats.hits + 1);
+        assert_eq!(my_fs.device().read_block(SUPERBLOCK_GOOD.datastart + index).unwrap(), block);
+        my_fs.b_zero(index).unwrap();
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap(), Block::new_zero(SUPERBLOCK_GOOD.datastart + index, 300));
+        my_fs.b_free(index).unwrap();
+        assert!(!my_fs.b_is_allocated(index).unwrap());
+
+        // the least recently used block is evicted
+        my_fs.b_get(6).unwrap();
+        my_fs.b_get(7).unwrap();
+        my_fs.b_get(8).unwrap();
+        let stats = my_fs.cache_stats();
+        my_fs.b_get(6).unwrap();
+        assert_eq!(my_fs.cache_stats().misses, stats.misses + 1);
+        my_fs.b_get(8).unwrap();
+        assert_eq!(my_fs.cache_stats().hits, stats.hits + 1);
+
+        // without a cache, every read goes to the device
+        my_fs.set_cache_capacity(0);
+        let stats = my_fs.cache_stats();
+        my_fs.b_get(8).unwrap();
+        my_fs.b_get(8).unwrap();
+        assert_eq!(my_fs.cache_stats(), CacheStats { hits: stats.hits, misses: stats.misses + 2 });
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn trailing_free() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("trailing_free");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.trim_trailing_free().unwrap(), 10);
+        for _ in 0..7 {
+            my_fs.b_alloc().unwrap();
+        }
+        assert_eq!(my_fs.trim_trailing_free().unwrap(), 3);
+        my_fs.b_free(6).unwrap();
+        my_fs.b_free(5).unwrap();
+        my_fs.b_free(1).unwrap();
+        assert_eq!(my_fs.trim_trailing_free().unwrap(), 5);
+        // nothing was changed
+        assert!(my_fs.b_is_allocated(4).unwrap());
+        assert_eq!(my_fs.trim_trailing_free().unwrap(), 5);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_lowest_first() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("alloc_lowest_first");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        for i in 0..6 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        // freed blocks are handed out again lowest first, before any block that was never used
+        my_fs.b_free(4).unwrap();
+        my_fs.b_free(1).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 1);
+        assert_eq!(my_fs.b_alloc().unwrap(), 4);
+        my_fs.set_alloc_mode(AllocMode::HighestFirst);
+        assert_eq!(my_fs.b_alloc().unwrap(), 9);
+        my_fs.set_alloc_mode(AllocMode::LowestFirst);
+        assert_eq!(my_fs.b_alloc().unwrap(), 6);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_next_fit() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("alloc_next_fit");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_alloc_mode(AllocMode::NextFit);
+        for i in 0..6 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        // freed blocks are only handed out again after wrapping around
+        my_fs.b_free(1).unwrap();
+        my_fs.b_free(4).unwrap();
+        for i in 6..10 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert_eq!(my_fs.b_alloc().unwrap(), 1);
+        my_fs.b_free(8).unwrap();
+        assert_eq!(my_fs.b_alloc().unwrap(), 4);
+        assert_eq!(my_fs.b_alloc().unwrap(), 8);
+        assert!(matches!(my_fs.b_alloc(), Err(CustomBlockFileSystemError::NoFreeDataBlock)));
+        // the cursor is remembered across modes
+        my_fs.b_free(2).unwrap();
+        my_fs.b_free(9).unwrap();
+        my_fs.set_alloc_mode(AllocMode::LowestFirst);
+        assert_eq!(my_fs.b_alloc().unwrap(), 2);
+        my_fs.set_alloc_mode(AllocMode::NextFit);
+        assert_eq!(my_fs.b_alloc().unwrap(), 9);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn bitmap_bits() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("bitmap_bits");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.datastart + 3).unwrap();
+        block.write_data(&[7], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+
+        my_fs.bitmap_set(3, true).unwrap();
+        assert!(my_fs.bitmap_get(3).unwrap());
+        assert!(my_fs.b_is_allocated(3).unwrap());
+        assert!(!my_fs.b_is_allocated(2).unwrap());
+        // the block is not zeroed, and setting a bit twice is fine
+        my_fs.bitmap_set(3, true).unwrap();
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart + 3).unwrap(), block);
+        assert_eq!(my_fs.b_alloc().unwrap(), 0);
+        my_fs.bitmap_set(0, false).unwrap();
+        my_fs.bitmap_set(0, false).unwrap();
+        assert!(!my_fs.b_is_allocated(0).unwrap());
+        assert!(my_fs.b_is_allocated(3).unwrap());
+        assert!(my_fs.bitmap_set(10, true).is_err());
+        assert!(my_fs.bitmap_get(10).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn count_free() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        let path = disk_prep_path("count_free");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 2494);
+        // allocate from both ends, so both bitmap blocks are counted
+        for i in 1..=5 {
+            my_fs.b_alloc().unwrap();
+            assert_eq!(my_fs.count_free_blocks().unwrap(), 2494 - i);
+        }
+        my_fs.set_alloc_mode(AllocMode::HighestFirst);
+        for i in 6..=10 {
+            assert!(my_fs.b_alloc().unwrap() >= 2400);
+            assert_eq!(my_fs.count_free_blocks().unwrap(), 2494 - i);
+        }
+        my_fs.b_free(2493).unwrap();
+        my_fs.b_free(0).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 2486);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_contiguous() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("alloc_contiguous");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.b_alloc_contiguous(4).unwrap(), 0);
+        for i in 4..10 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.datastart + 6).unwrap();
+        block.write_data(&[7, 7, 7], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+        // two runs of 2 and one of 3 blocks
+        for i in [1, 2, 5, 6, 7, 9].iter() {
+            my_fs.b_free(*i).unwrap();
+        }
+        assert!(matches!(my_fs.b_alloc_contiguous(4), Err(CustomBlockFileSystemError::NoContiguousRun)));
+        assert!(matches!(my_fs.b_alloc_contiguous(0), Err(CustomBlockFileSystemError::NoContiguousRun)));
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 6);
+        assert_eq!(my_fs.b_alloc_contiguous(3).unwrap(), 5);
+        for i in 5..8 {
+            assert!(my_fs.b_is_allocated(i).unwrap());
+        }
+        let zero = my_fs.b_get(SUPERBLOCK_GOOD.datastart + 6).unwrap();
+        assert!(zero.contents_as_ref().iter().all(|byte| *byte == 0));
+        assert_eq!(my_fs.b_alloc_contiguous(2).unwrap(), 1);
+        assert!(matches!(my_fs.b_alloc_contiguous(2), Err(CustomBlockFileSystemError::NoContiguousRun)));
+        assert_eq!(my_fs.b_alloc_contiguous(1).unwrap(), 9);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn free_range() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        let path = disk_prep_path("free_range");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        // the first bitmap block holds the bits of blocks 0 up to 2400
+        for i in 2390..2410 {
+            my_fs.bitmap_set(i, true).unwrap();
+        }
+        assert!(matches!(my_fs.b_free_range(2389, 5), Err(CustomBlockFileSystemError::BlockIsAlreadyFree)));
+        assert!(matches!(my_fs.b_free_range(2409, 2), Err(CustomBlockFileSystemError::BlockIsAlreadyFree)));
+        assert!(matches!(my_fs.b_free_range(2490, 5), Err(CustomBlockFileSystemError::DataIndexOutOfBounds)));
+        assert!(matches!(my_fs.b_free_range(2400, u64::MAX), Err(CustomBlockFileSystemError::DataIndexOutOfBounds)));
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 2494 - 20);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| log.lock().unwrap().push((event.block, event.op)))));
+        my_fs.b_free_range(2395, 10).unwrap();
+        my_fs.set_io_hook(None);
+        assert_eq!(*events.lock().unwrap(), vec![(4, BlockIoOp::Get), (5, BlockIoOp::Get), (4, BlockIoOp::Put), (5, BlockIoOp::Put)]);
+        for i in 2390..2410 {
+            assert_eq!(my_fs.b_is_allocated(i).unwrap(), !(2395..2405).contains(&i));
+        }
+        my_fs.b_free_range(2405, 0).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 2494 - 10);
+        // only the blocks that were actually freed are counted
+        assert_eq!(my_fs.stats().b_free, 10);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn is_free() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 2500,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 2494,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        let path = disk_prep_path("is_free");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let index = my_fs.b_alloc().unwrap();
+        assert!(!my_fs.b_is_free(index).unwrap());
+        assert!(my_fs.b_is_free(index + 1).unwrap());
+        // bits in the second bitmap block
+        my_fs.bitmap_set(2493, true).unwrap();
+        assert!(!my_fs.b_is_free(2493).unwrap());
+        assert!(my_fs.b_is_free(2492).unwrap());
+        assert!(matches!(my_fs.b_is_free(2494), Err(CustomBlockFileSystemError::DataIndexOutOfBounds)));
+        // querying changes nothing
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 2492);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn in_memory() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 15,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        assert!(CustomBlockFileSystem::mkfs_in_memory(&SuperBlock { ndatablocks: 11, ..SUPERBLOCK_GOOD }).is_err());
+        let mut my_fs = CustomBlockFileSystem::mkfs_in_memory(&SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.device().is_in_memory());
+        let index = my_fs.b_alloc().unwrap();
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap();
+        block.write_data(&[1, 2, 3], 10).unwrap();
+        my_fs.b_put(&block).unwrap();
+
+        let bytes = my_fs.unmountfs().to_bytes();
+        let dev = Device::from_bytes(SUPERBLOCK_GOOD.block_size, SUPERBLOCK_GOOD.nblocks, bytes).unwrap();
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert!(my_fs.b_is_allocated(index).unwrap());
+        assert!(!my_fs.b_is_allocated(index + 1).unwrap());
+        assert_eq!(my_fs.b_get(SUPERBLOCK_GOOD.datastart + index).unwrap(), block);
+        // nothing to clean up on disk
+        my_fs.unmountfs().destruct();
+    }
+
+    #[test]
+    fn checksummed() {
+        static SUPERBLOCK_CHECKSUMMED: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 11,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        // the data region runs up to the last block, leaving no room for the table
+        let full = SuperBlock { ndatablocks: 6, ..SUPERBLOCK_CHECKSUMMED };
+        assert_eq!(CustomBlockFileSystem::sb_validate_checksummed(&full), Err(SbInvalidReason::NoRoomForChecksums));
+        assert_eq!(CustomBlockFileSystem::checksum_blocks(&SUPERBLOCK_CHECKSUMMED), 1);
+
+        let path = disk_prep_path("checksummed");
+        let mut my_fs = CustomBlockFileSystem::mkfs_checksummed(&path, &SUPERBLOCK_CHECKSUMMED).unwrap();
+        assert!(my_fs.is_checksummed());
+        let i = my_fs.b_alloc().unwrap();
+        let mut block = Block::new_zero(SUPERBLOCK_CHECKSUMMED.datastart + i, 300);
+        block.write_data(&[1, 2, 3], 10).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let mut dev = my_fs.unmountfs();
+
+        // intact blocks read back fine after mounting again
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert!(my_fs.is_checksummed());
+        assert_eq!(my_fs.b_get(block.block_no).unwrap(), block);
+        assert_eq!(my_fs.scrub().unwrap(), vec![]);
+        dev = my_fs.unmountfs();
+
+        // flip a byte behind the back of the file system
+        let mut corrupted = dev.read_block(block.block_no).unwrap();
+        corrupted.write_data(&[2], 10).unwrap();
+        dev.write_block(&corrupted).unwrap();
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert!(matches!(my_fs.b_get(block.block_no), Err(CustomBlockFileSystemError::ChecksumMismatch { block: b }) if b == block.block_no));
+        assert_eq!(my_fs.scrub().unwrap(), vec![block.block_no]);
+        // other blocks are unaffected
+        assert!(my_fs.b_get(SUPERBLOCK_CHECKSUMMED.datastart + 1).is_ok());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn scrub() {
+        static SUPERBLOCK_CHECKSUMMED: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 11,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("scrub");
+        let mut my_fs = CustomBlockFileSystem::mkfs_checksummed(&path, &SUPERBLOCK_CHECKSUMMED).unwrap();
+        let mut blocks = Vec::new();
+        for i in 0..3 {
+            let mut block = Block::new_zero(SUPERBLOCK_CHECKSUMMED.datastart + my_fs.b_alloc().unwrap(), 300);
+            block.write_data(&[i + 1; 20], 0).unwrap();
+            my_fs.b_put(&block).unwrap();
+            blocks.push(block);
+        }
+        assert_eq!(my_fs.scrub().unwrap(), vec![]);
+
+        // corrupt one block on the device, while the file system still has a copy of it in its cache
+        assert_eq!(my_fs.b_get(blocks[1].block_no).unwrap(), blocks[1]);
+        let mut corrupted = Block::new(blocks[1].block_no, Box::from(blocks[1].contents_as_ref()));
+        corrupted.write_data(&[0xFF], 5).unwrap();
+        my_fs.device_mut().write_block(&corrupted).unwrap();
+        assert_eq!(my_fs.scrub().unwrap(), vec![blocks[1].block_no]);
+
+        // the same for a block that is not cached
+        let dev = my_fs.unmountfs();
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        let mut corrupted = Block::new(blocks[2].block_no, Box::from(blocks[2].contents_as_ref()));
+        corrupted.write_data(&[0xFF], 5).unwrap();
+        my_fs.device_mut().write_block(&corrupted).unwrap();
+        assert_eq!(my_fs.scrub().unwrap(), vec![blocks[1].block_no, blocks[2].block_no]);
+        assert_eq!(my_fs.b_get(blocks[0].block_no).unwrap(), blocks[0]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_only_mount() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("read_only_mount");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let i = my_fs.b_alloc().unwrap();
+        let mut block = Block::new_zero(SUPERBLOCK_GOOD.datastart + i, SUPERBLOCK_GOOD.block_size);
+        block.write_data(&[7, 8, 9], 0).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let dev = my_fs.unmountfs();
+
+        let mut my_fs = CustomBlockFileSystem::mountfs_ro(dev).unwrap();
+        assert!(my_fs.is_readonly());
+        let checksum = my_fs.fs_checksum().unwrap();
+        let zeroes = Block::new_zero(SUPERBLOCK_GOOD.datastart + i, SUPERBLOCK_GOOD.block_size);
+        assert!(matches!(my_fs.b_put(&zeroes), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_put_partial(&zeroes, 0, 3), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_free(i), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_zero(i), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_alloc(), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_alloc_many(2), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_alloc_contiguous(2), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_alloc_aligned(2), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        assert!(matches!(my_fs.b_free_range(i, 1), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        // nothing was counted for the refused calls
+        assert_eq!(my_fs.stats().b_alloc, 0);
+        assert_eq!(my_fs.stats().b_free, 0);
+        let sb = SuperBlock { ninodes: 5, ..SUPERBLOCK_GOOD };
+        assert!(matches!(my_fs.sup_put(&sb), Err(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        // reads still work, and nothing changed
+        assert_eq!(my_fs.b_get(block.block_no).unwrap(), block);
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert!(my_fs.b_is_allocated(i).unwrap());
+        assert_eq!(my_fs.fs_checksum().unwrap(), checksum);
+        let dev = my_fs.unmountfs();
+
+        // a regular mount can write again
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert!(!my_fs.is_readonly());
+        my_fs.b_free(i).unwrap();
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    /// Device keeping its blocks in a vector, to check that the file system works with any `BlockDevice`
+    struct VecDevice {
+        block_size: u64,
+        blocks: Vec<Vec<u8>>
+    }
+
+    impl BlockDevice for VecDevice {
+        fn read_block(&self, i: u64) -> cplfs_api::error_given::Result<Block> {
+            return Ok(Block::new(i, self.blocks[i as usize].clone().into_boxed_slice()))
+        }
+
+        fn write_block(&mut self, b: &Block) -> cplfs_api::error_given::Result<()> {
+            self.blocks[b.block_no as usize] = b.contents_as_ref().to_vec();
+            return Ok(())
+        }
+
+        fn block_size(&self) -> u64 {
+            return self.block_size
+        }
+
+        fn nblocks(&self) -> u64 {
+            return self.blocks.len() as u64
+        }
+    }
+
+    #[test]
+    fn custom_device() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let device = VecDevice { block_size: 300, blocks: vec![vec![0; 300]; 10] };
+        let mut my_fs = CustomBlockFileSystem::mkfs_on_device(device, &SUPERBLOCK_GOOD).unwrap();
+        let i = my_fs.b_alloc().unwrap();
+        let mut block = Block::new_zero(SUPERBLOCK_GOOD.datastart + i, 300);
+        block.write_data(&[4, 2], 17).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let device = my_fs.unmountfs_device();
+        assert_eq!(device.blocks[block.block_no as usize][17..19], [4, 2]);
+
+        let my_fs = CustomBlockFileSystem::mountfs_on_device(device).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert_eq!(my_fs.b_get(block.block_no).unwrap(), block);
+        assert!(my_fs.b_is_allocated(i).unwrap());
+        // the device has to match the superblock
+        let small = VecDevice { block_size: 300, blocks: vec![vec![0; 300]; 9] };
+        assert!(matches!(CustomBlockFileSystem::mkfs_on_device(small, &SUPERBLOCK_GOOD), Err(CustomBlockFileSystemError::IncompatibleDeviceSuperBlock)));
+    }
+
+    #[test]
+    fn grow() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 14,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("grow");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        for i in 0..5 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert!(matches!(my_fs.b_alloc(), Err(CustomBlockFileSystemError::NoFreeDataBlock)));
+        // garbage past the end of the bitmap does not survive growing
+        my_fs.b_put_partial(&Block::new(4, vec![0xFF; 300].into_boxed_slice()), 0, 1).unwrap();
+
+        assert!(matches!(my_fs.grow_datablocks(5), Err(CustomBlockFileSystemError::CannotGrow)));
+        my_fs.grow_datablocks(3).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap().ndatablocks, 8);
+        for i in 0..5 {
+            assert!(my_fs.b_is_allocated(i).unwrap());
+        }
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 3);
+        for i in 5..8 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        assert!(my_fs.b_alloc().is_err());
+
+        // the new size is persisted
+        let dev = my_fs.unmountfs();
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap().ndatablocks, 8);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn magic_and_version() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("magic_and_version");
+        let my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut dev = my_fs.unmountfs();
+        let block = dev.read_block(0).unwrap();
+        assert_eq!(block.deserialize_from::<u64>(FS_MAGIC_OFFSET).unwrap(), FS_MAGIC);
+        assert_eq!(block.deserialize_from::<u32>(FORMAT_VERSION_OFFSET).unwrap(), FORMAT_VERSION);
+
+        // a sup_put keeps the magic number
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        my_fs.sup_put(&SUPERBLOCK_GOOD).unwrap();
+        dev = my_fs.unmountfs();
+        assert_eq!(dev.read_block(0).unwrap().deserialize_from::<u64>(FS_MAGIC_OFFSET).unwrap(), FS_MAGIC);
+
+        let mut corrupted = Block::new(0, Box::from(block.contents_as_ref()));
+        corrupted.serialize_into(&(FORMAT_VERSION + 1), FORMAT_VERSION_OFFSET).unwrap();
+        dev.write_block(&corrupted).unwrap();
+        let result = CustomBlockFileSystem::mountfs(dev);
+        assert!(matches!(result, Err(CustomBlockFileSystemError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1));
+
+        // the superblock itself is still valid, but the magic number is not
+        let mut dev = utils::disk_open(&path, 300, 10);
+        let mut corrupted = Block::new(0, Box::from(block.contents_as_ref()));
+        corrupted.write_data(&[0x42], FS_MAGIC_OFFSET).unwrap();
+        dev.write_block(&corrupted).unwrap();
+        assert!(matches!(CustomBlockFileSystem::mountfs(dev), Err(CustomBlockFileSystemError::BadMagic)));
+
+        // a zeroed magic number is not mistaken for a disk written before it was introduced
+        let mut dev = utils::disk_open(&path, 300, 10);
+        let mut corrupted = Block::new(0, Box::from(block.contents_as_ref()));
+        corrupted.serialize_into(&0u64, FS_MAGIC_OFFSET).unwrap();
+        dev.write_block(&corrupted).unwrap();
+        assert!(matches!(CustomBlockFileSystem::mountfs_on_device(dev), Err(CustomBlockFileSystemError::BadMagic)));
+        let dev = utils::disk_open(&path, 300, 10);
+        assert!(matches!(CustomBlockFileSystem::mountfs_legacy(dev), Err(CustomBlockFileSystemError::BadMagic)));
+        let dev = utils::disk_open(&path, 300, 10);
+        assert!(matches!(CustomBlockFileSystem::mountfs(dev), Err(CustomBlockFileSystemError::BadMagic)));
+
+        // a disk holding nothing but a superblock is only mounted when asked for explicitly
+        let mut dev = utils::disk_open(&path, 300, 10);
+        let mut bare = Block::new_zero(0, 300);
+        bare.serialize_into(&SUPERBLOCK_GOOD, 0).unwrap();
+        dev.write_block(&bare).unwrap();
+        assert!(matches!(CustomBlockFileSystem::mountfs_on_device(dev), Err(CustomBlockFileSystemError::BadMagic)));
+        let dev = utils::disk_open(&path, 300, 10);
+        let my_fs = CustomBlockFileSystem::mountfs_legacy(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        let dev = my_fs.unmountfs();
+        // the other mounts accept the same disks as `mountfs`
+        let my_fs = CustomBlockFileSystem::mountfs_ro(dev).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        let dev = my_fs.unmountfs();
+        let my_fs = CustomBlockFileSystem::mountfs_with_cache_capacity(dev, 4).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        let mut dev = my_fs.unmountfs();
+        dev.write_block(&block).unwrap();
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn free_count_persisted() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 22,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 14,
+            bmapstart: 4,
+            datastart: 6,
+        };
+        let stored = |dev: &Device| dev.read_block(0).unwrap().deserialize_from::<u64>(FREE_BLOCKS_OFFSET).unwrap();
+        let path = disk_prep_path("free_count_persisted");
+        let my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let dev = my_fs.unmountfs();
+        assert_eq!(stored(&dev), 14);
+
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        for _ in 0..3 {
+            let allocated = my_fs.b_alloc_many(4).unwrap();
+            for i in allocated.iter().skip(1) {
+                my_fs.b_free(*i).unwrap();
+            }
+        }
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 11);
+        // bits written directly count too, but bits past the end of the data region do not
+        let mut bitmap = my_fs.b_get(4).unwrap();
+        bitmap.write_data(&[0b0100_0111, 0b1100_0000], 0).unwrap();
+        my_fs.b_put(&bitmap).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 10);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), my_fs.scan_free_blocks().unwrap());
+        let dev = my_fs.unmountfs();
+        assert_eq!(stored(&dev), 10);
+
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.stale_free_count(), None);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 10);
+        let mut dev = my_fs.unmountfs();
+
+        // a wrong count on disk is corrected when mounting
+        let mut sb_block = dev.read_block(0).unwrap();
+        sb_block.serialize_into(&4u64, FREE_BLOCKS_OFFSET).unwrap();
+        dev.write_block(&sb_block).unwrap();
+        let my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.stale_free_count(), Some(4));
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 10);
+        let dev = my_fs.unmountfs();
+        assert_eq!(stored(&dev), 10);
+
+        // growing the data region adds to the count
+        let mut my_fs = CustomBlockFileSystem::mountfs(dev).unwrap();
+        my_fs.grow_datablocks(2).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 12);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn block_index_bounds() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("block_index_bounds");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert!(matches!(my_fs.b_get(10), Err(CustomBlockFileSystemError::BlockIndexOutOfBounds)));
+        assert!(matches!(my_fs.b_get(u64::MAX), Err(CustomBlockFileSystemError::BlockIndexOutOfBounds)));
+        assert!(matches!(my_fs.b_put(&Block::new_zero(10, 300)), Err(CustomBlockFileSystemError::BlockIndexOutOfBounds)));
+        assert!(matches!(my_fs.b_put_partial(&Block::new_zero(10, 300), 0, 1), Err(CustomBlockFileSystemError::BlockIndexOutOfBounds)));
+        // the first and last block, including the superblock, stay accessible
+        let sb_block = my_fs.b_get(0).unwrap();
+        my_fs.b_put(&sb_block).unwrap();
+        let last = my_fs.b_get(9).unwrap();
+        my_fs.b_put(&last).unwrap();
 
         let dev = my_fs.unmountfs();
         utils::disk_destruct(dev);
-    }    
+    }
+
+    #[test]
+    fn put_partial() {
+        static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 10,
+            ninodes: 3,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("put_partial");
+        let mut my_fs = CustomBlockFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| log.lock().unwrap().push(event))));
+
+        let mut b6 = my_fs.b_get(6).unwrap();
+        let mut b7 = my_fs.b_get(7).unwrap();
+        b6.write_data(&[1, 2, 3], 100).unwrap();
+        b7.write_data(&[1, 2, 3], 100).unwrap();
+        my_fs.b_put_partial(&b6, 100, 3).unwrap();
+        my_fs.b_put(&b7).unwrap();
+        assert!(my_fs.b_put_partial(&b6, 299, 2).is_err());
+        // a range whose end does not fit in a u64 is out of bounds as well
+        assert!(matches!(my_fs.b_put_partial(&b6, 1, u64::MAX), Err(CustomBlockFileSystemError::ByteRangeOutOfBounds)));
+        my_fs.set_io_hook(None);
+
+        assert_eq!(my_fs.b_get(6).unwrap().contents_as_ref(), my_fs.b_get(7).unwrap().contents_as_ref());
+        assert_eq!(my_fs.b_get(6).unwrap(), b6);
+        assert_eq!(events.lock().unwrap()[2], BlockIoEvent { block: 6, op: BlockIoOp::PutPartial { start: 100, len: 3 } });
+        assert_eq!(events.lock().unwrap().len(), 4);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
 }
 
 // Here we define a submodule, called `tests`, that will contain our unit tests
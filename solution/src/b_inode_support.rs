@@ -21,28 +21,765 @@
 use cplfs_api::{fs::InodeSupport, types::{DInode, SuperBlock}};
 use cplfs_api::fs::BlockSupport;
 use cplfs_api::types::{Block, Inode};
-use cplfs_api::{controller::Device, error_given, fs::FileSysSupport, types::FType, types::{DINODE_SIZE}};
+use cplfs_api::{controller::Device, error_given, fs::FileSysSupport, types::FType, types::{DINODE_SIZE, DIRECT_POINTERS}};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
-use crate::a_block_support::{self, CustomBlockFileSystem};
+use crate::a_block_support::{self, AllocMode, CacheStats, CustomBlockFileSystem, IoHook, OpStats};
 
 /// Type of file system
 pub type FSName = CustomInodeFileSystem;
 
+/// Inode flag marking that the inode still owns the blocks it points to beyond its size,
+/// because it was truncated with `i_trunc_keep_blocks`. Such blocks are reused by writes, and freed by a real truncation.
+pub const INODE_FLAG_BLOCKS_KEPT: u8 = 0b0000_0001;
+
+/// Inode flag marking that the contents of the file are stored inline, in the bytes of its `direct_blocks`, rather than in data blocks
+pub const INODE_FLAG_INLINE: u8 = 0b0000_0010;
+
+/// Inode flag marking that a directory keeps a hash index of its entry names, in the block its last `direct_blocks` slot points to
+pub const INODE_FLAG_HASHED: u8 = 0b0000_0100;
+
+/// Slot of `direct_blocks` that holds the hash index of a directory with `INODE_FLAG_HASHED` set
+pub const HASH_INDEX_SLOT: usize = (DIRECT_POINTERS - 1) as usize;
+
+/// Run of consecutive blocks on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockExtent {
+    /// Number of the first block of the run on disk
+    pub start_block: u64,
+    /// Number of blocks in the run
+    pub length: u64
+}
+
+/// Metadata of an inode, as returned by `i_stat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeStat {
+    /// Number of the inode
+    pub inum: u64,
+    /// File type of the inode
+    pub ft: FType,
+    /// Size of the inode in bytes
+    pub size: u64,
+    /// Number of links to the inode
+    pub nlink: u16,
+    /// Number of data blocks the inode holds on to
+    pub blocks_used: u64
+}
+
+/// Read-only view of the metadata of an inode file system at the time it was taken.
+/// The superblock, the inode table and the bitmap are copied into memory, so later changes to the live file system do not show up in it.
+/// Data blocks are not copied.
+#[derive(Debug)]
+pub struct Snapshot {
+    superblock: SuperBlock,
+    // copies of blocks 0 up to the start of the data region
+    metadata: Vec<Block>,
+    // start of the inode region
+    inode_start: u64,
+    // the amount of inodes per block in this file system
+    nb_inodes_block: u64
+}
+
+impl Snapshot {
+    /// Superblock at the time of the snapshot
+    pub fn superblock(&self) -> SuperBlock {
+        return self.superblock;
+    }
+
+    /// Copy of the metadata block with number `i`, as it was at the time of the snapshot
+    pub fn b_get(&self, i: u64) -> Result<Block, CustomInodeFileSystemError> {
+        match self.metadata.get(i as usize) {
+            Some(block) => return Ok(Block::new(i, Box::from(block.contents_as_ref()))),
+            None => return Err(CustomInodeFileSystemError::BlockNotInSnapshot)
+        }
+    }
+
+    /// Inode with number `i`, as it was at the time of the snapshot
+    pub fn i_get(&self, i: u64) -> Result<Inode, CustomInodeFileSystemError> {
+        if i == 0 {
+            return Err(CustomInodeFileSystemError::ReservedInode);
+        }
+        if i > self.superblock.ninodes - 1 {
+            return Err(CustomInodeFileSystemError::InodeIndexOutOfBounds);
+        }
+        let block = &self.metadata[(self.inode_start + i / self.nb_inodes_block) as usize];
+        let offset = (i % self.nb_inodes_block) * (*DINODE_SIZE);
+        let dinode = block.deserialize_from::<DInode>(offset)?;
+        return Ok(Inode::new(i, dinode));
+    }
+
+    /// Whether the block with index `i` in the data region was allocated at the time of the snapshot
+    pub fn b_is_allocated(&self, i: u64) -> Result<bool, CustomInodeFileSystemError> {
+        if i > self.superblock.ndatablocks - 1 {
+            return Err(CustomInodeFileSystemError::GivenError(a_block_support::CustomBlockFileSystemError::DataIndexOutOfBounds));
+        }
+        let bitmapblockcapacity = self.superblock.block_size * 8;
+        let bitmap_block = &self.metadata[(self.superblock.bmapstart + i / bitmapblockcapacity) as usize];
+        let mut byte: [u8; 1] = [0];
+        bitmap_block.read_data(&mut byte, (i % bitmapblockcapacity) / 8)?;
+        let set_byte = 0b0000_0001 << (i % 8);
+        return Ok(byte[0] & set_byte == set_byte)
+    }
+}
+
+/// Handle to an inode that is being edited, obtained through `i_edit`.
+/// It dereferences to the inode, and writes it back with `i_put` when it is dropped, so changes cannot be forgotten.
+/// Call `commit` instead to write it back explicitly and find out whether that succeeded; errors while dropping are ignored.
+pub struct InodeGuard<'a> {
+    fs: &'a mut CustomInodeFileSystem,
+    inode: Inode,
+    // whether the inode was already written back through `commit`
+    committed: bool
+}
+
+impl InodeGuard<'_> {
+    /// Write the inode back to disk and release the guard
+    pub fn commit(mut self) -> Result<(), CustomInodeFileSystemError> {
+        self.committed = true;
+        self.fs.i_put(&self.inode)?;
+        return Ok(())
+    }
+}
+
+impl Deref for InodeGuard<'_> {
+    type Target = Inode;
+
+    fn deref(&self) -> &Inode {
+        return &self.inode;
+    }
+}
+
+impl DerefMut for InodeGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Inode {
+        return &mut self.inode;
+    }
+}
+
+impl Drop for InodeGuard<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.fs.i_put(&self.inode);
+        }
+    }
+}
+
+/// Source of the current time in seconds since the Unix epoch, see `CustomInodeFileSystem::set_clock`.
+/// It has to be `Send`, so that the file system can still be moved to another thread.
+pub type Clock = Box<dyn Fn() -> u64 + Send>;
+
+/// Seconds since the Unix epoch according to the system clock, the default clock of a file system
+fn system_time() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Reset every field of `dinode` for its next use as an inode of type `ft`, or `TFree` when it is freed, stamping both times with `now`.
+/// Nothing of its previous use (blocks, quota, entry count, flags, indirect block) is carried over.
+fn reset_dinode(dinode: &mut DInode, ft: FType, now: u64) {
+    *dinode = DInode { ft, mtime: now, ctime: now, ..DInode::default() };
+}
+
 /// Custom file system data type
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
 pub struct CustomInodeFileSystem {
     block_system: CustomBlockFileSystem,
     // start of the inode region
     inode_start: u64,
     // the amount of inodes per block in this file system
-    nb_inodes_block: u64
+    nb_inodes_block: u64,
+    // number of inodes according to the superblock, kept up to date by `sup_put`
+    ninodes: u64,
+    // the order in which free inodes are handed out
+    alloc_mode: AllocMode,
+    // whether each inode is free, indexed by inode number, so allocating does not have to scan the inode table.
+    // Built from disk when mounting, and kept up to date by `i_put`
+    free_inodes: Vec<bool>,
+    // source of the current time, in seconds since the Unix epoch, for the timestamps of inodes
+    clock: Clock,
+    // write-through cache of recently used inodes, in a RefCell since reads only borrow the file system
+    inode_cache: RefCell<InodeCache>,
+    // number of calls to `i_get` and `i_put`, the block operations are counted by the block layer
+    op_stats: Cell<OpStats>
+}
+
+/// Number of inodes the inode cache holds
+pub const INODE_CACHE_CAPACITY: usize = 16;
+
+/// Least recently used cache of inodes, keyed by inode number
+struct InodeCache {
+    // cached inodes, from least to most recently used
+    inodes: VecDeque<Inode>,
+    stats: CacheStats
+}
+
+impl InodeCache {
+    fn new() -> InodeCache {
+        InodeCache { inodes: VecDeque::new(), stats: CacheStats::default() }
+    }
+
+    /// Look up inode `inum`, marking it as most recently used, and count the hit or miss
+    fn get(&mut self, inum: u64) -> Option<Inode> {
+        match self.inodes.iter().position(|inode| inode.inum == inum) {
+            Some(position) => {
+                let inode = self.inodes.remove(position).unwrap();
+                self.inodes.push_back(inode.clone());
+                self.stats.hits += 1;
+                return Some(inode)
+            },
+            None => {
+                self.stats.misses += 1;
+                return None
+            }
+        }
+    }
+
+    /// Store a copy of `inode` as the most recently used one, replacing an older copy and evicting the least recently used inode if needed
+    fn insert(&mut self, inode: &Inode) {
+        self.inodes.retain(|cached| cached.inum != inode.inum);
+        if self.inodes.len() == INODE_CACHE_CAPACITY {
+            self.inodes.pop_front();
+        }
+        self.inodes.push_back(inode.clone());
+    }
+
+    /// Drop the inodes for which `stale` holds
+    fn invalidate<F: Fn(u64) -> bool>(&mut self, stale: F) {
+        self.inodes.retain(|cached| !stale(cached.inum));
+    }
 }
 
 impl CustomInodeFileSystem {
     /// Create a new CustomInodeFileSystem given a CustomBlockFileSystem
     pub fn new(blockfs: CustomBlockFileSystem, is: u64, nib: u64) -> CustomInodeFileSystem {
-        CustomInodeFileSystem {  block_system: blockfs, inode_start: is, nb_inodes_block: nib }
-    }  
+        let ninodes = blockfs.superblock.ninodes;
+        CustomInodeFileSystem {  block_system: blockfs, inode_start: is, nb_inodes_block: nib, ninodes, alloc_mode: AllocMode::default(), free_inodes: Vec::new(), clock: Box::new(system_time), inode_cache: RefCell::new(InodeCache::new()), op_stats: Cell::new(OpStats::default()) }
+    }
+
+    /// Replace the source of the current time used for the timestamps of inodes, e.g. by a fixed time in tests.
+    /// The clock returns seconds since the Unix epoch.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Current time according to the clock of the file system, in seconds since the Unix epoch
+    pub fn now(&self) -> u64 {
+        return (self.clock)()
+    }
+
+    /// Set both the modification and the change time of `inode` to now. Only the in-memory inode is changed,
+    /// it still has to be written back with `i_put`.
+    pub fn touch(&self, inode: &mut Inode) {
+        let now = self.now();
+        inode.disk_node.mtime = now;
+        inode.disk_node.ctime = now;
+    }
+
+    /// Rebuild the in-memory map of free inodes from the inode table on disk
+    fn load_free_inodes(&mut self) -> Result<(), CustomInodeFileSystemError> {
+        // inode 0 is never handed out
+        let mut free_inodes = vec![false];
+        for inode in self.iter_inodes() {
+            free_inodes.push(inode?.disk_node.ft == FType::TFree);
+        }
+        self.free_inodes = free_inodes;
+        return Ok(())
+    }
+
+    /// Inode numbers that are free according to the in-memory map, in the order the allocator considers them
+    fn free_candidates(&self) -> Vec<u64> {
+        return self.alloc_order().filter(|inum| self.free_inodes.get(*inum as usize) == Some(&true)).collect()
+    }
+
+    /// Set the order in which both `i_alloc` and `b_alloc` scan for a free inode or data block
+    pub fn set_alloc_mode(&mut self, mode: AllocMode) {
+        self.alloc_mode = mode;
+        self.block_system.set_alloc_mode(mode);
+    }
+
+    /// Write the block `b`, where only the `len` bytes starting at offset `start` changed
+    pub fn b_put_partial(&mut self, b: &Block, start: u64, len: u64) -> Result<(), CustomInodeFileSystemError> {
+        self.block_system.b_put_partial(b, start, len)?;
+        self.invalidate_cached_inodes(b.block_no);
+        return Ok(())
+    }
+
+    /// Number of `i_get` calls served from the inode cache and from disk since the file system was created or mounted
+    pub fn inode_cache_stats(&self) -> CacheStats {
+        return self.inode_cache.borrow().stats;
+    }
+
+    /// Drop the cached copies of the inodes stored in block `block_no`, as it was written without going through `i_put`
+    fn invalidate_cached_inodes(&self, block_no: u64) {
+        if block_no < self.inode_start {
+            return
+        }
+        let block_nb = block_no - self.inode_start;
+        let nb_inodes_block = self.nb_inodes_block;
+        self.inode_cache.borrow_mut().invalidate(|inum| inum / nb_inodes_block == block_nb);
+    }
+
+    /// Check that every block pointer of every inode in use lies in the data region, rather than in the superblock, inode or bitmap region.
+    /// Errors with the first offending inode and block number otherwise. Inodes storing their contents inline have no block pointers.
+    pub fn validate_no_metadata_overlap(&self) -> Result<(), CustomInodeFileSystemError> {
+        let superblock = self.sup_get()?;
+        for inode in self.iter_inodes() {
+            let inode = inode?;
+            if inode.disk_node.ft == FType::TFree || inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+                continue
+            }
+            let in_data_region = |block: u64| superblock.datastart <= block && block < superblock.datastart + superblock.ndatablocks;
+            // check the indirect block itself before reading the addresses it holds
+            let mut blocks = inode.disk_node.direct_blocks.to_vec();
+            blocks.push(inode.disk_node.indirect_block);
+            for block in blocks {
+                if block != 0 && !in_data_region(block) {
+                    return Err(CustomInodeFileSystemError::MetadataOverlap { inum: inode.inum, block });
+                }
+            }
+            for block in self.indirect_addresses(&inode)? {
+                if block != 0 && !in_data_region(block) {
+                    return Err(CustomInodeFileSystemError::MetadataOverlap { inum: inode.inum, block });
+                }
+            }
+        }
+        return Ok(())
+    }
+
+    /// Fetch the inode with number `inum` for editing. The returned guard writes it back when it goes out of scope.
+    pub fn i_edit(&mut self, inum: u64) -> Result<InodeGuard<'_>, CustomInodeFileSystemError> {
+        let inode = self.i_get(inum)?;
+        return Ok(InodeGuard { fs: self, inode, committed: false })
+    }
+
+    /// Fetch the inodes with the given numbers, in the same order, reading every inode block involved only once
+    pub fn i_get_many(&self, inums: &[u64]) -> Result<Vec<Inode>, CustomInodeFileSystemError> {
+        let mut block_nbs = Vec::new();
+        for inum in inums {
+            if *inum == 0 {
+                return Err(CustomInodeFileSystemError::ReservedInode);
+            }
+            if *inum > self.ninodes - 1 {
+                return Err(CustomInodeFileSystemError::InodeIndexOutOfBounds);
+            }
+            block_nbs.push(inum / self.nb_inodes_block);
+        }
+        block_nbs.sort();
+        block_nbs.dedup();
+        let mut blocks = Vec::new();
+        for block_nb in block_nbs.iter() {
+            blocks.push(self.b_get(self.inode_start + block_nb)?);
+        }
+        let mut inodes = Vec::new();
+        for inum in inums {
+            let position = block_nbs.binary_search(&(inum / self.nb_inodes_block)).unwrap();
+            let offset = (inum % self.nb_inodes_block) * (*DINODE_SIZE);
+            let dinode = blocks[position].deserialize_from::<DInode>(offset)?;
+            inodes.push(Inode::new(*inum, dinode));
+        }
+        return Ok(inodes)
+    }
+
+    /// Metadata of the inode with number `inum`. Besides the blocks holding its contents, `blocks_used` counts
+    /// the hash index of a hashed directory and the indirect block, as these are taken from the data region as well.
+    pub fn i_stat(&self, inum: u64) -> Result<InodeStat, CustomInodeFileSystemError> {
+        let inode = self.i_get(inum)?;
+        let owned = &inode.disk_node.direct_blocks[..self.owned_block_slots(&inode) as usize];
+        let mut blocks_used = owned.iter().filter(|block| **block != 0).count() as u64;
+        if inode.disk_node.flags & INODE_FLAG_HASHED != 0 && !(inode.disk_node.direct_blocks[HASH_INDEX_SLOT] == 0) {
+            blocks_used += 1;
+        }
+        if !(inode.disk_node.indirect_block == 0) {
+            blocks_used += 1 + self.indirect_addresses(&inode)?.iter().filter(|block| **block != 0).count() as u64;
+        }
+        return Ok(InodeStat {
+            inum,
+            ft: inode.disk_node.ft,
+            size: inode.disk_node.size,
+            nlink: inode.disk_node.nlink,
+            blocks_used
+        })
+    }
+
+    /// Allocate `count` inodes of type `ft` at once, and return their numbers in the order they were allocated.
+    /// Either all `count` inodes are allocated, or, if not enough inodes are free, none are and an error is returned.
+    /// Inodes sharing an inode block are written to disk together, with a single block write.
+    pub fn i_alloc_batch(&mut self, count: u64, ft: FType) -> Result<Vec<u64>, CustomInodeFileSystemError> {
+        if ft == FType::TFree {
+            return Err(CustomInodeFileSystemError::InvalidInodeType);
+        }
+        let mut inums = Vec::new();
+        for y in self.free_candidates() {
+            if inums.len() as u64 == count {
+                break
+            }
+            if self.i_get(y)?.disk_node.ft == FType::TFree {
+                inums.push(y);
+            } else {
+                self.free_inodes[y as usize] = false;
+            }
+        }
+        if (inums.len() as u64) < count {
+            return Err(CustomInodeFileSystemError::NoFreeInode);
+        }
+        let mut by_block: Vec<u64> = inums.iter().map(|inum| inum / self.nb_inodes_block).collect();
+        by_block.sort();
+        by_block.dedup();
+        for block_nb in by_block {
+            let mut block = self.b_get(self.inode_start + block_nb)?;
+            for inum in inums.iter().filter(|inum| *inum / self.nb_inodes_block == block_nb) {
+                let offset = (inum % self.nb_inodes_block) * (*DINODE_SIZE);
+                let mut dinode = block.deserialize_from::<DInode>(offset)?;
+                reset_dinode(&mut dinode, ft, self.now());
+                block.serialize_into(&dinode, offset)?;
+            }
+            self.b_put(&block)?;
+        }
+        for inum in inums.iter() {
+            self.free_inodes[*inum as usize] = false;
+        }
+        return Ok(inums)
+    }
+
+    /// Inode numbers in the order the allocator considers them. Inode 0 should never be allocated.
+    fn alloc_order(&self) -> Box<dyn Iterator<Item = u64>> {
+        let ninodes = self.ninodes;
+        match self.alloc_mode {
+            AllocMode::LowestFirst | AllocMode::NextFit => return Box::new(1..ninodes),
+            AllocMode::HighestFirst => return Box::new((1..ninodes).rev())
+        }
+    }
+
+    /// Take a read-only snapshot of the superblock, the inode table and the bitmap as they are now
+    pub fn snapshot(&self) -> Result<Snapshot, CustomInodeFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut metadata = Vec::new();
+        for i in 0..superblock.datastart {
+            metadata.push(self.b_get(i)?);
+        }
+        return Ok(Snapshot {
+            superblock,
+            metadata,
+            inode_start: self.inode_start,
+            nb_inodes_block: self.nb_inodes_block
+        });
+    }
+
+    /// Check whether the block with index `i` in the data region is currently allocated
+    pub fn b_is_allocated(&self, i: u64) -> Result<bool, CustomInodeFileSystemError> {
+        let allocated = self.block_system.b_is_allocated(i)?;
+        return Ok(allocated)
+    }
+
+    /// Check whether the block with index `i` in the data region is currently free
+    pub fn b_is_free(&self, i: u64) -> Result<bool, CustomInodeFileSystemError> {
+        let free = self.block_system.b_is_free(i)?;
+        return Ok(free)
+    }
+
+    /// Move the inode region so that it starts at block `new_inodestart`, keeping it in front of the bitmap.
+    /// All inode blocks are copied over, after which the superblock is updated to point at the new region.
+    /// Nothing is changed if the resulting layout would not be valid.
+    pub fn move_inode_region(&mut self, new_inodestart: u64) -> Result<(), CustomInodeFileSystemError> {
+        let mut sb = self.sup_get()?;
+        let old_inodestart = sb.inodestart;
+        sb.inodestart = new_inodestart;
+        if !(new_inodestart < sb.bmapstart) || !Self::sb_valid(&sb) {
+            return Err(CustomInodeFileSystemError::InvalidInodeRegion);
+        }
+        let nb_blocks = (sb.ninodes + self.nb_inodes_block - 1) / self.nb_inodes_block;
+        // copy in an order that never overwrites a block before it has been copied
+        let order: Box<dyn Iterator<Item = u64>> = if new_inodestart > old_inodestart {
+            Box::new((0..nb_blocks).rev())
+        } else {
+            Box::new(0..nb_blocks)
+        };
+        for x in order {
+            let block = self.b_get(old_inodestart + x)?;
+            self.b_put(&Block::new(new_inodestart + x, block.contents_as_ref().into()))?;
+        }
+        self.sup_put(&sb)?;
+        self.inode_start = new_inodestart;
+        return Ok(())
+    }
+
+    /// Truncate the given `inode` to size 0 like `i_trunc`, but keep the blocks it points to allocated,
+    /// so that writing the file again reuses them instead of allocating new ones.
+    /// A later `i_trunc` or `i_free` does free these blocks. An inline inode has no blocks to keep, so its contents are simply cleared.
+    pub fn i_trunc_keep_blocks(&mut self, inode: &mut Inode) -> Result<(), CustomInodeFileSystemError> {
+        inode.disk_node.size = 0;
+        // the slots of an inline inode hold data, not block pointers
+        if inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+            inode.disk_node.direct_blocks = [0; DIRECT_POINTERS as usize];
+        } else if inode.disk_node.direct_blocks.iter().any(|b| *b != 0) {
+            inode.disk_node.flags |= INODE_FLAG_BLOCKS_KEPT;
+        }
+        self.touch(inode);
+        self.i_put(inode)?;
+        return Ok(())
+    }
+
+    /// Truncate the given `inode` to `new_size` bytes, freeing only the blocks that lie entirely beyond it.
+    /// Once no blocks past the direct ones are needed anymore, the indirect block is freed too.
+    /// If `new_size` is not smaller than the current size, nothing changes: the file is never grown.
+    /// Truncating to 0 is the same as `i_trunc`.
+    pub fn i_trunc_to(&mut self, inode: &mut Inode, new_size: u64) -> Result<(), CustomInodeFileSystemError> {
+        let sb = self.sup_get()?;
+        if new_size > (DIRECT_POINTERS + sb.block_size / 8) * sb.block_size {
+            return Err(CustomInodeFileSystemError::SizeTooLarge);
+        }
+        if new_size >= inode.disk_node.size {
+            return Ok(())
+        }
+        if new_size == 0 {
+            return self.i_trunc(inode)
+        }
+        let keep = (new_size + sb.block_size - 1) / sb.block_size;
+        for index in keep..self.owned_block_slots(inode) {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if !(element == 0) {
+                self.b_free(element - sb.datastart)?;
+                inode.disk_node.direct_blocks[index as usize] = 0;
+            }
+        }
+        self.free_indirect_from(inode, keep.saturating_sub(DIRECT_POINTERS))?;
+        inode.disk_node.size = new_size;
+        self.touch(inode);
+        self.i_put(inode)?;
+        return Ok(())
+    }
+
+    /// Physical layout of the data blocks of the given `inode`, as runs of consecutive blocks on disk in file order.
+    /// Blocks that follow each other in the file and on disk are merged into the same extent.
+    /// Holes are skipped, but end the current extent.
+    pub fn file_layout(&self, inode: &Inode) -> Result<Vec<BlockExtent>, CustomInodeFileSystemError> {
+        let mut extents: Vec<BlockExtent> = Vec::new();
+        let mut after_hole = true;
+        for index in 0..self.owned_block_slots(inode) {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if element == 0 {
+                after_hole = true;
+                continue
+            }
+            match extents.last_mut() {
+                Some(extent) if !after_hole && extent.start_block + extent.length == element => extent.length += 1,
+                _ => extents.push(BlockExtent { start_block: element, length: 1 })
+            }
+            after_hole = false;
+        }
+        return Ok(extents)
+    }
+
+    /// Number of leading slots of `direct_blocks` that belong to the given `inode`.
+    /// These are the slots covering its size, or all of them if the inode kept its blocks when it was truncated.
+    /// Inline inodes own no blocks at all. The hash index of a hashed directory is not counted.
+    pub fn owned_block_slots(&self, inode: &Inode) -> u64 {
+        // the slots of an inline inode hold data, not block pointers
+        if inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+            return 0
+        }
+        if inode.disk_node.flags & INODE_FLAG_BLOCKS_KEPT != 0 {
+            // the last slot of a hashed directory points at its index, rather than at entries
+            if inode.disk_node.flags & INODE_FLAG_HASHED != 0 {
+                return HASH_INDEX_SLOT as u64
+            }
+            return inode.disk_node.direct_blocks.len() as u64
+        }
+        let block_size = self.block_system.superblock.block_size;
+        // the blocks past the direct ones are kept track of by the indirect block
+        return ((inode.disk_node.size + block_size - 1) / block_size).min(DIRECT_POINTERS)
+    }
+
+    /// Addresses held by the indirect block of `inode`, one for every block of the file past the direct ones, 0 where there is none.
+    /// Empty if the inode has no indirect block.
+    pub fn indirect_addresses(&self, inode: &Inode) -> Result<Vec<u64>, CustomInodeFileSystemError> {
+        if inode.disk_node.indirect_block == 0 {
+            return Ok(Vec::new())
+        }
+        let block = self.b_get(inode.disk_node.indirect_block)?;
+        let mut addresses = Vec::new();
+        for slot in 0..block.len() / 8 {
+            addresses.push(block.deserialize_from::<u64>(slot * 8)?);
+        }
+        return Ok(addresses)
+    }
+
+    /// Free the blocks the indirect block of `inode` holds from slot `first` on.
+    /// From slot 0 on, the indirect block itself is freed as well, and removed from the inode.
+    /// The inode itself is not written back.
+    fn free_indirect_from(&mut self, inode: &mut Inode, first: u64) -> Result<(), CustomInodeFileSystemError> {
+        if inode.disk_node.indirect_block == 0 {
+            return Ok(())
+        }
+        let sb = self.sup_get()?;
+        let mut block = self.b_get(inode.disk_node.indirect_block)?;
+        for (slot, address) in self.indirect_addresses(inode)?.into_iter().enumerate().skip(first as usize) {
+            if !(address == 0) {
+                self.b_free(address - sb.datastart)?;
+                block.serialize_into(&0u64, slot as u64 * 8)?;
+            }
+        }
+        if first == 0 {
+            self.b_free(inode.disk_node.indirect_block - sb.datastart)?;
+            inode.disk_node.indirect_block = 0;
+        } else {
+            self.b_put(&block)?;
+        }
+        return Ok(())
+    }
+
+    /// Free the hash index block of `inode`, if it is a hashed directory
+    fn free_hash_index(&mut self, inode: &Inode) -> Result<(), CustomInodeFileSystemError> {
+        let index_block = inode.disk_node.direct_blocks[HASH_INDEX_SLOT];
+        if inode.disk_node.flags & INODE_FLAG_HASHED != 0 && !(index_block == 0) {
+            let sb = self.sup_get()?;
+            self.b_free(index_block - sb.datastart)?;
+        }
+        return Ok(())
+    }
+
+    /// Iterate over all inodes in the file system, in order of their number.
+    /// Inode 0 is never handed out, so it is skipped.
+    pub fn iter_inodes(&self) -> impl Iterator<Item = Result<Inode, CustomInodeFileSystemError>> + '_ {
+        let ninodes = self.ninodes;
+        return (1..ninodes).map(move |i| self.i_get(i))
+    }
+
+    /// Iterate over all inodes of the given type `ft`, in order of their number.
+    /// Errors reading an inode are passed on, as its type is unknown.
+    pub fn iter_inodes_of_type(&self, ft: FType) -> impl Iterator<Item = Result<Inode, CustomInodeFileSystemError>> + '_ {
+        return self.iter_inodes().filter(move |inode| match inode {
+            Ok(inode) => inode.disk_node.ft == ft,
+            Err(_) => true
+        })
+    }
+
+    /// Number of free inodes, not counting inode 0 as it is never handed out. Changes nothing.
+    pub fn count_free_inodes(&self) -> Result<u64, CustomInodeFileSystemError> {
+        let mut free = 0;
+        for inode in self.iter_inodes_of_type(FType::TFree) {
+            inode?;
+            free += 1;
+        }
+        return Ok(free)
+    }
+
+    /// Number of data blocks that are free according to the bitmap
+    pub fn count_free_blocks(&self) -> Result<u64, CustomInodeFileSystemError> {
+        let free = self.block_system.count_free_blocks()?;
+        return Ok(free)
+    }
+
+    /// Read every allocated data block and return the numbers (on disk) of the blocks that cannot be read back intact.
+    pub fn scrub(&self) -> Result<Vec<u64>, CustomInodeFileSystemError> {
+        let failed = self.block_system.scrub()?;
+        return Ok(failed)
+    }
+
+    /// Install a callback that is invoked on every block read and write, or remove it again by passing `None`
+    pub fn set_io_hook(&mut self, hook: Option<IoHook>) {
+        self.block_system.set_io_hook(hook);
+    }
+
+    /// Checksum (CRC-64) over the contents of every block on the device, in order
+    pub fn fs_checksum(&self) -> Result<u64, CustomInodeFileSystemError> {
+        let checksum = self.block_system.fs_checksum()?;
+        return Ok(checksum)
+    }
+
+    /// Read the block with number `i`, sharing a single cached copy between callers, see `CustomBlockFileSystem::b_get_ref`
+    pub fn b_get_ref(&self, i: u64) -> Result<Arc<Block>, CustomInodeFileSystemError> {
+        let block = self.block_system.b_get_ref(i)?;
+        return Ok(block);
+    }
+
+    /// Number of free data blocks following the highest allocated one, without changing the file system
+    pub fn trim_trailing_free(&self) -> Result<u64, CustomInodeFileSystemError> {
+        let free = self.block_system.trim_trailing_free()?;
+        return Ok(free);
+    }
+
+    /// Read the bitmap bit of the data block with index `data_index`, see `CustomBlockFileSystem::bitmap_get`
+    pub fn bitmap_get(&self, data_index: u64) -> Result<bool, CustomInodeFileSystemError> {
+        let allocated = self.block_system.bitmap_get(data_index)?;
+        return Ok(allocated);
+    }
+
+    /// Set the bitmap bit of the data block with index `data_index`, see `CustomBlockFileSystem::bitmap_set`
+    pub fn bitmap_set(&mut self, data_index: u64, allocated: bool) -> Result<(), CustomInodeFileSystemError> {
+        self.block_system.bitmap_set(data_index, allocated)?;
+        return Ok(());
+    }
+
+    /// Free the `count` data blocks starting at index `start` at once, see `CustomBlockFileSystem::b_free_range`
+    pub fn b_free_range(&mut self, start: u64, count: u64) -> Result<(), CustomInodeFileSystemError> {
+        self.block_system.b_free_range(start, count)?;
+        return Ok(());
+    }
+
+    /// Number of block reads served from the block cache and from the device
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.block_system.cache_stats();
+    }
+
+    /// Change the number of blocks the block cache holds. 0 disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.block_system.set_cache_capacity(capacity);
+    }
+
+    /// Number of calls to the block and inode operations since the file system was created or mounted, or since `reset_stats`
+    pub fn stats(&self) -> OpStats {
+        let inode_stats = self.op_stats.get();
+        return OpStats { i_get: inode_stats.i_get, i_put: inode_stats.i_put, ..self.block_system.stats() }
+    }
+
+    /// Start counting the calls to the block and inode operations from 0 again
+    pub fn reset_stats(&mut self) {
+        self.block_system.reset_stats();
+        self.op_stats.set(OpStats::default());
+    }
+
+    /// Number of data blocks at the start of the data region that are reserved, and never handed out by the allocator
+    pub fn reserved_data_blocks(&self) -> u64 {
+        return self.block_system.reserved_data_blocks();
+    }
+
+    /// Mount the file system on `dev` like `mountfs`, but read-only, see `CustomBlockFileSystem::mountfs_ro`
+    pub fn mountfs_ro(dev: Device) -> Result<Self, CustomInodeFileSystemError> {
+        let block_fs = CustomBlockFileSystem::mountfs_ro(dev)?;
+        return Self::from_block_fs(block_fs)
+    }
+
+    /// Create an inode file system on top of the mounted block file system `block_fs`
+    fn from_block_fs(block_fs: CustomBlockFileSystem) -> Result<Self, CustomInodeFileSystemError> {
+        let sb = block_fs.sup_get()?;
+        let nb_inodes_block = sb.block_size / *DINODE_SIZE;
+        let inode_start = sb.inodestart;
+        let mut inode_fs = CustomInodeFileSystem::new(block_fs,inode_start , nb_inodes_block);
+        inode_fs.load_free_inodes()?;
+        return Ok(inode_fs);
+    }
+
+    /// Whether this file system was mounted read-only, through `mountfs_ro`
+    pub fn is_readonly(&self) -> bool {
+        return self.block_system.is_readonly();
+    }
+
+    /// Fail with `ReadOnlyFilesystem` if this file system was mounted read-only
+    pub fn check_writable(&self) -> Result<(), CustomInodeFileSystemError> {
+        self.block_system.check_writable()?;
+        return Ok(())
+    }
+
+    /// Write back any state that is only kept in memory to the device, see `CustomBlockFileSystem::flush`
+    pub fn flush(&mut self) -> Result<(), CustomInodeFileSystemError> {
+        self.block_system.flush()?;
+        return Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
@@ -58,13 +795,62 @@ pub enum CustomInodeFileSystemError {
     /// Error thrown when an inode index is greater 
     /// than the number of inodes in the system.
     InodeIndexOutOfBounds,
-    #[error("The inode trying to be freed is already free")]
+    #[error("The inode trying to be freed is already free (TFree)")]
     /// Error thrown when the inode that is trying
     /// to be freed is already free.
     InodeAlreadyFree,
     #[error("There is no free inode available")]
     /// Thrown when there is no free inode available
     NoFreeInode,
+    #[error("Inodes cannot be allocated with type TFree")]
+    /// Thrown when an inode is allocated with the TFree type, as it would be considered free right away
+    InvalidInodeType,
+    #[error("Inode 0 is reserved and cannot be used")]
+    /// Thrown when inode 0 is requested or freed.
+    /// Inode 0 serves as a sentinel (e.g. an empty directory entry refers to it), so it is never allocated.
+    ReservedInode,
+    #[error("The inode region cannot be moved there")]
+    /// Thrown when moving the inode region would result in an invalid layout
+    InvalidInodeRegion,
+    #[error("The size is larger than a file can be")]
+    /// Thrown when a file is truncated to a size that does not fit in its direct blocks
+    SizeTooLarge,
+    #[error("The block is not part of the snapshot")]
+    /// Thrown when a snapshot is asked for a block it did not copy, such as a data block
+    BlockNotInSnapshot,
+    #[error("Inode {inum} points at block {block}, which lies outside the data region")]
+    /// Thrown when an inode in use has a block pointer into the superblock, inode or bitmap region, or past the end of the disk
+    MetadataOverlap {
+        /// Number of the offending inode
+        inum: u64,
+        /// Block number the inode points at
+        block: u64
+    },
+}
+
+impl CustomInodeFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomInodeFileSystemError::GivenError(e) => e.kind(),
+            CustomInodeFileSystemError::APIError(e) => a_block_support::api_error_kind(e),
+            CustomInodeFileSystemError::InodeIndexOutOfBounds
+            | CustomInodeFileSystemError::InodeAlreadyFree
+            | CustomInodeFileSystemError::InvalidInodeType
+            | CustomInodeFileSystemError::ReservedInode
+            | CustomInodeFileSystemError::InvalidInodeRegion
+            | CustomInodeFileSystemError::BlockNotInSnapshot => ErrorKind::InvalidInput,
+            CustomInodeFileSystemError::NoFreeInode => ErrorKind::StorageFull,
+            CustomInodeFileSystemError::SizeTooLarge => ErrorKind::FileTooLarge,
+            CustomInodeFileSystemError::MetadataOverlap { .. } => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl From<CustomInodeFileSystemError> for io::Error {
+    fn from(e: CustomInodeFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
 }
 
 
@@ -75,6 +861,8 @@ impl FileSysSupport for CustomInodeFileSystem {
         return CustomBlockFileSystem::sb_valid(sb);
     }
 
+    /// Every inode, including the reserved inode 0, starts out as a zeroed `TFree` inode.
+    /// Inode 0 stays like that forever: `i_alloc` never hands it out, and `i_get` and `i_free` refuse it.
     fn mkfs<P: AsRef<std::path::Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
         let mut fs = CustomBlockFileSystem::mkfs(path, sb)?;
         let inodestart = sb.inodestart;
@@ -88,7 +876,7 @@ impl FileSysSupport for CustomInodeFileSystem {
             if block_stop > sb.ninodes {
                 break
             }
-            let mut block = fs.device.read_block(inodestart + x)?;
+            let mut block = fs.b_get(inodestart + x)?;
             // for every inode in this in block
             for y in 0..nb_inodes_block {
                 // The number of inodes does not 
@@ -100,23 +888,22 @@ impl FileSysSupport for CustomInodeFileSystem {
                 let dinode = DInode::default();
                 let offset = y * (*DINODE_SIZE);
                 block.serialize_into(&dinode, offset)?;
-                fs.device.write_block(&block)?;
+                fs.b_put(&block)?;
             }
             
         }
-        return Ok(CustomInodeFileSystem::new(fs, inodestart, nb_inodes_block))
+        let mut inode_fs = CustomInodeFileSystem::new(fs, inodestart, nb_inodes_block);
+        inode_fs.load_free_inodes()?;
+        return Ok(inode_fs)
     }
 
     fn mountfs(dev: Device) -> Result<Self, Self::Error> {
         let block_fs = CustomBlockFileSystem::mountfs(dev)?;
-        let sb = block_fs.sup_get()?;
-        let nb_inodes_block = sb.block_size / *DINODE_SIZE;
-        let inode_start = sb.inodestart;
-        return Ok(CustomInodeFileSystem::new(block_fs,inode_start , nb_inodes_block));
+        return Self::from_block_fs(block_fs)
     }
 
     fn unmountfs(self) -> Device {
-        return self.block_system.device;
+        return self.block_system.unmountfs();
     }
 }
 
@@ -128,6 +915,7 @@ impl BlockSupport for CustomInodeFileSystem {
 
     fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
         let result = self.block_system.b_put(b)?;
+        self.invalidate_cached_inodes(b.block_no);
         return Ok(result);
     }
 
@@ -153,6 +941,13 @@ impl BlockSupport for CustomInodeFileSystem {
 
     fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
         let result = self.block_system.sup_put(sup)?;
+        // the inode region may have moved or changed size
+        self.inode_cache.borrow_mut().invalidate(|_| true);
+        // the map of free inodes has to cover the new number of inodes
+        if !(sup.ninodes == self.ninodes) {
+            self.ninodes = sup.ninodes;
+            self.load_free_inodes()?;
+        }
         return Ok(result);
     }
 }
@@ -162,29 +957,51 @@ impl InodeSupport for CustomInodeFileSystem {
     type Inode = Inode;
 
     fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
-        let superblock = self.sup_get()?;
-        if i > superblock.ninodes - 1{
+        let mut stats = self.op_stats.get();
+        stats.i_get += 1;
+        self.op_stats.set(stats);
+        if i == 0 {
+            return Err(CustomInodeFileSystemError::ReservedInode);
+        }
+        if i > self.ninodes - 1{
             return Err(CustomInodeFileSystemError::InodeIndexOutOfBounds);
         }
+        if let Some(inode) = self.inode_cache.borrow_mut().get(i) {
+            return Ok(inode);
+        }
         let required_block = i / self.nb_inodes_block;
         let block = self.b_get(self.inode_start + required_block)?;
         let offset = (i % self.nb_inodes_block) * (*DINODE_SIZE);
         let dinode = block.deserialize_from::<DInode>(offset)?;
-        return Ok(Inode::new(i, dinode));
+        let inode = Inode::new(i, dinode);
+        self.inode_cache.borrow_mut().insert(&inode);
+        return Ok(inode);
     }
 
     fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        let mut stats = self.op_stats.get();
+        stats.i_put += 1;
+        self.op_stats.set(stats);
+        self.check_writable()?;
         let block_nb = ino.inum / self.nb_inodes_block;
         let mut block = self.b_get(self.inode_start + block_nb)?;
         let offset = (ino.inum % self.nb_inodes_block) * (*DINODE_SIZE);
         block.serialize_into(&ino.disk_node, offset)?;
         let result = self.b_put(&block)?;
+        self.inode_cache.borrow_mut().insert(ino);
+        if let Some(free) = self.free_inodes.get_mut(ino.inum as usize) {
+            *free = ino.disk_node.ft == FType::TFree;
+        }
         return Ok(result);
     }
 
     fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        if i == 0 {
+            return Err(CustomInodeFileSystemError::ReservedInode);
+        }
         let sb = self.sup_get()?;
-        if i > sb.ninodes - 1  {
+        if i > self.ninodes - 1  {
             return Err(CustomInodeFileSystemError::InodeIndexOutOfBounds);
         }
 
@@ -195,49 +1012,54 @@ impl InodeSupport for CustomInodeFileSystem {
         
         if inode.disk_node.nlink == 0 {
             let file_blocks = inode.disk_node.direct_blocks;
-            let nb_selected_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil();
-            for index in 0..(nb_selected_blocks as i64){
+            for index in 0..self.owned_block_slots(&inode) {
                 let element = file_blocks[index as usize];
                 if !(element == 0) {
                     self.b_free(element - sb.datastart)?;
                 }
             }
-            inode.disk_node.ft = FType::TFree;
-            inode.disk_node.direct_blocks = [0 as u64;12];
+            self.free_hash_index(&inode)?;
+            self.free_indirect_from(&mut inode, 0)?;
+            reset_dinode(&mut inode.disk_node, FType::TFree, self.now());
             self.i_put(&inode)?;
         }
         return Ok(())
     }
 
     fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
-        let sb = self.sup_get()?;
-        let ninodes = sb.ninodes;
-        // The inode with index 0 should never be allocated.
-        for y in 1..ninodes {
+        self.check_writable()?;
+        if ft == FType::TFree {
+            return Err(CustomInodeFileSystemError::InvalidInodeType);
+        }
+        for y in self.free_candidates() {
             let mut inode = self.i_get(y)?;
             if inode.disk_node.ft == FType::TFree {
-                inode.disk_node.ft = ft;
-                inode.disk_node.size = 0;
-                inode.disk_node.nlink = 0;
+                // inodes freed before `i_free` reset everything may still hold the values of their previous use
+                reset_dinode(&mut inode.disk_node, ft, self.now());
                 self.i_put(&inode)?;
                 return Ok(y);
             }
-        }      
+            // the inode was taken without going through `i_put`
+            self.free_inodes[y as usize] = false;
+        }
         return Err(CustomInodeFileSystemError::NoFreeInode)
     }
 
     fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
         let sb = self.sup_get()?;
         let file_blocks = inode.disk_node.direct_blocks;
-        let selected_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil();
-        for index in 0..(selected_blocks as i64){
+        for index in 0..self.owned_block_slots(inode) {
             let element = file_blocks[index as usize];
             if !(element == 0) {
                 self.b_free(element - sb.datastart)?;
             }
         }
+        self.free_hash_index(inode)?;
+        self.free_indirect_from(inode, 0)?;
         inode.disk_node.size = 0;
         inode.disk_node.direct_blocks = [0 as u64;12];
+        inode.disk_node.flags &= !(INODE_FLAG_BLOCKS_KEPT | INODE_FLAG_HASHED);
+        self.touch(inode);
         self.i_put(&inode)?; 
 
         return Ok(())
@@ -251,8 +1073,11 @@ impl InodeSupport for CustomInodeFileSystem {
 #[path = "../../api/fs-tests"]
 mod test_with_utils {
     use std::path::PathBuf;
-    use cplfs_api::{fs::{FileSysSupport, BlockSupport, InodeSupport}, types::{FType, InodeLike, SuperBlock}};
-    use super::CustomInodeFileSystem;
+    use cplfs_api::{fs::{FileSysSupport, BlockSupport, InodeSupport}, types::{DInode, DINODE_SIZE, FType, InodeLike, SuperBlock}};
+    use std::sync::{Arc, Mutex};
+    use super::{BlockExtent, CustomInodeFileSystem, CustomInodeFileSystemError, INODE_FLAG_BLOCKS_KEPT, INODE_FLAG_HASHED, INODE_FLAG_INLINE};
+    use crate::a_block_support::{BlockIoEvent, BlockIoOp, CacheStats};
+    use crate::a_block_support::AllocMode;
     static BLOCK_SIZE: u64 = 300;
     static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
         block_size: BLOCK_SIZE,
@@ -271,6 +1096,12 @@ mod test_with_utils {
     #[path = "utils.rs"]
     mod utils;
 
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomInodeFileSystem>();
+    }
+
     // exact copy of original test
     #[test]
     fn mkfs_multiple_inode_blocks() {
@@ -411,6 +1242,518 @@ mod test_with_utils {
         let dev = my_fs.unmountfs();
         utils::disk_destruct(dev);
     }
+
+    #[test]
+    fn ialloc_free_type() {
+        let path = disk_prep_path("ialloc_free_type");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+
+        assert!(my_fs.i_alloc(FType::TFree).is_err());
+        // nothing was allocated by the failed call
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+        assert_eq!(my_fs.i_get(2).unwrap().get_ft(), FType::TFree);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn ialloc_resets_inode() {
+        let path = disk_prep_path("ialloc_resets_inode");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        // a free inode left behind with the metadata of its previous use, e.g. on an older image
+        let mut stale = my_fs.i_get(1).unwrap();
+        stale.disk_node.flags = INODE_FLAG_INLINE | INODE_FLAG_BLOCKS_KEPT;
+        stale.disk_node.max_size = 10;
+        stale.disk_node.entry_count = 3;
+        stale.disk_node.indirect_block = SUPERBLOCK_GOOD.datastart;
+        stale.disk_node.direct_blocks[0] = 42;
+        my_fs.i_put(&stale).unwrap();
+
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+        let inode = my_fs.i_get(1).unwrap();
+        assert_eq!(inode.disk_node, DInode { ft: FType::TFile, mtime: inode.disk_node.mtime, ctime: inode.disk_node.ctime, ..DInode::default() });
+
+        // freeing resets the flags as well
+        let mut inode = inode;
+        inode.disk_node.flags = INODE_FLAG_HASHED;
+        my_fs.i_put(&inode).unwrap();
+        my_fs.i_free(1).unwrap();
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.flags, 0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn reserved_inode() {
+        let path = disk_prep_path("reserved_inode");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+
+        assert!(my_fs.i_get(0).is_err());
+        assert!(my_fs.i_free(0).is_err());
+        // inode 0 is never handed out, not even when all other inodes are taken
+        for i in 1..6 {
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i);
+        }
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+        my_fs.set_alloc_mode(AllocMode::HighestFirst);
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn ialloc_descending() {
+        let path = disk_prep_path("ialloc_descending");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_alloc_mode(AllocMode::HighestFirst);
+
+        for i in (1..6).rev() {
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i);
+        }
+        // inode 0 is never handed out
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+        assert_eq!(my_fs.b_alloc().unwrap(), 4);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn iter_inodes_type() {
+        let path = disk_prep_path("iter_inodes_type");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.i_alloc(FType::TFile).unwrap();
+        let dir = my_fs.i_alloc(FType::TDir).unwrap();
+        my_fs.i_alloc(FType::TFile).unwrap();
+
+        assert_eq!(my_fs.iter_inodes().count(), 5);
+        let dirs: Vec<u64> = my_fs.iter_inodes_of_type(FType::TDir).map(|i| i.unwrap().inum).collect();
+        assert_eq!(dirs, vec![dir]);
+        assert_eq!(my_fs.iter_inodes_of_type(FType::TFile).count(), 2);
+        assert_eq!(my_fs.iter_inodes_of_type(FType::TFree).count(), 2);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn move_inodes() {
+        // 3 blocks of inodes, with room for 4
+        static SUPERBLOCK_ROOMY: SuperBlock = SuperBlock {
+            block_size: BLOCK_SIZE,
+            nblocks: 11,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 5,
+            bmapstart: 5,
+            datastart: 6,
+        };
+        let path = disk_prep_path("move_inodes");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_ROOMY).unwrap();
+        for _ in 1..6 {
+            let inum = my_fs.i_alloc(FType::TFile).unwrap();
+            let mut inode = my_fs.i_get(inum).unwrap();
+            inode.disk_node.size = inum * 10;
+            my_fs.i_put(&inode).unwrap();
+        }
+
+        // no room for 3 blocks in front of the bitmap
+        assert!(my_fs.move_inode_region(3).is_err());
+        assert!(my_fs.move_inode_region(0).is_err());
+        my_fs.move_inode_region(2).unwrap();
+        assert_eq!(my_fs.sup_get().unwrap().inodestart, 2);
+        for inum in 1..6 {
+            assert_eq!(my_fs.i_get(inum).unwrap().get_size(), inum * 10);
+        }
+
+        // and back again, also after remounting
+        my_fs.move_inode_region(1).unwrap();
+        let dev = my_fs.unmountfs();
+        let my_fs = CustomInodeFileSystem::mountfs(dev).unwrap();
+        for inum in 1..6 {
+            assert_eq!(my_fs.i_get(inum).unwrap().get_size(), inum * 10);
+        }
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn layout_extents() {
+        let path = disk_prep_path("layout_extents");
+        let my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let contiguous = <<CustomInodeFileSystem as InodeSupport>::Inode as InodeLike>::new(
+            1, &FType::TFile, 0, 3 * BLOCK_SIZE, &[5, 6, 7]).unwrap();
+        assert_eq!(my_fs.file_layout(&contiguous).unwrap(), vec![BlockExtent { start_block: 5, length: 3 }]);
+
+        // blocks following each other on disk but not in the file do not form a single extent
+        let scattered = <<CustomInodeFileSystem as InodeSupport>::Inode as InodeLike>::new(
+            1, &FType::TFile, 0, 5 * BLOCK_SIZE, &[8, 5, 0, 6, 7]).unwrap();
+        assert_eq!(my_fs.file_layout(&scattered).unwrap(), vec![
+            BlockExtent { start_block: 8, length: 1 },
+            BlockExtent { start_block: 5, length: 1 },
+            BlockExtent { start_block: 6, length: 2 }
+        ]);
+        let empty = <<CustomInodeFileSystem as InodeSupport>::Inode as InodeLike>::new(
+            1, &FType::TFile, 0, 0, &[5]).unwrap();
+        assert!(my_fs.file_layout(&empty).unwrap().is_empty());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn ialloc_batch() {
+        let path = disk_prep_path("ialloc_batch");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.i_alloc_batch(6, FType::TFile).is_err());
+        assert_eq!(my_fs.iter_inodes_of_type(FType::TFree).count(), 5);
+
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&writes);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == BlockIoOp::Put {
+                log.lock().unwrap().push(event.block);
+            }
+        })));
+        // 2 inodes per block, so inodes 1 to 3 are spread over the first two inode blocks
+        assert_eq!(my_fs.i_alloc_batch(3, FType::TDir).unwrap(), vec![1, 2, 3]);
+        assert_eq!(*writes.lock().unwrap(), vec![1, 2]);
+        my_fs.set_io_hook(None);
+        for i in 1..4 {
+            assert_eq!(my_fs.i_get(i).unwrap().get_ft(), FType::TDir);
+        }
+
+        assert!(my_fs.i_alloc_batch(3, FType::TFile).is_err());
+        assert_eq!(my_fs.i_alloc_batch(2, FType::TFile).unwrap(), vec![4, 5]);
+        assert!(my_fs.i_alloc_batch(0, FType::TFile).unwrap().is_empty());
+
+        // an inode freed with an indirect block and a quota comes back without any of it
+        let mut inode = my_fs.i_get(5).unwrap();
+        inode.disk_node.indirect_block = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        inode.disk_node.max_size = 100;
+        inode.disk_node.entry_count = 2;
+        inode.disk_node.flags = INODE_FLAG_BLOCKS_KEPT;
+        my_fs.i_put(&inode).unwrap();
+        my_fs.i_free(5).unwrap();
+        assert_eq!(my_fs.i_alloc_batch(1, FType::TFile).unwrap(), vec![5]);
+        let inode = my_fs.i_get(5).unwrap();
+        assert_eq!(inode.disk_node, DInode { ft: FType::TFile, mtime: inode.disk_node.mtime, ctime: inode.disk_node.ctime, ..DInode::default() });
+
+        // the same for an inode that was left behind free without being reset
+        let mut stale = my_fs.i_get(5).unwrap();
+        stale.disk_node.ft = FType::TFree;
+        stale.disk_node.indirect_block = SUPERBLOCK_GOOD.datastart;
+        stale.disk_node.max_size = 100;
+        stale.disk_node.flags = INODE_FLAG_INLINE;
+        stale.disk_node.size = 7;
+        my_fs.i_put(&stale).unwrap();
+        assert_eq!(my_fs.i_alloc_batch(1, FType::TFile).unwrap(), vec![5]);
+        let inode = my_fs.i_get(5).unwrap();
+        assert_eq!(inode.disk_node, DInode { ft: FType::TFile, mtime: inode.disk_node.mtime, ctime: inode.disk_node.ctime, ..DInode::default() });
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn snapshot_keeps_old_state() {
+        let path = disk_prep_path("snapshot_keeps_old_state");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let snapshot = my_fs.snapshot().unwrap();
+
+        let mut ino = my_fs.i_get(inum).unwrap();
+        ino.disk_node.size = 20;
+        ino.disk_node.direct_blocks[0] = my_fs.b_alloc().unwrap() + SUPERBLOCK_GOOD.datastart;
+        my_fs.i_put(&ino).unwrap();
+        my_fs.i_alloc(FType::TDir).unwrap();
+        let mut sb = my_fs.sup_get().unwrap();
+        sb.nblocks -= 1;
+        sb.ndatablocks -= 1;
+        my_fs.sup_put(&sb).unwrap();
+
+        assert_eq!(snapshot.superblock(), SUPERBLOCK_GOOD);
+        let old = snapshot.i_get(inum).unwrap();
+        assert_eq!(old.get_ft(), FType::TFile);
+        assert_eq!(old.get_size(), 0);
+        assert_eq!(old.disk_node.direct_blocks[0], 0);
+        assert_eq!(snapshot.i_get(inum + 1).unwrap().get_ft(), FType::TFree);
+        assert!(!snapshot.b_is_allocated(0).unwrap());
+        assert!(my_fs.b_is_allocated(0).unwrap());
+        assert!(snapshot.i_get(0).is_err());
+        assert!(snapshot.b_get(SUPERBLOCK_GOOD.datastart).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn edit_guard() {
+        let path = disk_prep_path("edit_guard");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        assert!(my_fs.i_edit(0).is_err());
+        {
+            let mut guard = my_fs.i_edit(inum).unwrap();
+            assert_eq!(guard.get_ft(), FType::TFile);
+            guard.disk_node.size = 42;
+            guard.disk_node.nlink = 2;
+        }
+        let ino = my_fs.i_get(inum).unwrap();
+        assert_eq!(ino.get_size(), 42);
+        assert_eq!(ino.get_nlink(), 2);
+
+        let mut guard = my_fs.i_edit(inum).unwrap();
+        guard.disk_node.ft = FType::TDir;
+        guard.commit().unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap().get_ft(), FType::TDir);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn metadata_overlap() {
+        let path = disk_prep_path("metadata_overlap");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        ino.disk_node.direct_blocks[0] = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        ino.disk_node.size = 1;
+        my_fs.i_put(&ino).unwrap();
+        my_fs.validate_no_metadata_overlap().unwrap();
+
+        ino.disk_node.direct_blocks[1] = SUPERBLOCK_GOOD.bmapstart;
+        my_fs.i_put(&ino).unwrap();
+        match my_fs.validate_no_metadata_overlap() {
+            Err(CustomInodeFileSystemError::MetadataOverlap { inum: i, block }) => {
+                assert_eq!(i, inum);
+                assert_eq!(block, SUPERBLOCK_GOOD.bmapstart);
+            },
+            other => panic!("expected a metadata overlap, got {:?}", other)
+        }
+        // once the inode is freed, its pointers no longer matter
+        ino.disk_node.direct_blocks[1] = SUPERBLOCK_GOOD.nblocks;
+        ino.disk_node.ft = FType::TFree;
+        my_fs.i_put(&ino).unwrap();
+        my_fs.validate_no_metadata_overlap().unwrap();
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn count_free() {
+        let path = disk_prep_path("count_free");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert_eq!(my_fs.count_free_inodes().unwrap(), 5);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 5);
+        let first = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.i_alloc(FType::TDir).unwrap();
+        assert_eq!(my_fs.count_free_inodes().unwrap(), 3);
+        my_fs.b_alloc().unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 4);
+        my_fs.i_free(first).unwrap();
+        assert_eq!(my_fs.count_free_inodes().unwrap(), 4);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn trunc_to() {
+        let path = disk_prep_path("trunc_to");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        for i in 0..3 {
+            ino.disk_node.direct_blocks[i] = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        }
+        ino.disk_node.size = 3 * BLOCK_SIZE;
+        my_fs.i_put(&ino).unwrap();
+        let blocks = ino.disk_node.direct_blocks;
+
+        // growing is not supported, and leaves the file as it is
+        my_fs.i_trunc_to(&mut ino, 4 * BLOCK_SIZE).unwrap();
+        // the limit includes the blocks an indirect block can point to
+        assert!(matches!(my_fs.i_trunc_to(&mut ino, (12 + BLOCK_SIZE / 8) * BLOCK_SIZE + 1), Err(CustomInodeFileSystemError::SizeTooLarge)));
+        assert_eq!(my_fs.i_get(inum).unwrap().get_size(), 3 * BLOCK_SIZE);
+
+        my_fs.i_trunc_to(&mut ino, BLOCK_SIZE + BLOCK_SIZE / 2).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap(), ino);
+        assert_eq!(ino.get_size(), BLOCK_SIZE + BLOCK_SIZE / 2);
+        assert_eq!(ino.disk_node.direct_blocks[..3], [blocks[0], blocks[1], 0]);
+        assert!(my_fs.b_is_allocated(blocks[0] - SUPERBLOCK_GOOD.datastart).unwrap());
+        assert!(my_fs.b_is_allocated(blocks[1] - SUPERBLOCK_GOOD.datastart).unwrap());
+        assert!(my_fs.b_is_free(blocks[2] - SUPERBLOCK_GOOD.datastart).unwrap());
+
+        // a block that is only partly needed is kept
+        my_fs.i_trunc_to(&mut ino, BLOCK_SIZE + 1).unwrap();
+        assert_eq!(ino.disk_node.direct_blocks[1], blocks[1]);
+        my_fs.i_trunc_to(&mut ino, 0).unwrap();
+        assert_eq!(ino.disk_node.direct_blocks[..2], [0, 0]);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn trunc_keep_blocks() {
+        let path = disk_prep_path("trunc_keep_blocks");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_clock(Box::new(|| 42));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        for i in 0..2 {
+            ino.disk_node.direct_blocks[i] = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        }
+        ino.disk_node.size = 2 * BLOCK_SIZE;
+        my_fs.i_put(&ino).unwrap();
+        let blocks = ino.disk_node.direct_blocks;
+
+        // the blocks stay with the file
+        my_fs.set_clock(Box::new(|| 43));
+        my_fs.i_trunc_keep_blocks(&mut ino).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap(), ino);
+        assert_eq!(ino.get_size(), 0);
+        assert_eq!(ino.disk_node.direct_blocks, blocks);
+        assert_eq!(ino.disk_node.flags, INODE_FLAG_BLOCKS_KEPT);
+        assert_eq!((ino.disk_node.mtime, ino.disk_node.ctime), (43, 43));
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks - 2);
+
+        // an inline inode has no blocks to keep
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inline = my_fs.i_get(inum).unwrap();
+        inline.disk_node.flags = INODE_FLAG_INLINE;
+        inline.disk_node.direct_blocks[0] = 0x0102_0304;
+        inline.disk_node.size = 4;
+        my_fs.i_put(&inline).unwrap();
+        my_fs.set_clock(Box::new(|| 44));
+        my_fs.i_trunc_keep_blocks(&mut inline).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap(), inline);
+        assert_eq!(inline.get_size(), 0);
+        assert_eq!(inline.disk_node.flags, INODE_FLAG_INLINE);
+        assert_eq!(inline.disk_node.direct_blocks, [0; 12]);
+        assert_eq!((inline.disk_node.mtime, inline.disk_node.ctime), (44, 44));
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks - 2);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_without_scan() {
+        let path = disk_prep_path("alloc_without_scan");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        for i in 1..6 {
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i);
+        }
+        my_fs.i_free(3).unwrap();
+
+        let reads = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&reads);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == BlockIoOp::Get {
+                *counter.lock().unwrap() += 1;
+            }
+        })));
+        // inode 3 itself comes from the inode cache, only its block is read when writing it back
+        assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), 3);
+        assert_eq!(*reads.lock().unwrap(), 1);
+        // a full inode table is noticed without reading anything
+        assert!(matches!(my_fs.i_alloc(FType::TFile), Err(CustomInodeFileSystemError::NoFreeInode)));
+        assert_eq!(*reads.lock().unwrap(), 1);
+        my_fs.set_io_hook(None);
+
+        // the map is rebuilt from disk when mounting
+        my_fs.i_free(2).unwrap();
+        let dev = my_fs.unmountfs();
+        let mut my_fs = CustomInodeFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 2);
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+        // inodes taken with a plain `i_put` are not handed out
+        my_fs.i_free(4).unwrap();
+        let mut ino = my_fs.i_get(4).unwrap();
+        ino.disk_node.ft = FType::TFile;
+        my_fs.i_put(&ino).unwrap();
+        assert!(my_fs.i_alloc(FType::TFile).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn ninodes_after_sup_put() {
+        let path = disk_prep_path("ninodes_after_sup_put");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.i_get(5).is_ok());
+
+        let mut sb = SUPERBLOCK_GOOD;
+        sb.ninodes = 4;
+        my_fs.sup_put(&sb).unwrap();
+        assert!(my_fs.i_get(3).is_ok());
+        assert!(matches!(my_fs.i_get(4), Err(CustomInodeFileSystemError::InodeIndexOutOfBounds)));
+        assert!(matches!(my_fs.i_free(5), Err(CustomInodeFileSystemError::InodeIndexOutOfBounds)));
+        assert_eq!(my_fs.count_free_inodes().unwrap(), 3);
+
+        my_fs.sup_put(&SUPERBLOCK_GOOD).unwrap();
+        assert!(my_fs.i_get(5).is_ok());
+        assert_eq!(my_fs.i_get_many(&[5, 1]).unwrap().len(), 2);
+        assert_eq!(my_fs.count_free_inodes().unwrap(), 5);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn inode_cache() {
+        let path = disk_prep_path("inode_cache");
+        let mut my_fs = CustomInodeFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        // writing an inode block directly drops the cached copies of its inodes
+        let block_no = SUPERBLOCK_GOOD.inodestart + 5 / (BLOCK_SIZE / *DINODE_SIZE);
+        let block = my_fs.b_get(block_no).unwrap();
+        my_fs.b_put(&block).unwrap();
+
+        let reads = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&reads);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == BlockIoOp::Get {
+                *counter.lock().unwrap() += 1;
+            }
+        })));
+        let stats = my_fs.inode_cache_stats();
+        let first = my_fs.i_get(5).unwrap();
+        assert_eq!(my_fs.i_get(5).unwrap(), first);
+        assert_eq!(*reads.lock().unwrap(), 1);
+        assert_eq!(my_fs.inode_cache_stats(), CacheStats { hits: stats.hits + 1, misses: stats.misses + 1 });
+        my_fs.set_io_hook(None);
+
+        // allocating and freeing are never hidden by a stale copy
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), 1);
+        let mut inode = my_fs.i_get(1).unwrap();
+        assert_eq!(inode.disk_node.ft, FType::TFile);
+        inode.disk_node.nlink = 2;
+        my_fs.i_put(&inode).unwrap();
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.nlink, 2);
+        inode.disk_node.nlink = 0;
+        my_fs.i_put(&inode).unwrap();
+        my_fs.i_free(1).unwrap();
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.ft, FType::TFree);
+        // nor is writing an inode block directly
+        let mut block = my_fs.b_get(SUPERBLOCK_GOOD.inodestart).unwrap();
+        block.serialize_into(&DInode { ft: FType::TDir, nlink: 1, ..Default::default() }, *DINODE_SIZE).unwrap();
+        my_fs.b_put(&block).unwrap();
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.ft, FType::TDir);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
 }
 
 
@@ -18,439 +18,2794 @@
 //! ...
 //!
 
-use cplfs_api::{controller::Device, error_given, fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport}, types::{Block, DIRENTRY_SIZE, DIRNAME_SIZE, DirEntry, FType, Inode, SuperBlock}};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use cplfs_api::{controller::Device, error_given, fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport}, types::{Block, DIRENTRY_SIZE, DIRNAME_SIZE, DirEntry, FType, Inode, ROOT_INUM, SuperBlock}};
 use thiserror::Error;
-use crate::b_inode_support::{self, CustomInodeFileSystem};
+use crate::b_inode_support::{self, Clock, CustomInodeFileSystem, InodeGuard, HASH_INDEX_SLOT, INODE_FLAG_HASHED};
+use crate::a_block_support::{self, AllocMode, CacheStats, IoHook, OpStats};
 
 /// Type of my file system
 pub type FSName = CustomDirFileSystem;
 
-// Custom type
-/// Custom file system data type
-pub struct CustomDirFileSystem {
-    inode_fs: CustomInodeFileSystem,
-}
-
-impl CustomDirFileSystem {
+/// Size in bytes of a slot in the hash index of a directory
+const HASH_SLOT_SIZE: u64 = 2;
+/// Value of a hash index slot that was never used
+const HASH_EMPTY: u16 = 0;
+/// Value of a hash index slot whose entry was removed, lookups have to continue past it
+const HASH_TOMBSTONE: u16 = u16::MAX;
 
-    /// Create a new CustomDirFileSystem given a CustomInodeFileSystem
-    pub fn new(inodefs: CustomInodeFileSystem) -> CustomDirFileSystem {
-        CustomDirFileSystem {  inode_fs: inodefs }
-    }  
+/// FNV-1a hash of a directory entry name, to find its slot in the hash index of a directory
+fn name_hash(name: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    return hash
 }
 
-#[derive(Error, Debug)]
-/// Custom type for errors in CustomDirFileSystem
-pub enum CustomDirFileSystemError {
-    /// An error occured in the inode layer
-    #[error("InodeFileSystemError")]
-    GivenError(#[from] b_inode_support::CustomInodeFileSystemError),
-    #[error("API error")]
-    /// The input provided to some method in the controller layer was invalid
-    APIError(#[from] error_given::APIError),
-    #[error("No directory entry was found for the provided name")]
-    /// The provided name did not correspond to a directory entry
-    NoEntryFoundForName,
-    #[error("The provided inode is not of directory type")]
-    /// The provided inode to search for a directory is not of the right type
-    InodeWrongType,
-    #[error("The provided name is invalid for a directory entry")]
-    /// The provided name is invalid for a directory entry
-    InvalidEntryName,
-    #[error("Inode corresponding to inum is not currently in use")]
-    /// Inode corresponding to inum is not currently in use.
-    DirectoryInodeNotInUse,
-    #[error("Inode has no room for extra block")]
-    /// Inode has no room for extra block
-    InodeBlocksFull
-
+/// Metadata of a directory entry and the inode it refers to, as listed by `stat_entries`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InodeStat {
+    /// Name of the entry
+    pub name: String,
+    /// Number of the inode the entry refers to
+    pub inum: u64,
+    /// File type of the inode
+    pub ft: FType,
+    /// Size of the inode in bytes
+    pub size: u64,
+    /// Number of links to the inode
+    pub nlink: u16
 }
 
-impl FileSysSupport for CustomDirFileSystem {
-    type Error = CustomDirFileSystemError;
+/// Problems found by `health_report`. Every field lists the details of one kind of problem; a healthy file system has none at all.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Directory entries referring to a free inode, as the directory, the name of the entry and the inode it refers to
+    pub dangling_entries: Vec<(u64, String, u64)>,
+    /// Inodes in use that no directory entry refers to (other than the root directory)
+    pub orphaned_inodes: Vec<u64>,
+    /// Indices of data blocks that are allocated in the bitmap, but do not belong to any inode
+    pub leaked_blocks: Vec<u64>,
+    /// Indices of data blocks that belong to an inode, but are free in the bitmap
+    pub unallocated_blocks_in_use: Vec<u64>,
+    /// Inodes whose `nlink` disagrees with the number of entries referring to them, as the inode, its `nlink` and the expected value
+    pub link_count_mismatches: Vec<(u64, u16, u64)>
+}
 
-    fn sb_valid(sb: &SuperBlock) -> bool {
-        return CustomInodeFileSystem::sb_valid(sb);
-    }
-    fn mkfs<P: AsRef<std::path::Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
-        let mut inode_fs = CustomInodeFileSystem::mkfs(path, sb)?;
-        // get the first inode and change it's nlink attribute
-        let mut root_inode = inode_fs.i_get(1)?;
-        root_inode.disk_node.nlink = 1;
-        // Change type
-        root_inode.disk_node.ft = FType::TDir;
-        inode_fs.i_put(&root_inode)?;
-        return Ok(CustomDirFileSystem::new(inode_fs))
+impl HealthReport {
+    /// Total number of problems found
+    pub fn issue_count(&self) -> usize {
+        return self.dangling_entries.len() + self.orphaned_inodes.len() + self.leaked_blocks.len()
+            + self.unallocated_blocks_in_use.len() + self.link_count_mismatches.len()
     }
 
-    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
-        let inode_fs = CustomInodeFileSystem::mountfs(dev)?;
-        return Ok(CustomDirFileSystem::new(inode_fs));
+    /// Whether no problems were found at all
+    pub fn is_healthy(&self) -> bool {
+        return self.issue_count() == 0
     }
+}
 
-    fn unmountfs(self) -> Device {
-        return self.inode_fs.unmountfs();
+/// Single problem found by `fsck`. Data blocks are identified by their index in the data region, as in the bitmap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// A data block belongs to the inode `inum`, but is free in the bitmap
+    UnallocatedBlockInUse {
+        /// Index of the block in the data region
+        block: u64,
+        /// Inode the block belongs to
+        inum: u64
+    },
+    /// A data block belongs to more than one inode, or appears twice in the same one
+    BlockReferencedTwice {
+        /// Index of the block in the data region
+        block: u64,
+        /// Inode the block was found in first
+        first: u64,
+        /// Inode the block was found in again
+        second: u64
+    },
+    /// A data block is allocated in the bitmap, but does not belong to any inode
+    LeakedBlock {
+        /// Index of the block in the data region
+        block: u64
+    },
+    /// A directory entry refers to a free inode
+    DanglingEntry {
+        /// Directory holding the entry
+        dir: u64,
+        /// Name of the entry
+        name: String,
+        /// Inode the entry refers to
+        inum: u64
+    },
+    /// An inode in use that no directory entry refers to, other than the root directory
+    OrphanedInode {
+        /// Number of the inode
+        inum: u64
+    },
+    /// The `nlink` of an inode disagrees with the number of entries referring to it
+    LinkCountMismatch {
+        /// Number of the inode
+        inum: u64,
+        /// `nlink` stored in the inode
+        nlink: u16,
+        /// Number of entries referring to the inode
+        expected: u64
     }
 }
 
+/// Problems found by `fsck`, in the order they were found
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Every problem found
+    pub problems: Vec<FsckProblem>
+}
 
-impl BlockSupport for CustomDirFileSystem {
-    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
-        let block = self.inode_fs.b_get(i)?;
-        return Ok(block);      
+impl FsckReport {
+    /// Whether no problems were found at all
+    pub fn is_clean(&self) -> bool {
+        return self.problems.is_empty()
     }
+}
 
-    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
-        let result = self.inode_fs.b_put(b)?;
-        return Ok(result);
-    }
+/// Fills an empty directory with many entries at once, obtained through `dir_builder`.
+/// Entries are appended one after the other into the block being filled, which is only written once it is full,
+/// and duplicate names are detected in memory instead of by scanning the directory.
+/// The directory inode and the link counts of the inodes the entries refer to are only updated by `finish`,
+/// A builder that is dropped without finishing frees the blocks it allocated again, leaving the directory empty.
+#[must_use = "the directory is only updated once the builder is finished"]
+pub struct DirBuilder<'a> {
+    fs: &'a mut CustomDirFileSystem,
+    dir: Inode,
+    // names added so far
+    names: HashSet<String>,
+    // number of links added per inode, in order of first use
+    links: Vec<(u64, u64)>,
+    // block that is being filled, and the number of entries in it
+    current: Option<Block>,
+    used_slots: u64,
+    // number of blocks of the directory in use
+    nb_blocks: u64,
+    nb_entries: u64,
+    // data indices of the blocks allocated so far, freed on drop unless the directory took them over in `finish`
+    allocated: Vec<u64>,
+    committed: bool
+}
 
-    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
-        let res = self.inode_fs.b_free(i)?;
-        return Ok(res)       
+impl DirBuilder<'_> {
+    /// Append an entry with the given `name`, referring to the inode `inum`
+    pub fn add(&mut self, name: &str, inum: u64) -> Result<(), CustomDirFileSystemError> {
+        let dir_entry = match CustomDirFileSystem::new_de(inum, name) {
+            None => return Err(CustomDirFileSystemError::InvalidEntryName),
+            Some(dir_entry) => dir_entry
+        };
+        if self.names.contains(name) {
+            return Err(CustomDirFileSystemError::InvalidEntryName);
+        }
+        let position = match self.links.iter().position(|(other, _)| *other == inum) {
+            Some(position) => position,
+            None => {
+                if self.fs.i_get(inum)?.disk_node.ft == FType::TFree {
+                    return Err(CustomDirFileSystemError::DirectoryInodeNotInUse);
+                }
+                self.links.push((inum, 0));
+                self.links.len() - 1
+            }
+        };
+        let superblock = self.fs.sup_get()?;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        if self.current.is_none() || self.used_slots == nb_dirs {
+            self.flush_block()?;
+            if self.nb_blocks == CustomDirFileSystem::dir_block_limit(&self.dir) {
+                return Err(CustomDirFileSystemError::InodeBlocksFull);
+            }
+            let index = self.fs.b_alloc()?;
+            self.allocated.push(index);
+            let block_no = superblock.datastart + index;
+            self.dir.disk_node.direct_blocks[self.nb_blocks as usize] = block_no;
+            self.nb_blocks += 1;
+            self.current = Some(Block::new_zero(block_no, superblock.block_size));
+            self.used_slots = 0;
+        }
+        if let Some(block) = self.current.as_mut() {
+            block.serialize_into(&dir_entry, self.used_slots * *DIRENTRY_SIZE)?;
+        }
+        self.used_slots += 1;
+        self.nb_entries += 1;
+        self.links[position].1 += 1;
+        self.names.insert(name.to_string());
+        return Ok(())
     }
 
-    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
-        let result = self.inode_fs.b_zero(i)?;
-        return Ok(result);
+    /// Write the block being filled to disk, if there is one
+    fn flush_block(&mut self) -> Result<(), CustomDirFileSystemError> {
+        if let Some(block) = self.current.take() {
+            self.fs.b_put(&block)?;
+        }
+        return Ok(())
     }
 
-    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
-        let index = self.inode_fs.b_alloc()?;
-        return Ok(index);
+    /// Write the last block, the directory inode and the link counts of the inodes the entries refer to, and return the updated directory
+    pub fn finish(mut self) -> Result<Inode, CustomDirFileSystemError> {
+        self.flush_block()?;
+        let superblock = self.fs.sup_get()?;
+        if self.nb_blocks > 0 {
+            self.dir.disk_node.size = superblock.block_size * (self.nb_blocks - 1) + self.used_slots * *DIRENTRY_SIZE;
+        }
+        self.dir.disk_node.entry_count += self.nb_entries as u32;
+        self.fs.inode_fs.touch(&mut self.dir);
+        self.fs.i_put(&self.dir)?;
+        // the blocks belong to the directory now
+        self.committed = true;
+        if self.dir.disk_node.flags & INODE_FLAG_HASHED != 0 {
+            self.fs.hash_index_rebuild(&self.dir)?;
+        }
+        for (inum, count) in self.links.iter() {
+            // as in `dirlink`, entries referring to the directory itself do not count
+            if !(*inum == self.dir.inum) {
+                let mut inode = self.fs.i_get(*inum)?;
+                inode.disk_node.nlink += *count as u16;
+                inode.disk_node.ctime = self.fs.inode_fs.now();
+                self.fs.i_put(&inode)?;
+            }
+        }
+        return Ok(self.fs.i_get(self.dir.inum)?)
     }
+}
 
-    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
-        let superblock = self.inode_fs.sup_get()?;
-        return Ok(superblock);
+impl Drop for DirBuilder<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            for index in self.allocated.iter() {
+                let _ = self.fs.b_free(*index);
+            }
+        }
     }
+}
 
-    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
-        let result = self.inode_fs.sup_put(sup)?;
-        return Ok(result);
-    }
+// Custom type
+/// Custom file system data type
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
+pub struct CustomDirFileSystem {
+    inode_fs: CustomInodeFileSystem,
 }
 
-impl InodeSupport for CustomDirFileSystem {
-    type Inode = Inode;
+impl CustomDirFileSystem {
 
-    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
-        let inode = self.inode_fs.i_get(i)?;
-        return Ok(inode);
+    /// Create a new CustomDirFileSystem given a CustomInodeFileSystem
+    pub fn new(inodefs: CustomInodeFileSystem) -> CustomDirFileSystem {
+        CustomDirFileSystem {  inode_fs: inodefs }
     }
 
-    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
-        let result = self.inode_fs.i_put(ino)?;
-        return Ok(result);
+    /// Allocate a new inode of type `ft` and link it into the directory `parent` under `name`.
+    /// The name is checked before anything is allocated, and the new inode is freed
+    /// again if linking it fails, so a failed create never leaks an inode.
+    /// Returns the number of the newly created inode.
+    pub fn create(&mut self, parent: &mut Inode, name: &str, ft: FType) -> Result<u64, CustomDirFileSystemError> {
+        if !(parent.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        if Self::new_de(0, name).is_none() {
+            return Err(CustomDirFileSystemError::InvalidEntryName);
+        }
+        // only allocate once we know the name is still free
+        match self.dirlookup(parent, name) {
+            Ok(_) => return Err(CustomDirFileSystemError::InvalidEntryName),
+            Err(CustomDirFileSystemError::NoEntryFoundForName) => (),
+            Err(e) => return Err(e)
+        }
+        return self.i_alloc_link(parent, name, ft)
     }
 
-    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
-        let result = self.inode_fs.i_free(i)?;
-        return Ok(result);
+    /// Create a new directory named `name` in the directory `parent`, with a `.` entry referring to itself and a `..` entry referring to `parent`.
+    /// Link counts follow `dirlink`: the new directory gets one link from `parent`, and `parent` one from `..`.
+    /// `parent` is updated to reflect its new entry and link count. Returns the number of the new directory.
+    pub fn mkdir(&mut self, parent: &mut Inode, name: &str) -> Result<u64, CustomDirFileSystemError> {
+        let inum = self.create(parent, name, FType::TDir)?;
+        self.add_dot_entries(inum, parent.inum)?;
+        *parent = self.i_get(parent.inum)?;
+        return Ok(inum)
     }
 
-    fn i_alloc(&mut self, ft: cplfs_api::types::FType) -> Result<u64, Self::Error> {
-        let i = self.inode_fs.i_alloc(ft)?;
-        return Ok(i);
+    /// Allocate a new inode of type `ft` and link it into the directory `parent` under `name`, as a single operation:
+    /// if linking fails (e.g. because the name is taken), the new inode is freed again, so it never leaks.
+    /// Unlike `create`, this does not check the name up front.
+    /// Returns the number of the newly allocated inode.
+    pub fn i_alloc_link(&mut self, parent: &mut Inode, name: &str, ft: FType) -> Result<u64, CustomDirFileSystemError> {
+        let inum = self.i_alloc(ft)?;
+        match self.dirlink(parent, name, inum) {
+            Ok(_) => return Ok(inum),
+            Err(e) => {
+                // nlink is still zero here, so this really releases the inode.
+                // Best effort: the error of `dirlink` is the one worth reporting
+                let _ = self.i_free(inum);
+                return Err(e)
+            }
+        }
     }
 
-    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
-        let result = self.inode_fs.i_trunc(inode)?;
-        return Ok(result);
+    /// Variant of `get_name_str` that checks the name of `de` for signs of corruption,
+    /// i.e. non-printable characters, or characters following the NUL that terminates the name.
+    pub fn try_get_name_str(de: &DirEntry) -> Result<String, NameError> {
+        let mut string = String::new();
+        let mut terminated = false;
+        for c in de.name.iter() {
+            if *c == '\0' {
+                terminated = true;
+            } else if terminated {
+                return Err(NameError::InteriorNul);
+            } else if c.is_control() {
+                return Err(NameError::NonPrintable);
+            } else {
+                string.push(*c);
+            }
+        }
+        return Ok(string)
     }
-}
 
-impl DirectorySupport for CustomDirFileSystem {
-    fn new_de(inum: u64, name: &str) -> Option<DirEntry> {
-        let mut dir_entry = DirEntry::default();
-        dir_entry.inum = inum;
-        match Self::set_name_str(&mut dir_entry, name) {
-            Some(_) => return Some(dir_entry),
-            None => return None
+    /// Return every entry of the directory `inode` whose name starts with `prefix`, as pairs of the name and the inode number.
+    /// An empty prefix returns all entries of the directory.
+    pub fn dir_lookup_prefix(&self, inode: &Inode, prefix: &str) -> Result<Vec<(String, u64)>, CustomDirFileSystemError> {
+        let mut matches = Vec::new();
+        for (dir_entry, _) in self.dir_entries(inode)? {
+            let name = Self::get_name_str(&dir_entry);
+            if name.starts_with(prefix) {
+                matches.push((name, dir_entry.inum));
+            }
         }
+        return Ok(matches)
     }
 
-    fn get_name_str(de: &DirEntry) -> String {
-        let char_array = de.name;
-        let mut string = String::new();
-        for i in 0..DIRNAME_SIZE {
-            if char_array[i] == '\0' {
-                break
+    /// Remove the entry with the given `name` from the directory `inode`, and decrease the `nlink` field of the inode it refers to
+    /// (unless the entry is a self-reference, analogous to `dirlink`). The inode is freed once its `nlink` drops to 0.
+    /// The size of the directory is left untouched, the freed slot is reused by later calls to `dirlink`.
+    /// The `.` and `..` entries cannot be removed, and neither can an entry that refers to a directory that is not empty.
+    /// When a directory is freed, the link its `..` entry held on its parent is dropped as well.
+    /// Returns the byte offset of the removed entry within the given `inode`
+    pub fn dirunlink(&mut self, inode: &mut Inode, name: &str) -> Result<u64, CustomDirFileSystemError> {
+        if name == "." || name == ".." {
+            return Err(CustomDirFileSystemError::CannotUnlinkDotEntry);
+        }
+        let superblock = self.sup_get()?;
+        let (dir_entry, offset) = match self.dir_entries(inode)?.into_iter().find(|(de, _)| Self::get_name_str(de) == name) {
+            Some(found) => found,
+            None => return Err(CustomDirFileSystemError::NoEntryFoundForName)
+        };
+        // entries referring to the directory itself hold no link
+        let mut target = None;
+        if !(inode.inum == dir_entry.inum) {
+            let mut corresponding_inode = self.i_get(dir_entry.inum)?;
+            if corresponding_inode.disk_node.ft == FType::TDir && !self.dir_is_empty(&corresponding_inode)? {
+                return Err(CustomDirFileSystemError::DirectoryNotEmpty);
             }
-            else {
-                string.push(char_array[i]);
+            // an entry whose inode has no links left means the file system is corrupt, so change nothing
+            corresponding_inode.disk_node.nlink = corresponding_inode.disk_node.nlink.checked_sub(1).ok_or(CustomDirFileSystemError::CorruptDirectory)?;
+            target = Some(corresponding_inode);
+        }
+        let block_no = inode.disk_node.direct_blocks[(offset / superblock.block_size) as usize];
+        let mut block = self.b_get(block_no)?;
+        block.serialize_into(&DirEntry::default(), offset % superblock.block_size)?;
+        self.b_put(&block)?;
+        self.hash_index_remove(inode, name, offset)?;
+        if let Some(mut corresponding_inode) = target {
+            corresponding_inode.disk_node.ctime = self.inode_fs.now();
+            self.i_put(&corresponding_inode)?;
+            if corresponding_inode.disk_node.nlink == 0 {
+                if corresponding_inode.disk_node.ft == FType::TDir {
+                    self.drop_parent_link(inode, &corresponding_inode)?;
+                }
+                self.i_free(corresponding_inode.inum)?;
             }
         }
-        return string;
+        inode.disk_node.entry_count -= 1;
+        self.inode_fs.touch(inode);
+        self.i_put(inode)?;
+        return Ok(offset)
     }
 
-    fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()> {
-        let empty_cond = name.is_empty();
-        let point_cond = !(name == "." || name == ".." || name.chars().all(char::is_alphanumeric));
-        let length_cond = name.len() > DIRNAME_SIZE;
-        if empty_cond || point_cond || length_cond{
-            return None
+    /// Drop the link the `..` entry of the directory `dir` holds on the inode it refers to, which is usually `parent`.
+    /// Nothing happens if `dir` has no `..` entry or if it refers to `dir` itself.
+    fn drop_parent_link(&mut self, parent: &mut Inode, dir: &Inode) -> Result<(), CustomDirFileSystemError> {
+        let dotdot = match self.dir_entries(dir)?.into_iter().find(|(de, _)| Self::get_name_str(de) == "..") {
+            Some((dir_entry, _)) => dir_entry.inum,
+            None => return Ok(())
+        };
+        if dotdot == dir.inum {
+            return Ok(());
         }
-        else {
-            let mut newname = name.to_string();
-            if newname.len() < DIRNAME_SIZE {
-                newname.push('\0');
-            } 
-            let chars: Vec<char> = newname.chars().collect();
-            let mut array = ['\0'; DIRNAME_SIZE];
-            let mut index = 0;
-            for i in chars {
-                array[index] = i;  
-                index += 1;
-            }
-            de.name = array;
-            return Some(())
-        } 
+        if dotdot == parent.inum {
+            // `parent` is written back by the caller
+            parent.disk_node.nlink = parent.disk_node.nlink.checked_sub(1).ok_or(CustomDirFileSystemError::CorruptDirectory)?;
+            return Ok(());
+        }
+        let mut other = self.i_get(dotdot)?;
+        other.disk_node.nlink = other.disk_node.nlink.checked_sub(1).ok_or(CustomDirFileSystemError::CorruptDirectory)?;
+        other.disk_node.ctime = self.inode_fs.now();
+        self.i_put(&other)?;
+        return Ok(())
     }
 
-    fn dirlookup(&self, inode: &Self::Inode, name: &str) -> Result<(Self::Inode, u64), Self::Error> {
-        if !(inode.disk_node.ft == FType::TDir) {
+    /// Rename the entry `old` of the directory `dir` to `new`, keeping the inode it refers to and its `nlink` as they are.
+    /// The name is rewritten in place, so the entry keeps its offset and no blocks are allocated.
+    /// Errors if `old` is not an entry of `dir`, or if `new` is not a valid name or already an entry of `dir`.
+    /// The `.` and `..` entries cannot be renamed.
+    pub fn dirrename(&mut self, dir: &mut Inode, old: &str, new: &str) -> Result<(), CustomDirFileSystemError> {
+        if old == "." || old == ".." {
+            return Err(CustomDirFileSystemError::CannotUnlinkDotEntry);
+        }
+        let (target, offset) = self.dirlookup(dir, old)?;
+        let dir_entry = match Self::new_de(target.inum, new) {
+            Some(dir_entry) => dir_entry,
+            None => return Err(CustomDirFileSystemError::InvalidEntryName)
+        };
+        match self.dirlookup(dir, new) {
+            Ok(_) => return Err(CustomDirFileSystemError::InvalidEntryName),
+            Err(CustomDirFileSystemError::NoEntryFoundForName) => (),
+            Err(e) => return Err(e)
+        }
+        let superblock = self.sup_get()?;
+        let mut block = self.b_get(dir.disk_node.direct_blocks[(offset / superblock.block_size) as usize])?;
+        block.serialize_into(&dir_entry, offset % superblock.block_size)?;
+        self.b_put(&block)?;
+        // the entry is now found under a different hash
+        self.hash_index_remove(dir, old, offset)?;
+        self.hash_index_insert(dir, new, offset)?;
+        self.inode_fs.touch(dir);
+        self.i_put(dir)?;
+        return Ok(())
+    }
+
+    /// Start filling the empty directory `dir` with a `DirBuilder`, which is much faster than calling `dirlink` for every entry
+    pub fn dir_builder(&mut self, dir: &Inode) -> Result<DirBuilder<'_>, CustomDirFileSystemError> {
+        // check the inode on disk, `dir` might be out of date
+        let dir = self.i_get(dir.inum)?;
+        if !(dir.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        if !(dir.disk_node.size == 0) {
+            return Err(CustomDirFileSystemError::DirectoryNotEmpty);
+        }
+        return Ok(DirBuilder { fs: self, dir, names: HashSet::new(), links: Vec::new(), current: None, used_slots: 0, nb_blocks: 0, nb_entries: 0, allocated: Vec::new(), committed: false })
+    }
+
+    /// Replace all entries of the directory `dir` by `entries`, given as pairs of a name and an inode number.
+    /// The new entries are packed densely from the start of the directory, blocks that are no longer needed are freed,
+    /// and the `nlink` fields of the inodes that gain or lose entries are updated (self-references are not counted, as in `dirlink`).
+    /// Inodes that lose their last link are freed like `dirunlink` does, which is refused for directories that are not empty.
+    /// If any name is invalid or occurs twice, any entry refers to a free inode, or a link count would go out of range, nothing is changed.
+    pub fn dir_replace_entries(&mut self, dir: &mut Inode, entries: &[(&str, u64)]) -> Result<(), CustomDirFileSystemError> {
+        if !(dir.disk_node.ft == FType::TDir) {
             return Err(CustomDirFileSystemError::InodeWrongType);
         }
         let superblock = self.sup_get()?;
-        let file_blocks = inode.disk_node.direct_blocks;
-        let nb_selected_blocks = (inode.disk_node.size as f64/superblock.block_size as f64).ceil(); 
-        for index in 0..(nb_selected_blocks as u64) {
-            let element = file_blocks[index as usize];
-            if !(element == 0) {
-                // b-get: read the nth block of the entire disk and return it
-                let block = self.b_get(element)?;
-                let nb_dirs = superblock.block_size/ *DIRENTRY_SIZE;
-                let mut offset = 0 ;
-                for _ in 0..(nb_dirs) {
-                    let dir_entry = block.deserialize_from::<DirEntry>(offset)?;
-                    // check if this is not an empty entry
-                    if dir_entry.inum != 0 {
-                        // check if the names match
-                        if Self::get_name_str(&dir_entry) == *name {
-                            let inode = self.i_get(dir_entry.inum)?;
-                            return Ok((inode, superblock.block_size*index + offset))
-                        }
-                    }
-                    offset += *DIRENTRY_SIZE;
-                    if offset >= inode.disk_node.size {
-                        break;
-                    }
-                }
+        let mut new_entries = Vec::new();
+        for (index, (name, inum)) in entries.iter().enumerate() {
+            let dir_entry = match Self::new_de(*inum, name) {
+                None => return Err(CustomDirFileSystemError::InvalidEntryName),
+                Some(dir_entry) => dir_entry
+            };
+            if entries[..index].iter().any(|(other, _)| other == name) {
+                return Err(CustomDirFileSystemError::InvalidEntryName);
+            }
+            if self.i_get(*inum)?.disk_node.ft == FType::TFree {
+                return Err(CustomDirFileSystemError::DirectoryInodeNotInUse);
             }
+            new_entries.push(dir_entry);
+        }
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        let needed_blocks = (new_entries.len() as u64).div_ceil(nb_dirs);
+        if needed_blocks > Self::dir_block_limit(dir) {
+            return Err(CustomDirFileSystemError::InodeBlocksFull);
         }
+        let old_entries = self.dir_entries(dir)?;
+        let old_blocks = self.dir_block_count(dir)?;
 
-        return Err(CustomDirFileSystemError::NoEntryFoundForName)
-    }
+        // net change in links per inode, so no inode is ever written with a temporarily wrong count
+        let mut link_changes: Vec<(u64, i64)> = Vec::new();
+        let removed = old_entries.iter().map(|(dir_entry, _)| (dir_entry.inum, -1));
+        let added = new_entries.iter().map(|dir_entry| (dir_entry.inum, 1));
+        for (inum, change) in removed.chain(added) {
+            if inum == dir.inum {
+                continue
+            }
+            match link_changes.iter_mut().find(|(other, _)| *other == inum) {
+                Some((_, total)) => *total += change,
+                None => link_changes.push((inum, change))
+            }
+        }
+        // work out every new count before anything is written
+        let mut new_links = Vec::new();
+        for (inum, change) in link_changes {
+            if change == 0 {
+                continue
+            }
+            let inode = self.i_get(inum)?;
+            let nlink = match i16::try_from(change).ok().and_then(|change| inode.disk_node.nlink.checked_add_signed(change)) {
+                Some(nlink) => nlink,
+                None => return Err(CustomDirFileSystemError::CorruptDirectory)
+            };
+            if nlink == 0 && inode.disk_node.ft == FType::TDir && !self.dir_is_empty(&inode)? {
+                return Err(CustomDirFileSystemError::DirectoryNotEmpty);
+            }
+            new_links.push((inode, nlink));
+        }
 
-    fn dirlink(&mut self,inode: &mut Self::Inode,name: &str,inum: u64,) -> Result<u64, Self::Error> {
-        // The inode has to be a directory
-        if !(inode.disk_node.ft == FType::TDir) {
-            return Err(CustomDirFileSystemError::InodeWrongType);
+        // allocate the missing blocks first, so running out of space leaves everything as it was
+        let mut blocks = Vec::new();
+        let mut allocated = Vec::new();
+        for index in 0..needed_blocks {
+            let element = dir.disk_node.direct_blocks[index as usize];
+            if index < old_blocks && !(element == 0) {
+                blocks.push(element);
+                continue
+            }
+            match self.b_alloc() {
+                Ok(data_index) => {
+                    allocated.push(data_index);
+                    blocks.push(superblock.datastart + data_index);
+                },
+                Err(e) => {
+                    for data_index in allocated {
+                        self.b_free(data_index)?;
+                    }
+                    return Err(e)
+                }
+            }
         }
 
-        let mut corresponding_inode = self.i_get(inum)?;
-        // errors and does nothing if the inode corresponding to inum is not currently in use.
-        if corresponding_inode.disk_node.ft == FType::TFree {
-            return Err(CustomDirFileSystemError::DirectoryInodeNotInUse);
-        };
 
-        //name is invalid
-        let new_dir_entry = match Self::new_de(inum,name) {
-            None => return Err(CustomDirFileSystemError::InvalidEntryName),
-            Some(dir_entry) => dir_entry
+        for (index, block_no) in blocks.iter().enumerate() {
+            let mut block = Block::new_zero(*block_no, superblock.block_size);
+            let first = index * nb_dirs as usize;
+            for (slot, dir_entry) in new_entries.iter().skip(first).take(nb_dirs as usize).enumerate() {
+                block.serialize_into(dir_entry, slot as u64 * *DIRENTRY_SIZE)?;
+            }
+            self.b_put(&block)?;
+            dir.disk_node.direct_blocks[index] = *block_no;
+        }
+        for index in needed_blocks..old_blocks {
+            let element = dir.disk_node.direct_blocks[index as usize];
+            if !(element == 0) {
+                self.b_free(element - superblock.datastart)?;
+                dir.disk_node.direct_blocks[index as usize] = 0;
+            }
+        }
+        dir.disk_node.size = match new_entries.len() as u64 {
+            0 => 0,
+            n => superblock.block_size * ((n - 1) / nb_dirs) + ((n - 1) % nb_dirs + 1) * *DIRENTRY_SIZE
         };
-
-        // Name is already an entry inside inode.
-        match self.dirlookup(inode, name) {
-            // the name already exists, so return error
-            Ok(_)=> return Err(CustomDirFileSystemError::InvalidEntryName),
-            Err(_) => ()
+        dir.disk_node.entry_count = new_entries.len() as u32;
+        self.inode_fs.touch(dir);
+        self.i_put(dir)?;
+        if dir.disk_node.flags & INODE_FLAG_HASHED != 0 {
+            self.hash_index_rebuild(dir)?;
         }
 
-        let superblock = self.sup_get()?;
-        let file_blocks = inode.disk_node.direct_blocks;
-        let nb_selected_blocks = (inode.disk_node.size as f64/superblock.block_size as f64).ceil(); 
-        let nb_dirs = superblock.block_size/ *DIRENTRY_SIZE;
-        for index in 0..(nb_selected_blocks as u64) {
-            let element = file_blocks[index as usize];
-            if !(element == 0) {
-                // b-get: read the nth block of the entire disk and return it
-                let mut block = self.b_get(element)?;
-                let mut offset = 0 ;
-                for _ in 0..(nb_dirs) {
-                    let dir_entry = block.deserialize_from::<DirEntry>(offset)?;
-                    // check if we have an empty entry
-                    // we might be over the size of the inode
-                    // but there might still place in this block 
-                    // to add a dir entry
-                    // here we need to do offset + DIRENTRY SIZE
-                    // because this should be taken inot account aswell
-                    if dir_entry.inum == 0 || ((superblock.block_size*index) + offset + *DIRENTRY_SIZE) >= inode.disk_node.size {
-                        if (superblock.block_size*index + offset + *DIRENTRY_SIZE) >= inode.disk_node.size {
-                            inode.disk_node.size = superblock.block_size*index + offset + *DIRENTRY_SIZE;
-                            self.i_put(&inode)?;
-                        }
-                        if dir_entry.inum == 0 {
-                            block.serialize_into(&new_dir_entry, offset)?;  
-                            // write block back to disk
-                            self.b_put(&block)?;
-                            // if inum and inode's number are equal, then nothing happens
-                            if !(inode.inum == inum) {
-                                corresponding_inode.disk_node.nlink += 1;
-                                self.i_put(&corresponding_inode)?;      
-                            } 
-                            return Ok(superblock.block_size*index + offset);
-                        }
-                    }
-                    // keeps the last starting offset
-                    offset +=  *DIRENTRY_SIZE;           
+        for (mut inode, nlink) in new_links {
+            inode.disk_node.nlink = nlink;
+            inode.disk_node.ctime = self.inode_fs.now();
+            self.i_put(&inode)?;
+            if nlink == 0 {
+                if inode.disk_node.ft == FType::TDir {
+                    self.drop_parent_link(dir, &inode)?;
                 }
+                self.i_free(inode.inum)?;
             }
         }
+        // `drop_parent_link` may have changed the link count of `dir` itself
+        self.i_put(dir)?;
+        return Ok(())
+    }
 
-        // inode has no room for extra block
-        if nb_selected_blocks == inode.disk_node.direct_blocks.len() as f64 {
-            return Err(CustomDirFileSystemError::InodeBlocksFull);
+    /// Add the `.` and `..` entries to the directory `dir_inum` where they are missing, referring to the directory itself and to `parent_inum`.
+    /// This upgrades directories that were created without these entries. Linking `..` increases the `nlink` of the parent,
+    /// as `dirlink` does for any entry; existing entries are left untouched.
+    pub fn add_dot_entries(&mut self, dir_inum: u64, parent_inum: u64) -> Result<(), CustomDirFileSystemError> {
+        let mut dir = self.i_get(dir_inum)?;
+        for (name, inum) in [(".", dir_inum), ("..", parent_inum)].iter() {
+            match self.dirlookup(&dir, name) {
+                Ok(_) => (),
+                Err(CustomDirFileSystemError::NoEntryFoundForName) => {
+                    self.dirlink(&mut dir, name, *inum)?;
+                },
+                Err(e) => return Err(e)
+            }
         }
+        return Ok(())
+    }
 
-        // if we did not exit the function
-        // allocate a new block
-        // Returns the index (within the data region) of the newly allocated block.
-        let new_block_index = superblock.datastart + self.b_alloc()?;
-        let mut new_block = self.b_get(new_block_index)?;
-        // we start at the beginning of the block
-        new_block.serialize_into(&new_dir_entry, 0)?;  
-        // increase the size
-        inode.disk_node.size = (superblock.block_size * (nb_selected_blocks as u64)) + *DIRENTRY_SIZE;
-        // find zero element and change it with index
-        inode.disk_node.direct_blocks[nb_selected_blocks as usize] = new_block_index;
+    /// List the entries of the directory `dir` together with the metadata of the inodes they refer to, in the order of the entries.
+    /// The inodes are fetched per inode block, so every inode block is read at most once, however many entries refer into it.
+    pub fn stat_entries(&self, dir: &Inode) -> Result<Vec<InodeStat>, CustomDirFileSystemError> {
+        let entries = self.dir_entries(dir)?;
+        let inums: Vec<u64> = entries.iter().map(|(dir_entry, _)| dir_entry.inum).collect();
+        let inodes = self.inode_fs.i_get_many(&inums)?;
+        let mut stats = Vec::new();
+        for ((dir_entry, _), inode) in entries.iter().zip(inodes) {
+            stats.push(InodeStat {
+                name: Self::get_name_str(dir_entry),
+                inum: inode.inum,
+                ft: inode.disk_node.ft,
+                size: inode.disk_node.size,
+                nlink: inode.disk_node.nlink
+            });
+        }
+        return Ok(stats)
+    }
+
+    /// The root directory, i.e. the inode with number `ROOT_INUM`. Errors if that inode is not a directory.
+    pub fn root_inode(&self) -> Result<Inode, CustomDirFileSystemError> {
+        let root = self.i_get(ROOT_INUM)?;
+        if !(root.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        return Ok(root)
+    }
+
+    /// Check whether `inode` is the root directory
+    pub fn is_root(&self, inode: &Inode) -> bool {
+        return inode.inum == ROOT_INUM;
+    }
+
+    /// Number of the parent directory of the directory `dir`, as found in its `..` entry.
+    /// The root directory is its own parent.
+    pub fn parent_of(&self, dir: &Inode) -> Result<u64, CustomDirFileSystemError> {
+        if !(dir.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        if self.is_root(dir) {
+            return Ok(ROOT_INUM);
+        }
+        let (parent, _) = self.dirlookup(dir, "..")?;
+        return Ok(parent.inum)
+    }
+
+    /// Names of all entries of the directory `inode`, sorted
+    pub fn list_names(&self, inode: &Inode) -> Result<Vec<String>, CustomDirFileSystemError> {
+        let mut names: Vec<String> = self.dir_entries(inode)?.iter().map(|(dir_entry, _)| Self::get_name_str(dir_entry)).collect();
+        names.sort();
+        return Ok(names)
+    }
+
+    /// Check whether the directory `inode` has no entries besides `.` and `..`
+    pub fn dir_is_empty(&self, inode: &Inode) -> Result<bool, CustomDirFileSystemError> {
+        for (dir_entry, _) in self.dir_entries(inode)? {
+            let name = Self::get_name_str(&dir_entry);
+            if !(name == "." || name == "..") {
+                return Ok(false);
+            }
+        }
+        return Ok(true)
+    }
+
+    /// Return the dangling entries of the directory `inode`, i.e. the entries that refer to an inode that is free.
+    /// Every entry is returned as a pair of its name and the inode number it refers to.
+    pub fn verify_directory(&self, inode: &Inode) -> Result<Vec<(String, u64)>, CustomDirFileSystemError> {
+        let mut dangling = Vec::new();
+        for (dir_entry, _) in self.dir_entries(inode)? {
+            if self.i_get(dir_entry.inum)?.disk_node.ft == FType::TFree {
+                dangling.push((Self::get_name_str(&dir_entry), dir_entry.inum));
+            }
+        }
+        return Ok(dangling)
+    }
+
+    /// Check the whole file system for dangling entries, orphaned inodes, leaked blocks, blocks in use that are free in the bitmap
+    /// and wrong link counts, and collect everything that was found in a single report. Nothing is changed.
+    /// Link counts follow `dirlink`: entries referring to the directory they are in do not count, and the root directory has one extra link.
+    pub fn health_report(&self) -> Result<HealthReport, CustomDirFileSystemError> {
+        let mut report = HealthReport::default();
+        for problem in self.fsck()?.problems {
+            match problem {
+                FsckProblem::DanglingEntry { dir, name, inum } => report.dangling_entries.push((dir, name, inum)),
+                FsckProblem::OrphanedInode { inum } => report.orphaned_inodes.push(inum),
+                FsckProblem::LeakedBlock { block } => report.leaked_blocks.push(block),
+                FsckProblem::UnallocatedBlockInUse { block, .. } => report.unallocated_blocks_in_use.push(block),
+                FsckProblem::LinkCountMismatch { inum, nlink, expected } => report.link_count_mismatches.push((inum, nlink, expected)),
+                // not a kind of problem the health report keeps track of
+                FsckProblem::BlockReferencedTwice { .. } => ()
+            }
+        }
+        return Ok(report)
+    }
+
+    /// Check the consistency of the whole file system, without changing anything, and collect every problem found:
+    /// - every data block belonging to an inode in use is allocated in the bitmap, and belongs to no other inode
+    /// - every allocated data block belongs to some inode
+    /// - every directory entry refers to an inode in use
+    /// - the `nlink` of every inode in use equals the number of entries referring to it (plus one for the root directory)
+    ///
+    /// The blocks of an inode include its indirect block, the blocks that one points to, and the hash index of a hashed directory.
+    pub fn fsck(&self) -> Result<FsckReport, CustomDirFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut report = FsckReport::default();
+        let mut references = vec![0 as u64; superblock.ninodes as usize];
+        // the inode each data block belongs to, if any
+        let mut owners: Vec<Option<u64>> = vec![None; superblock.ndatablocks as usize];
+        let mut inodes = Vec::new();
+        for inode in self.inode_fs.iter_inodes() {
+            let inode = inode?;
+            if inode.disk_node.ft == FType::TFree {
+                continue
+            }
+            if inode.disk_node.ft == FType::TDir {
+                for (name, inum) in self.verify_directory(&inode)? {
+                    report.problems.push(FsckProblem::DanglingEntry { dir: inode.inum, name, inum });
+                }
+                for (dir_entry, _) in self.dir_entries(&inode)? {
+                    if !(dir_entry.inum == inode.inum) && dir_entry.inum < superblock.ninodes {
+                        references[dir_entry.inum as usize] += 1;
+                    }
+                }
+            }
+            let mut blocks: Vec<u64> = inode.disk_node.direct_blocks[..self.inode_fs.owned_block_slots(&inode) as usize].iter().copied().filter(|block| *block != 0).collect();
+            let indirect_block = inode.disk_node.indirect_block;
+            if superblock.datastart <= indirect_block && indirect_block < superblock.datastart + superblock.ndatablocks {
+                blocks.push(indirect_block);
+                blocks.extend(self.inode_fs.indirect_addresses(&inode)?.into_iter().filter(|block| *block != 0));
+            }
+            if inode.disk_node.flags & INODE_FLAG_HASHED != 0 {
+                blocks.push(inode.disk_node.direct_blocks[HASH_INDEX_SLOT]);
+            }
+            for block in blocks {
+                // pointers outside the data region are reported by `validate_no_metadata_overlap`
+                if !(superblock.datastart <= block && block < superblock.datastart + superblock.ndatablocks) {
+                    continue
+                }
+                let index = block - superblock.datastart;
+                match owners[index as usize] {
+                    Some(first) => report.problems.push(FsckProblem::BlockReferencedTwice { block: index, first, second: inode.inum }),
+                    None => owners[index as usize] = Some(inode.inum)
+                }
+            }
+            inodes.push(inode);
+        }
+        for inode in inodes {
+            let mut expected = references[inode.inum as usize];
+            if inode.inum == ROOT_INUM {
+                expected += 1;
+            } else if expected == 0 {
+                report.problems.push(FsckProblem::OrphanedInode { inum: inode.inum });
+            }
+            if !(inode.disk_node.nlink as u64 == expected) {
+                report.problems.push(FsckProblem::LinkCountMismatch { inum: inode.inum, nlink: inode.disk_node.nlink, expected });
+            }
+        }
+        for index in self.inode_fs.reserved_data_blocks()..superblock.ndatablocks {
+            let allocated = self.inode_fs.b_is_allocated(index)?;
+            match owners[index as usize] {
+                Some(inum) if !allocated => report.problems.push(FsckProblem::UnallocatedBlockInUse { block: index, inum }),
+                None if allocated => report.problems.push(FsckProblem::LeakedBlock { block: index }),
+                _ => ()
+            }
+        }
+        return Ok(report)
+    }
+
+    /// Export the entries in use of the directory `inode` as a JSON array,
+    /// containing an object with the fields `name`, `inum`, `ftype` and `size` for every entry.
+    /// The JSON is put together by hand rather than with `serde_json`, so that the crate does not need another dependency;
+    /// names are escaped by `json_string`, which the tests check by parsing the output back.
+    pub fn dir_to_json(&self, inode: &Inode) -> Result<String, CustomDirFileSystemError> {
+        let mut objects = Vec::new();
+        for (dir_entry, _) in self.dir_entries(inode)? {
+            let entry_inode = self.i_get(dir_entry.inum)?;
+            objects.push(format!(
+                "{{\"name\":{},\"inum\":{},\"ftype\":{},\"size\":{}}}",
+                json_string(&Self::get_name_str(&dir_entry)),
+                dir_entry.inum,
+                json_string(&format!("{:?}", entry_inode.disk_node.ft)),
+                entry_inode.disk_node.size
+            ));
+        }
+        return Ok(format!("[{}]", objects.join(",")))
+    }
+
+    /// Number of entries in use in the directory `inode`, as kept in its metadata.
+    /// Unlike scanning the directory, this does not need to read any of its blocks.
+    pub fn dir_count_cached(&self, inode: &Inode) -> u64 {
+        return inode.disk_node.entry_count as u64
+    }
+
+    /// Recompute the entry count of every directory on disk, and write it back where it is out of date.
+    /// Images written before the entry count was kept in the metadata always store 0.
+    fn recount_directories(&mut self) -> Result<(), CustomDirFileSystemError> {
+        let directories = self.inode_fs.iter_inodes_of_type(FType::TDir).collect::<Result<Vec<Inode>, _>>()?;
+        for mut inode in directories {
+            let count = self.dir_entries(&inode)?.len() as u32;
+            if inode.disk_node.entry_count != count {
+                inode.disk_node.entry_count = count;
+                self.i_put(&inode)?;
+            }
+        }
+        return Ok(())
+    }
+
+    /// Number of blocks covered by the size of the directory `inode`.
+    /// Errors if the size implies more blocks than the directory can point to, which means the directory is corrupt.
+    fn dir_block_count(&self, inode: &Inode) -> Result<u64, CustomDirFileSystemError> {
+        let superblock = self.sup_get()?;
+        let nb_blocks = inode.disk_node.size.div_ceil(superblock.block_size);
+        if nb_blocks > Self::dir_block_limit(inode) {
+            return Err(CustomDirFileSystemError::CorruptDirectory);
+        }
+        return Ok(nb_blocks)
+    }
+
+    /// Number of blocks the directory `inode` can hold entries in.
+    /// A hashed directory uses its last block pointer for its index instead.
+    fn dir_block_limit(inode: &Inode) -> u64 {
+        if inode.disk_node.flags & INODE_FLAG_HASHED != 0 {
+            return HASH_INDEX_SLOT as u64
+        }
+        return inode.disk_node.direct_blocks.len() as u64
+    }
+
+    /// Turn the directory `dir` into a hashed directory, whose entries are found through an index instead of by scanning all of them.
+    /// The index is a hash table of `u16` slots in a single extra block, referring to entries by their number within the directory.
+    /// It lives in the last block pointer of the directory, so the directory must not use that block for entries yet,
+    /// and it has to be large enough to refer to every entry the remaining blocks can hold. Enabling it twice has no effect.
+    pub fn dir_enable_hash_index(&mut self, dir: &mut Inode) -> Result<(), CustomDirFileSystemError> {
+        if !(dir.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        if dir.disk_node.flags & INODE_FLAG_HASHED != 0 {
+            return Ok(())
+        }
+        let superblock = self.sup_get()?;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        let nb_slots = superblock.block_size / HASH_SLOT_SIZE;
+        if self.dir_block_count(dir)? > HASH_INDEX_SLOT as u64 || HASH_INDEX_SLOT as u64 * nb_dirs >= nb_slots.min(HASH_TOMBSTONE as u64) {
+            return Err(CustomDirFileSystemError::HashIndexUnavailable);
+        }
+        let index_block = superblock.datastart + self.b_alloc()?;
+        dir.disk_node.direct_blocks[HASH_INDEX_SLOT] = index_block;
+        dir.disk_node.flags |= INODE_FLAG_HASHED;
+        self.hash_index_rebuild(dir)?;
+        self.i_put(dir)?;
+        return Ok(())
+    }
+
+    /// Write the hash index of the hashed directory `dir` from scratch, from the entries it currently holds
+    fn hash_index_rebuild(&mut self, dir: &Inode) -> Result<(), CustomDirFileSystemError> {
+        let superblock = self.sup_get()?;
+        let mut index = Block::new_zero(dir.disk_node.direct_blocks[HASH_INDEX_SLOT], superblock.block_size);
+        for (dir_entry, offset) in self.dir_entries(dir)? {
+            let slot = self.hash_free_slot(&index, &Self::get_name_str(&dir_entry))?;
+            index.serialize_into(&Self::entry_number(&superblock, offset), slot * HASH_SLOT_SIZE)?;
+        }
+        self.b_put(&index)?;
+        return Ok(())
+    }
+
+    /// Add the entry `name`, at byte offset `offset` in the directory, to the hash index of `dir`, if it has one
+    fn hash_index_insert(&mut self, dir: &Inode, name: &str, offset: u64) -> Result<(), CustomDirFileSystemError> {
+        if dir.disk_node.flags & INODE_FLAG_HASHED == 0 {
+            return Ok(())
+        }
+        let superblock = self.sup_get()?;
+        let mut index = self.b_get(dir.disk_node.direct_blocks[HASH_INDEX_SLOT])?;
+        let slot = self.hash_free_slot(&index, name)?;
+        index.serialize_into(&Self::entry_number(&superblock, offset), slot * HASH_SLOT_SIZE)?;
+        self.b_put(&index)?;
+        return Ok(())
+    }
+
+    /// Remove the entry at byte offset `offset` in the directory from the hash index of `dir`, if it has one
+    fn hash_index_remove(&mut self, dir: &Inode, name: &str, offset: u64) -> Result<(), CustomDirFileSystemError> {
+        if dir.disk_node.flags & INODE_FLAG_HASHED == 0 {
+            return Ok(())
+        }
+        let superblock = self.sup_get()?;
+        let mut index = self.b_get(dir.disk_node.direct_blocks[HASH_INDEX_SLOT])?;
+        let nb_slots = superblock.block_size / HASH_SLOT_SIZE;
+        let wanted = Self::entry_number(&superblock, offset);
+        let start = name_hash(name) % nb_slots;
+        for probe in 0..nb_slots {
+            let slot = (start + probe) % nb_slots;
+            let value = index.deserialize_from::<u16>(slot * HASH_SLOT_SIZE)?;
+            if value == HASH_EMPTY {
+                break
+            }
+            if value == wanted {
+                index.serialize_into(&HASH_TOMBSTONE, slot * HASH_SLOT_SIZE)?;
+                self.b_put(&index)?;
+                return Ok(())
+            }
+        }
+        return Err(CustomDirFileSystemError::CorruptDirectory)
+    }
+
+    /// Find the entry `name` in the hashed directory `dir` through its index.
+    /// Returns the entry and its byte offset in the directory, if it exists.
+    fn hash_index_find(&self, dir: &Inode, name: &str) -> Result<Option<(DirEntry, u64)>, CustomDirFileSystemError> {
+        let superblock = self.sup_get()?;
+        let index = self.b_get(dir.disk_node.direct_blocks[HASH_INDEX_SLOT])?;
+        let nb_slots = superblock.block_size / HASH_SLOT_SIZE;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        let start = name_hash(name) % nb_slots;
+        // probes often land in the same directory block, so keep the last one around
+        let mut current: Option<Block> = None;
+        for probe in 0..nb_slots {
+            let slot = (start + probe) % nb_slots;
+            let value = index.deserialize_from::<u16>(slot * HASH_SLOT_SIZE)?;
+            if value == HASH_EMPTY {
+                break
+            }
+            if value == HASH_TOMBSTONE {
+                continue
+            }
+            let number = (value - 1) as u64;
+            let block_no = dir.disk_node.direct_blocks[(number / nb_dirs) as usize];
+            let block = match current {
+                Some(block) if block.block_no == block_no => block,
+                _ => self.b_get(block_no)?
+            };
+            let dir_entry = block.deserialize_from::<DirEntry>((number % nb_dirs) * *DIRENTRY_SIZE)?;
+            current = Some(block);
+            if dir_entry.inum != 0 && Self::get_name_str(&dir_entry) == name {
+                return Ok(Some((dir_entry, (number / nb_dirs) * superblock.block_size + (number % nb_dirs) * *DIRENTRY_SIZE)))
+            }
+        }
+        return Ok(None)
+    }
+
+    /// First slot of the hash index `index` that `name` can be stored in
+    fn hash_free_slot(&self, index: &Block, name: &str) -> Result<u64, CustomDirFileSystemError> {
+        let nb_slots = index.len() / HASH_SLOT_SIZE;
+        let start = name_hash(name) % nb_slots;
+        for probe in 0..nb_slots {
+            let slot = (start + probe) % nb_slots;
+            let value = index.deserialize_from::<u16>(slot * HASH_SLOT_SIZE)?;
+            if value == HASH_EMPTY || value == HASH_TOMBSTONE {
+                return Ok(slot)
+            }
+        }
+        // cannot happen, the index has more slots than the directory has room for entries
+        return Err(CustomDirFileSystemError::CorruptDirectory)
+    }
+
+    /// Value stored in a hash index for the entry at byte offset `offset` in a directory: its entry number, plus one
+    fn entry_number(superblock: &SuperBlock, offset: u64) -> u16 {
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        let number = (offset / superblock.block_size) * nb_dirs + (offset % superblock.block_size) / *DIRENTRY_SIZE;
+        return (number + 1) as u16
+    }
+
+    /// Scan the directory `inode` and return all entries in use, together with their offset in the directory.
+    /// Only entries that start within the size of the directory are taken into account.
+    fn dir_entries(&self, inode: &Inode) -> Result<Vec<(DirEntry, u64)>, CustomDirFileSystemError> {
+        if !(inode.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        let superblock = self.sup_get()?;
+        let size = inode.disk_node.size;
+        let nb_selected_blocks = self.dir_block_count(inode)?;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        let mut entries = Vec::new();
+        for index in 0..nb_selected_blocks {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if element == 0 {
+                continue
+            }
+            let block = self.b_get(element)?;
+            for slot in 0..nb_dirs {
+                let offset = superblock.block_size * index + slot * *DIRENTRY_SIZE;
+                if offset >= size {
+                    break
+                }
+                let dir_entry = block.deserialize_from::<DirEntry>(slot * *DIRENTRY_SIZE)?;
+                if dir_entry.inum != 0 {
+                    entries.push((dir_entry, offset));
+                }
+            }
+        }
+        return Ok(entries)
+    }
+
+    /// Byte offset of the first unused entry slot within the size of the directory `inode`.
+    /// Returns `None` if all slots up to the size are in use, in which case `dirlink` has to grow the directory.
+    pub fn dir_next_free_offset(&self, inode: &Inode) -> Result<Option<u64>, CustomDirFileSystemError> {
+        if !(inode.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        let superblock = self.sup_get()?;
+        let size = inode.disk_node.size;
+        let nb_selected_blocks = self.dir_block_count(inode)?;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        for index in 0..nb_selected_blocks {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if element == 0 {
+                continue
+            }
+            let block = self.b_get(element)?;
+            for slot in 0..nb_dirs {
+                let offset = superblock.block_size * index + slot * *DIRENTRY_SIZE;
+                if offset >= size {
+                    break
+                }
+                let dir_entry = block.deserialize_from::<DirEntry>(slot * *DIRENTRY_SIZE)?;
+                if dir_entry.inum == 0 {
+                    return Ok(Some(offset));
+                }
+            }
+        }
+        return Ok(None)
+    }
+
+    /// Set the order in which both `i_alloc` and `b_alloc` scan for a free inode or data block
+    pub fn set_alloc_mode(&mut self, mode: AllocMode) {
+        self.inode_fs.set_alloc_mode(mode);
+    }
+
+    /// Read every allocated data block and return the numbers (on disk) of the blocks that cannot be read back intact.
+    pub fn scrub(&self) -> Result<Vec<u64>, CustomDirFileSystemError> {
+        let failed = self.inode_fs.scrub()?;
+        return Ok(failed)
+    }
+
+    /// Replace the source of the current time used for the timestamps of inodes, see `CustomInodeFileSystem::set_clock`
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.inode_fs.set_clock(clock);
+    }
+
+    /// Install a callback that is invoked on every block read and write, or remove it again by passing `None`
+    pub fn set_io_hook(&mut self, hook: Option<IoHook>) {
+        self.inode_fs.set_io_hook(hook);
+    }
+
+    /// Checksum (CRC-64) over the contents of every block on the device, in order
+    pub fn fs_checksum(&self) -> Result<u64, CustomDirFileSystemError> {
+        let checksum = self.inode_fs.fs_checksum()?;
+        return Ok(checksum)
+    }
+
+    /// Fetch the inode with number `inum` for editing. The returned guard writes it back when it goes out of scope.
+    pub fn i_edit(&mut self, inum: u64) -> Result<InodeGuard<'_>, CustomDirFileSystemError> {
+        let guard = self.inode_fs.i_edit(inum)?;
+        return Ok(guard);
+    }
+
+    /// Check that every block pointer of every inode in use lies in the data region
+    pub fn validate_no_metadata_overlap(&self) -> Result<(), CustomDirFileSystemError> {
+        self.inode_fs.validate_no_metadata_overlap()?;
+        return Ok(());
+    }
+
+    /// Read the block with number `i`, sharing a single cached copy between callers, see `CustomBlockFileSystem::b_get_ref`
+    pub fn b_get_ref(&self, i: u64) -> Result<Arc<Block>, CustomDirFileSystemError> {
+        let block = self.inode_fs.b_get_ref(i)?;
+        return Ok(block);
+    }
+
+    /// Number of free data blocks following the highest allocated one, without changing the file system
+    pub fn trim_trailing_free(&self) -> Result<u64, CustomDirFileSystemError> {
+        let free = self.inode_fs.trim_trailing_free()?;
+        return Ok(free);
+    }
+
+    /// Number of block reads served from the block cache and from the device
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.inode_fs.cache_stats();
+    }
+
+    /// Change the number of blocks the block cache holds. 0 disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.inode_fs.set_cache_capacity(capacity);
+    }
+
+    /// Number of calls to the block and inode operations, see `CustomInodeFileSystem::stats`
+    pub fn stats(&self) -> OpStats {
+        return self.inode_fs.stats();
+    }
+
+    /// Start counting the calls to the block and inode operations from 0 again
+    pub fn reset_stats(&mut self) {
+        self.inode_fs.reset_stats();
+    }
+
+    /// Number of free inodes, not counting inode 0 as it is never handed out
+    pub fn count_free_inodes(&self) -> Result<u64, CustomDirFileSystemError> {
+        let free = self.inode_fs.count_free_inodes()?;
+        return Ok(free)
+    }
+
+    /// Number of data blocks that are free according to the bitmap
+    pub fn count_free_blocks(&self) -> Result<u64, CustomDirFileSystemError> {
+        let free = self.inode_fs.count_free_blocks()?;
+        return Ok(free)
+    }
+
+    /// Mount the file system on `dev` like `mountfs`, but read-only, see `CustomBlockFileSystem::mountfs_ro`.
+    /// The entry counts of the directories are not recounted, as they cannot be written back.
+    pub fn mountfs_ro(dev: Device) -> Result<Self, CustomDirFileSystemError> {
+        let inode_fs = CustomInodeFileSystem::mountfs_ro(dev)?;
+        return Ok(CustomDirFileSystem::new(inode_fs));
+    }
+
+    /// Whether this file system was mounted read-only, through `mountfs_ro`
+    pub fn is_readonly(&self) -> bool {
+        return self.inode_fs.is_readonly();
+    }
+
+    /// Write back any state that is only kept in memory to the device, see `CustomBlockFileSystem::flush`
+    pub fn flush(&mut self) -> Result<(), CustomDirFileSystemError> {
+        self.inode_fs.flush()?;
+        return Ok(())
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+/// Reasons why the name of a directory entry is considered corrupt
+pub enum NameError {
+    #[error("The name contains characters after its terminating NUL")]
+    /// The name contains non-NUL characters after its terminating NUL
+    InteriorNul,
+    #[error("The name contains non-printable characters")]
+    /// The name contains non-printable characters
+    NonPrintable
+}
+
+/// Quote `s` as a JSON string, escaping quotes, backslashes and control characters
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    return quoted
+}
+
+#[derive(Error, Debug)]
+/// Custom type for errors in CustomDirFileSystem
+pub enum CustomDirFileSystemError {
+    /// An error occured in the inode layer
+    #[error("InodeFileSystemError")]
+    GivenError(#[from] b_inode_support::CustomInodeFileSystemError),
+    #[error("API error")]
+    /// The input provided to some method in the controller layer was invalid
+    APIError(#[from] error_given::APIError),
+    #[error("No directory entry was found for the provided name")]
+    /// The provided name did not correspond to a directory entry
+    NoEntryFoundForName,
+    #[error("The provided inode is not of directory type")]
+    /// The provided inode to search for a directory is not of the right type
+    InodeWrongType,
+    #[error("The provided name is invalid for a directory entry")]
+    /// The provided name is invalid for a directory entry
+    InvalidEntryName,
+    #[error("Inode corresponding to inum is not currently in use")]
+    /// Inode corresponding to inum is not currently in use.
+    DirectoryInodeNotInUse,
+    #[error("Inode has no room for extra block")]
+    /// Inode has no room for extra block
+    InodeBlocksFull,
+    #[error("The size of the directory exceeds what its blocks can hold")]
+    /// The size of the directory implies more blocks than it can point to
+    CorruptDirectory,
+    #[error("The directory cannot be given a hash index")]
+    /// Thrown when a hash index is requested for a directory that already uses all its blocks for entries,
+    /// or when blocks are too small for the index to refer to every entry
+    HashIndexUnavailable,
+    #[error("The directory already has entries")]
+    /// Thrown when an operation that fills a directory from scratch is used on a directory that is not empty
+    DirectoryNotEmpty,
+    #[error("The . and .. entries cannot be unlinked")]
+    /// Thrown when trying to remove the `.` or `..` entry of a directory
+    CannotUnlinkDotEntry
+
+}
+
+impl CustomDirFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomDirFileSystemError::GivenError(e) => e.kind(),
+            CustomDirFileSystemError::APIError(e) => a_block_support::api_error_kind(e),
+            CustomDirFileSystemError::NoEntryFoundForName | CustomDirFileSystemError::DirectoryInodeNotInUse => ErrorKind::NotFound,
+            CustomDirFileSystemError::InodeWrongType => ErrorKind::NotADirectory,
+            CustomDirFileSystemError::InvalidEntryName => ErrorKind::InvalidFilename,
+            CustomDirFileSystemError::InodeBlocksFull => ErrorKind::StorageFull,
+            CustomDirFileSystemError::CorruptDirectory => ErrorKind::InvalidData,
+            CustomDirFileSystemError::HashIndexUnavailable => ErrorKind::Unsupported,
+            CustomDirFileSystemError::DirectoryNotEmpty => ErrorKind::DirectoryNotEmpty,
+            CustomDirFileSystemError::CannotUnlinkDotEntry => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl From<CustomDirFileSystemError> for io::Error {
+    fn from(e: CustomDirFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
+}
+
+impl FileSysSupport for CustomDirFileSystem {
+    type Error = CustomDirFileSystemError;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return CustomInodeFileSystem::sb_valid(sb);
+    }
+    fn mkfs<P: AsRef<std::path::Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        let mut inode_fs = CustomInodeFileSystem::mkfs(path, sb)?;
+        // get the first inode and change it's nlink attribute
+        let mut root_inode = inode_fs.i_get(ROOT_INUM)?;
+        root_inode.disk_node.nlink = 1;
+        // Change type
+        root_inode.disk_node.ft = FType::TDir;
+        inode_fs.i_put(&root_inode)?;
+        return Ok(CustomDirFileSystem::new(inode_fs))
+    }
+
+    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+        let inode_fs = CustomInodeFileSystem::mountfs(dev)?;
+        let mut dir_fs = CustomDirFileSystem::new(inode_fs);
+        dir_fs.recount_directories()?;
+        return Ok(dir_fs);
+    }
+
+    fn unmountfs(self) -> Device {
+        return self.inode_fs.unmountfs();
+    }
+}
+
+
+impl BlockSupport for CustomDirFileSystem {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        let block = self.inode_fs.b_get(i)?;
+        return Ok(block);      
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        let result = self.inode_fs.b_put(b)?;
+        return Ok(result);
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let res = self.inode_fs.b_free(i)?;
+        return Ok(res)       
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.inode_fs.b_zero(i)?;
+        return Ok(result);
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        let index = self.inode_fs.b_alloc()?;
+        return Ok(index);
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        let superblock = self.inode_fs.sup_get()?;
+        return Ok(superblock);
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        let result = self.inode_fs.sup_put(sup)?;
+        return Ok(result);
+    }
+}
+
+impl InodeSupport for CustomDirFileSystem {
+    type Inode = Inode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        let inode = self.inode_fs.i_get(i)?;
+        return Ok(inode);
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        let result = self.inode_fs.i_put(ino)?;
+        return Ok(result);
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.inode_fs.i_free(i)?;
+        return Ok(result);
+    }
+
+    fn i_alloc(&mut self, ft: cplfs_api::types::FType) -> Result<u64, Self::Error> {
+        let i = self.inode_fs.i_alloc(ft)?;
+        return Ok(i);
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        let result = self.inode_fs.i_trunc(inode)?;
+        return Ok(result);
+    }
+}
+
+impl DirectorySupport for CustomDirFileSystem {
+    fn new_de(inum: u64, name: &str) -> Option<DirEntry> {
+        let mut dir_entry = DirEntry::default();
+        dir_entry.inum = inum;
+        match Self::set_name_str(&mut dir_entry, name) {
+            Some(_) => return Some(dir_entry),
+            None => return None
+        }
+    }
+
+    fn get_name_str(de: &DirEntry) -> String {
+        match Self::try_get_name_str(de) {
+            Ok(string) => return string,
+            // lossy fallback for corrupt names: everything up to the first NUL
+            Err(_) => return de.name.iter().take_while(|c| **c != '\0').collect()
+        }
+    }
+
+    // Names of up to DIRNAME_SIZE characters are stored. Their length is counted in chars, as that is how they are stored,
+    // and a name that fills the whole array goes without the terminating NUL, which `get_name_str` allows for.
+    fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()> {
+        let empty_cond = name.is_empty();
+        let point_cond = !(name == "." || name == ".." || name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')));
+        let length_cond = name.chars().count() > DIRNAME_SIZE;
+        if empty_cond || point_cond || length_cond{
+            return None
+        }
+        else {
+            // everything after the name stays NUL, so shorter names are terminated
+            let mut array = ['\0'; DIRNAME_SIZE];
+            for (index, c) in name.chars().enumerate() {
+                array[index] = c;
+            }
+            de.name = array;
+            return Some(())
+        } 
+    }
+
+    fn dirlookup(&self, inode: &Self::Inode, name: &str) -> Result<(Self::Inode, u64), Self::Error> {
+        if !(inode.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+        if inode.disk_node.flags & INODE_FLAG_HASHED != 0 {
+            match self.hash_index_find(inode, name)? {
+                Some((dir_entry, offset)) => return Ok((self.i_get(dir_entry.inum)?, offset)),
+                None => return Err(CustomDirFileSystemError::NoEntryFoundForName)
+            }
+        }
+        let superblock = self.sup_get()?;
+        let file_blocks = inode.disk_node.direct_blocks;
+        let nb_selected_blocks = self.dir_block_count(inode)? as f64;
+        for index in 0..(nb_selected_blocks as u64) {
+            let element = file_blocks[index as usize];
+            if !(element == 0) {
+                // b-get: read the nth block of the entire disk and return it
+                let block = self.b_get(element)?;
+                let nb_dirs = superblock.block_size/ *DIRENTRY_SIZE;
+                let mut offset = 0 ;
+                for _ in 0..(nb_dirs) {
+                    let dir_entry = block.deserialize_from::<DirEntry>(offset)?;
+                    // check if this is not an empty entry
+                    if dir_entry.inum != 0 {
+                        // check if the names match
+                        if Self::get_name_str(&dir_entry) == *name {
+                            let inode = self.i_get(dir_entry.inum)?;
+                            return Ok((inode, superblock.block_size*index + offset))
+                        }
+                    }
+                    offset += *DIRENTRY_SIZE;
+                    if offset >= inode.disk_node.size {
+                        break;
+                    }
+                }
+            }
+        }
+
+        return Err(CustomDirFileSystemError::NoEntryFoundForName)
+    }
+
+    fn dirlink(&mut self,inode: &mut Self::Inode,name: &str,inum: u64,) -> Result<u64, Self::Error> {
+        // refuse before `inode` gets touched
+        self.inode_fs.check_writable()?;
+        // The inode has to be a directory
+        if !(inode.disk_node.ft == FType::TDir) {
+            return Err(CustomDirFileSystemError::InodeWrongType);
+        }
+
+        let mut corresponding_inode = self.i_get(inum)?;
+        // errors and does nothing if the inode corresponding to inum is not currently in use.
+        if corresponding_inode.disk_node.ft == FType::TFree {
+            return Err(CustomDirFileSystemError::DirectoryInodeNotInUse);
+        };
+
+        //name is invalid
+        let new_dir_entry = match Self::new_de(inum,name) {
+            None => return Err(CustomDirFileSystemError::InvalidEntryName),
+            Some(dir_entry) => dir_entry
+        };
+
+        // Name is already an entry inside inode.
+        match self.dirlookup(inode, name) {
+            // the name already exists, so return error
+            Ok(_)=> return Err(CustomDirFileSystemError::InvalidEntryName),
+            Err(_) => ()
+        }
+
+        let superblock = self.sup_get()?;
+        let nb_selected_blocks = self.dir_block_count(inode)?;
+        let nb_dirs = superblock.block_size / *DIRENTRY_SIZE;
+        // a slot freed by unlinking is reused first, the directory only grows once all slots within its size are taken
+        let offset = match self.dir_next_free_offset(inode)? {
+            Some(offset) => offset,
+            None => {
+                let last = nb_selected_blocks.saturating_sub(1);
+                let used_slots = (inode.disk_node.size - superblock.block_size * last) / *DIRENTRY_SIZE;
+                if nb_selected_blocks > 0 && used_slots < nb_dirs && !(inode.disk_node.direct_blocks[last as usize] == 0) {
+                    // room left after the last entry, in the last block
+                    superblock.block_size * last + used_slots * *DIRENTRY_SIZE
+                } else {
+                    // inode has no room for extra block
+                    if nb_selected_blocks == Self::dir_block_limit(inode) {
+                        return Err(CustomDirFileSystemError::InodeBlocksFull);
+                    }
+                    // Returns the index (within the data region) of the newly allocated block.
+                    let new_block_index = superblock.datastart + self.b_alloc()?;
+                    inode.disk_node.direct_blocks[nb_selected_blocks as usize] = new_block_index;
+                    superblock.block_size * nb_selected_blocks
+                }
+            }
+        };
+
+        let mut block = self.b_get(inode.disk_node.direct_blocks[(offset / superblock.block_size) as usize])?;
+        block.serialize_into(&new_dir_entry, offset % superblock.block_size)?;
+        if offset + *DIRENTRY_SIZE > inode.disk_node.size {
+            inode.disk_node.size = offset + *DIRENTRY_SIZE;
+        }
+        inode.disk_node.entry_count += 1;
+        self.inode_fs.touch(inode);
         // write inode back
         self.i_put(inode)?;
         // put the block back on disk
-        self.b_put(&new_block)?;
-        //corresponding_inode = self.i_get(inum)?;
+        self.b_put(&block)?;
+        // if inum and inode's number are equal, then nothing happens
         if !(inode.inum == inum) {
             corresponding_inode.disk_node.nlink += 1;
-            self.i_put(&corresponding_inode)?;      
-        } 
-        return Ok(superblock.block_size * (nb_selected_blocks as u64));       
+            corresponding_inode.disk_node.ctime = inode.disk_node.ctime;
+            self.i_put(&corresponding_inode)?;
+        }
+        self.hash_index_insert(inode, name, offset)?;
+        return Ok(offset);
+    }
+}
+
+
+
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::{path::PathBuf, sync::{Arc, Mutex}};
+    use cplfs_api::{fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport}, types::{DirEntry, DIRENTRY_SIZE, DIRNAME_SIZE, FType, InodeLike, SuperBlock}};
+
+    use super::{json_string, CustomDirFileSystem, CustomDirFileSystemError, FsckProblem, InodeStat, NameError};
+    use crate::b_inode_support::{CustomInodeFileSystemError, HASH_INDEX_SLOT};
+    use crate::a_block_support::CustomBlockFileSystemError;
+    use crate::a_block_support::{BlockIoEvent, BlockIoOp::{Get, Put}, OpStats};
+
+    fn disk_prep_path(name: &str) -> PathBuf {
+        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    }
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomDirFileSystem>();
+    }
+
+    static BLOCK_SIZE: u64 = 1000;
+    static NBLOCKS: u64 = 10;
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: BLOCK_SIZE,
+        nblocks: NBLOCKS,
+        ninodes: 8,
+        inodestart: 1,
+        ndatablocks: 5,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    #[test]
+    fn dirlookup_link_new_block() {
+        let path = disk_prep_path("lkup_link_new_block");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut i2 = <<CustomDirFileSystem as InodeSupport>::Inode as InodeLike>::new(
+            5,
+            &FType::TDir,
+            0,
+            (2.5 * (BLOCK_SIZE as f32)) as u64,
+            &[5, 6, 7], //All of these blocks are initially 0'ed -> free direntries
+        )
+        .unwrap();
+        my_fs.i_put(&i2).unwrap();
+
+        //Allocate blocks 5-6-7
+        for i in 0..4 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        //Allocate inodes 2,3,4
+        for i in 0..3 {
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i + 2);
+        }
+
+        let dir_entries_block = BLOCK_SIZE / *DIRENTRY_SIZE;
+        // 45 direntries to fill a bloc, 3 blocks were assigned
+        for i in 0..(dir_entries_block *3) {
+            let mut string = i.to_string();
+            let front = "test";
+            string.push_str(front);
+            let _res = my_fs.dirlink(&mut i2, &string, 2).unwrap();
+        }
+        // a new block should be allocated now, the dir entry should begin 
+        // at the start of the new block
+        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock", 2).unwrap(), 3000);
+        // DIRENTRY = 22
+        assert_eq!(i2.disk_node.size, 3000 + *DIRENTRY_SIZE);
+        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock2", 2).unwrap(), 3000 + *DIRENTRY_SIZE);
+        assert_eq!(i2.disk_node.size, 3000 + 2* *DIRENTRY_SIZE);
+        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock3", 2).unwrap(),  3000 + 2* *DIRENTRY_SIZE);
+        assert_eq!(i2.disk_node.size,  3000 + 3* *DIRENTRY_SIZE);
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dirlookup_link_extend_block() {
+        let path = disk_prep_path("lkup_link_extend_block");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+
+        let mut i2 = <<CustomDirFileSystem as InodeSupport>::Inode as InodeLike>::new(
+            5,
+            &FType::TDir,
+            0,
+            (2.5 * (BLOCK_SIZE as f32)) as u64,
+            &[5, 6, 7], //All of these blocks are initially 0'ed -> free direntries
+        )
+        .unwrap();
+        my_fs.i_put(&i2).unwrap();
+        //Allocate blocks 5-6-7
+        for i in 0..3 {
+            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        }
+        //Allocate inodes 2,3,4
+        for i in 0..3 {
+            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i + 2);
+        }
+
+        let nb_inodes = 2500 / *DIRENTRY_SIZE;
+        // fill up 2.5 block size
+        for i in 0..nb_inodes -1{
+            let mut string = i.to_string();
+            let front = "test";
+            string.push_str(front);
+            let _res = my_fs.dirlink(&mut i2, &string, 2).unwrap();
+        }
+
+        // can start here if size is extended
+        assert_eq!(my_fs.dirlink(&mut i2, "extendblock", 2).unwrap(), 2484);
+        // size should be extended
+        assert_eq!(i2.disk_node.size, 2506);
+        assert_eq!(my_fs.dirlink(&mut i2, "extendblock2", 2).unwrap(), 2506);
+        assert_eq!(i2.disk_node.size, 2528);
+        assert_eq!(my_fs.dirlink(&mut i2, "extendblock3", 2).unwrap(), 2528);
+        assert_eq!(i2.disk_node.size, 2550);
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    fn free_inodes(my_fs: &CustomDirFileSystem) -> u64 {
+        (1..SUPERBLOCK_GOOD.ninodes).filter(|i| my_fs.i_get(*i).unwrap().get_ft() == FType::TFree).count() as u64
+    }
+
+    #[test]
+    fn create_existing_name() {
+        let path = disk_prep_path("create_existing_name");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+
+        let inum = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlookup(&iroot, "file").unwrap().0.inum, inum);
+        assert_eq!(my_fs.i_get(inum).unwrap().get_nlink(), 1);
+
+        // a colliding create should not leave an allocated inode behind
+        let free_before = free_inodes(&my_fs);
+        assert!(my_fs.create(&mut iroot, "file", FType::TDir).is_err());
+        assert!(my_fs.create(&mut iroot, "in valid", FType::TFile).is_err());
+        assert_eq!(free_inodes(&my_fs), free_before);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn alloc_link_collision() {
+        let path = disk_prep_path("alloc_link_collision");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc_link(&mut iroot, "file", FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlookup(&iroot, "file").unwrap().0.inum, inum);
+
+        let free_before = free_inodes(&my_fs);
+        assert!(my_fs.i_alloc_link(&mut iroot, "file", FType::TFile).is_err());
+        assert_eq!(free_inodes(&my_fs), free_before);
+        // the inode that was allocated for the failed link is handed out again
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), inum + 1);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn upgrade_dot_entries() {
+        let path = disk_prep_path("upgrade_dot_entries");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        let root_links = my_fs.i_get(1).unwrap().get_nlink();
+
+        my_fs.add_dot_entries(dir, 1).unwrap();
+        let idir = my_fs.i_get(dir).unwrap();
+        assert_eq!(my_fs.dirlookup(&idir, ".").unwrap().0.inum, dir);
+        assert_eq!(my_fs.dirlookup(&idir, "..").unwrap().0.inum, 1);
+        assert_eq!(my_fs.i_get(1).unwrap().get_nlink(), root_links + 1);
+        assert_eq!(my_fs.i_get(dir).unwrap().get_nlink(), 1);
+
+        // directories that already have them are left alone
+        my_fs.add_dot_entries(dir, 1).unwrap();
+        my_fs.add_dot_entries(1, 1).unwrap();
+        assert_eq!(my_fs.i_get(dir).unwrap(), idir);
+        let iroot = my_fs.i_get(1).unwrap();
+        assert_eq!(my_fs.dirlookup(&iroot, "..").unwrap().0.inum, 1);
+        assert_eq!(iroot.get_nlink(), root_links + 1);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn oversized_directory() {
+        let path = disk_prep_path("oversized_directory");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        iroot.disk_node.size = 12 * BLOCK_SIZE + 1;
+        my_fs.i_put(&iroot).unwrap();
+
+        assert!(my_fs.dirlookup(&iroot, "file").is_err());
+        assert!(my_fs.dirlink(&mut iroot, "other", 1).is_err());
+        assert!(my_fs.dir_lookup_prefix(&iroot, "").is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn next_free_offset() {
+        let path = disk_prep_path("next_free_offset");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        assert_eq!(my_fs.dir_next_free_offset(&iroot).unwrap(), None);
+        my_fs.create(&mut iroot, "a", FType::TFile).unwrap();
+        my_fs.create(&mut iroot, "b", FType::TFile).unwrap();
+        my_fs.create(&mut iroot, "c", FType::TFile).unwrap();
+        assert_eq!(my_fs.dir_next_free_offset(&iroot).unwrap(), None);
+
+        let offset = my_fs.dirlookup(&iroot, "b").unwrap().1;
+        my_fs.dirunlink(&mut iroot, "b").unwrap();
+        assert_eq!(my_fs.dir_next_free_offset(&iroot).unwrap(), Some(offset));
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlink(&mut iroot, "d", inum).unwrap(), offset);
+        assert_eq!(my_fs.dir_next_free_offset(&iroot).unwrap(), None);
+
+        let file = my_fs.i_get(inum).unwrap();
+        assert!(my_fs.dir_next_free_offset(&file).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn root_and_parent() {
+        let path = disk_prep_path("root_and_parent");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        my_fs.add_dot_entries(dir, 1).unwrap();
+        let mut idir = my_fs.i_get(dir).unwrap();
+        let sub = my_fs.create(&mut idir, "sub", FType::TDir).unwrap();
+        let isub = my_fs.i_get(sub).unwrap();
+        // without a .. entry, the parent is unknown
+        assert!(my_fs.parent_of(&isub).is_err());
+        my_fs.add_dot_entries(sub, dir).unwrap();
+        let isub = my_fs.i_get(sub).unwrap();
+
+        assert!(my_fs.is_root(&iroot));
+        assert!(!my_fs.is_root(&idir));
+        assert_eq!(my_fs.parent_of(&isub).unwrap(), dir);
+        assert_eq!(my_fs.parent_of(&idir).unwrap(), 1);
+        assert_eq!(my_fs.parent_of(&iroot).unwrap(), 1);
+        my_fs.add_dot_entries(1, 1).unwrap();
+        assert_eq!(my_fs.parent_of(&my_fs.i_get(1).unwrap()).unwrap(), 1);
+
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        assert!(my_fs.parent_of(&my_fs.i_get(file).unwrap()).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn stat_entries_per_block() {
+        // two inodes per inode block
+        static SUPERBLOCK_SMALL: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 12,
+            ninodes: 8,
+            inodestart: 1,
+            ndatablocks: 6,
+            bmapstart: 5,
+            datastart: 6,
+        };
+        let path = disk_prep_path("stat_entries_per_block");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_SMALL).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let mut expected = Vec::new();
+        for name in ["a", "b", "c", "d", "e", "f"].iter() {
+            let ft = if name < &"d" { FType::TFile } else { FType::TDir };
+            let inum = my_fs.create(&mut iroot, name, ft).unwrap();
+            expected.push(InodeStat { name: name.to_string(), inum, ft, size: 0, nlink: 1 });
+        }
+        my_fs.dirlink(&mut iroot, "alias", expected[0].inum).unwrap();
+        expected[0].nlink = 2;
+        let alias = InodeStat { name: String::from("alias"), ..expected[0].clone() };
+        expected.push(alias);
+
+        let reads = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&reads);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == Get {
+                log.lock().unwrap().push(event.block);
+            }
+        })));
+        let stats = my_fs.stat_entries(&iroot).unwrap();
+        my_fs.set_io_hook(None);
+        assert_eq!(stats, expected);
+        // one read of the directory block, and one read of each of the inode blocks 2 to 4, holding inodes 2 to 7
+        let mut reads = reads.lock().unwrap().clone();
+        assert_eq!(reads.len(), 4);
+        reads.sort();
+        assert_eq!(reads[..3], [2, 3, 4]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn replace_entries() {
+        let path = disk_prep_path("replace_entries");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let a = my_fs.create(&mut iroot, "a", FType::TFile).unwrap();
+        let b = my_fs.create(&mut iroot, "b", FType::TFile).unwrap();
+        let c = my_fs.create(&mut iroot, "c", FType::TFile).unwrap();
+        let d = my_fs.i_alloc(FType::TFile).unwrap();
+
+        // invalid or duplicate names change nothing
+        let before = my_fs.i_get(1).unwrap();
+        assert!(my_fs.dir_replace_entries(&mut iroot, &[("b", b), ("", d)]).is_err());
+        assert!(my_fs.dir_replace_entries(&mut iroot, &[("b", b), ("d", d), ("b", c)]).is_err());
+        assert!(my_fs.dir_replace_entries(&mut iroot, &[("b", b), ("d", 7)]).is_err());
+        assert_eq!(my_fs.i_get(1).unwrap(), before);
+        assert_eq!(my_fs.i_get(d).unwrap().get_nlink(), 0);
+
+        my_fs.set_clock(Box::new(|| 1234));
+        my_fs.dir_replace_entries(&mut iroot, &[("b", b), ("d", d), ("self", 1), ("alias", d)]).unwrap();
+        assert_eq!(iroot.disk_node.mtime, 1234);
+        assert_eq!(iroot.disk_node.ctime, 1234);
+        assert!(my_fs.dirlookup(&iroot, "a").is_err());
+        assert!(my_fs.dirlookup(&iroot, "c").is_err());
+        assert_eq!(my_fs.dirlookup(&iroot, "alias").unwrap().1, 3 * *DIRENTRY_SIZE);
+        assert_eq!(my_fs.i_get(1).unwrap(), iroot);
+        assert_eq!(iroot.get_size(), 4 * *DIRENTRY_SIZE);
+        assert_eq!(my_fs.dir_count_cached(&iroot), 4);
+        assert_eq!(my_fs.i_get(1).unwrap().get_nlink(), 1);
+        // the inodes that lost their last link are freed, as by `dirunlink`
+        assert_eq!(my_fs.i_get(a).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.i_get(b).unwrap().get_nlink(), 1);
+        assert_eq!(my_fs.i_get(c).unwrap().get_ft(), FType::TFree);
+        assert_eq!(my_fs.i_get(d).unwrap().get_nlink(), 2);
+        assert!(my_fs.fsck().unwrap().is_clean());
+
+        // a link count that would overflow is refused before anything changes
+        let mut ib = my_fs.i_get(b).unwrap();
+        ib.disk_node.nlink = u16::MAX;
+        my_fs.i_put(&ib).unwrap();
+        let before = my_fs.i_get(1).unwrap();
+        assert!(matches!(my_fs.dir_replace_entries(&mut iroot, &[("b", b), ("b2", b), ("d", d), ("self", 1), ("alias", d)]),
+            Err(CustomDirFileSystemError::CorruptDirectory)));
+        assert_eq!(my_fs.i_get(1).unwrap(), before);
+        assert_eq!(my_fs.i_get(b).unwrap().get_nlink(), u16::MAX as u64);
+        ib.disk_node.nlink = 1;
+        my_fs.i_put(&ib).unwrap();
+
+        // growing to a second block and shrinking back frees it again
+        let nb_dirs = BLOCK_SIZE / *DIRENTRY_SIZE;
+        let names: Vec<String> = (0..=nb_dirs).map(|i| format!("e{}", i)).collect();
+        let entries: Vec<(&str, u64)> = names.iter().map(|name| (name.as_str(), b)).collect();
+        my_fs.dir_replace_entries(&mut iroot, &entries).unwrap();
+        assert_eq!(iroot.get_size(), BLOCK_SIZE + *DIRENTRY_SIZE);
+        assert_eq!(my_fs.i_get(b).unwrap().get_nlink() as u64, nb_dirs + 1);
+        assert_eq!(my_fs.i_get(d).unwrap().get_ft(), FType::TFree);
+        let second = iroot.disk_node.direct_blocks[1];
+        assert!(!(second == 0));
+        my_fs.dir_replace_entries(&mut iroot, &[("b", b)]).unwrap();
+        assert_eq!(iroot.disk_node.direct_blocks[1], 0);
+        assert!(my_fs.b_free(second - SUPERBLOCK_GOOD.datastart).is_err());
+        assert_eq!(my_fs.dir_lookup_prefix(&iroot, "").unwrap(), vec![(String::from("b"), b)]);
+        assert_eq!(my_fs.i_get(b).unwrap().get_nlink(), 1);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn hashed_lookup() {
+        static SUPERBLOCK_SMALL: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 18,
+            ninodes: 8,
+            inodestart: 1,
+            ndatablocks: 12,
+            bmapstart: 5,
+            datastart: 6,
+        };
+        let path = disk_prep_path("hashed_lookup");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_SMALL).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        assert!(my_fs.dir_enable_hash_index(&mut my_fs.i_get(file).unwrap()).is_err());
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        let mut idir = my_fs.i_get(dir).unwrap();
+        let names: Vec<String> = (0..100).map(|i| format!("entry{}", i)).collect();
+        for name in names.iter() {
+            my_fs.dirlink(&mut idir, name, file).unwrap();
+        }
+
+        let reads = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&reads);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == Get {
+                *count.lock().unwrap() += 1;
+            }
+        })));
+        let linear: Vec<u64> = names.iter().map(|name| my_fs.dirlookup(&idir, name).unwrap().1).collect();
+        let linear_reads = *reads.lock().unwrap();
+        my_fs.dir_enable_hash_index(&mut idir).unwrap();
+        assert_eq!(my_fs.i_get(dir).unwrap(), idir);
+        *reads.lock().unwrap() = 0;
+        let hashed: Vec<u64> = names.iter().map(|name| my_fs.dirlookup(&idir, name).unwrap().1).collect();
+        let hashed_reads = *reads.lock().unwrap();
+        my_fs.set_io_hook(None);
+        assert_eq!(hashed, linear);
+        // a scan reads over 4 blocks per lookup on average here, the index less than 3 (index and entry block(s)),
+        // while the directory inode comes from the inode cache
+        assert!(hashed_reads < 3 * names.len() && linear_reads > 4 * names.len(), "{} reads through the index, {} by scanning", hashed_reads, linear_reads);
+        assert!(my_fs.dirlookup(&idir, "missing").is_err());
+
+        // linking and unlinking keep the index up to date
+        assert_eq!(my_fs.dirunlink(&mut idir, "entry50").unwrap(), linear[50]);
+        assert!(my_fs.dirlookup(&idir, "entry50").is_err());
+        assert_eq!(my_fs.dirlookup(&idir, "entry99").unwrap().1, linear[99]);
+        assert_eq!(my_fs.dirlink(&mut idir, "again", file).unwrap(), linear[50]);
+        assert_eq!(my_fs.dirlookup(&idir, "again").unwrap().1, linear[50]);
+        assert!(my_fs.dirlink(&mut idir, "entry7", file).is_err());
+        my_fs.dirrename(&mut idir, "entry7", "moved").unwrap();
+        assert!(my_fs.dirlookup(&idir, "entry7").is_err());
+        assert_eq!(my_fs.dirlookup(&idir, "moved").unwrap().1, linear[7]);
+        let last = my_fs.dirlink(&mut idir, "last", file).unwrap();
+        assert_eq!(my_fs.dirlookup(&idir, "last").unwrap().1, last);
+        my_fs.dir_replace_entries(&mut idir, &[("one", file), ("two", file)]).unwrap();
+        assert_eq!(my_fs.dirlookup(&idir, "two").unwrap().1, *DIRENTRY_SIZE);
+        assert!(my_fs.dirlookup(&idir, "entry0").is_err());
+
+        // truncating the directory releases its index as well
+        let index_block = idir.disk_node.direct_blocks[HASH_INDEX_SLOT];
+        my_fs.i_trunc(&mut idir).unwrap();
+        assert!(my_fs.b_free(index_block - SUPERBLOCK_SMALL.datastart).is_err());
+        assert_eq!(idir.disk_node.flags, 0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn health() {
+        let path = disk_prep_path("health");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        my_fs.add_dot_entries(dir, 1).unwrap();
+        let mut idir = my_fs.i_get(dir).unwrap();
+        let file = my_fs.create(&mut idir, "file", FType::TFile).unwrap();
+        let mut ifile = my_fs.i_get(file).unwrap();
+        ifile.disk_node.direct_blocks[0] = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        ifile.disk_node.size = 10;
+        my_fs.i_put(&ifile).unwrap();
+        let report = my_fs.health_report().unwrap();
+        assert!(report.is_healthy(), "{:?}", report);
+
+        // an orphan, a leaked block, a dangling entry and a wrong link count
+        let orphan = my_fs.i_alloc(FType::TFile).unwrap();
+        let leaked = my_fs.b_alloc().unwrap();
+        let gone = my_fs.create(&mut idir, "gone", FType::TFile).unwrap();
+        let mut igone = my_fs.i_get(gone).unwrap();
+        igone.disk_node.ft = FType::TFree;
+        my_fs.i_put(&igone).unwrap();
+        ifile.disk_node.nlink = 3;
+        my_fs.i_put(&ifile).unwrap();
+        let fs_before = my_fs.fs_checksum().unwrap();
+        let report = my_fs.health_report().unwrap();
+        assert_eq!(my_fs.fs_checksum().unwrap(), fs_before);
+        assert_eq!(report.dangling_entries, vec![(dir, String::from("gone"), gone)]);
+        assert_eq!(report.orphaned_inodes, vec![orphan]);
+        assert_eq!(report.leaked_blocks, vec![leaked]);
+        assert_eq!(report.link_count_mismatches, vec![(file, 3, 1)]);
+        assert!(report.unallocated_blocks_in_use.is_empty());
+        assert_eq!(report.issue_count(), 4);
+
+        // a block that is in use but free according to the bitmap
+        let index = ifile.disk_node.direct_blocks[0] - SUPERBLOCK_GOOD.datastart;
+        my_fs.b_free(index).unwrap();
+        let report = my_fs.health_report().unwrap();
+        assert_eq!(report.unallocated_blocks_in_use, vec![index]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn fsck() {
+        let path = disk_prep_path("fsck");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        let mut ifile = my_fs.i_get(file).unwrap();
+        ifile.disk_node.direct_blocks[0] = SUPERBLOCK_GOOD.datastart + my_fs.b_alloc().unwrap();
+        ifile.disk_node.size = 10;
+        my_fs.i_put(&ifile).unwrap();
+        let other = my_fs.create(&mut iroot, "other", FType::TFile).unwrap();
+        let report = my_fs.fsck().unwrap();
+        assert!(report.is_clean(), "{:?}", report);
+
+        // a leaked block
+        let leaked = my_fs.b_alloc().unwrap();
+        assert_eq!(my_fs.fsck().unwrap().problems, vec![FsckProblem::LeakedBlock { block: leaked }]);
+
+        // the same block in two files, which also means it is no longer leaked
+        let mut iother = my_fs.i_get(other).unwrap();
+        iother.disk_node.direct_blocks[0] = ifile.disk_node.direct_blocks[0];
+        iother.disk_node.direct_blocks[1] = SUPERBLOCK_GOOD.datastart + leaked;
+        iother.disk_node.size = 2 * BLOCK_SIZE;
+        my_fs.i_put(&iother).unwrap();
+        let block = ifile.disk_node.direct_blocks[0] - SUPERBLOCK_GOOD.datastart;
+        assert_eq!(my_fs.fsck().unwrap().problems, vec![FsckProblem::BlockReferencedTwice { block, first: file, second: other }]);
+
+        // every problem is reported, not just the first
+        my_fs.b_free(block).unwrap();
+        ifile.disk_node.nlink = 2;
+        my_fs.i_put(&ifile).unwrap();
+        let problems = my_fs.fsck().unwrap().problems;
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems.contains(&FsckProblem::LinkCountMismatch { inum: file, nlink: 2, expected: 1 }));
+        assert!(problems.contains(&FsckProblem::UnallocatedBlockInUse { block, inum: file }));
+        // the health report leaves out blocks referenced twice
+        assert_eq!(my_fs.health_report().unwrap().issue_count(), 2);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn build_directory() {
+        static SUPERBLOCK_LARGE: SuperBlock = SuperBlock {
+            block_size: 2000,
+            nblocks: 20,
+            ninodes: 8,
+            inodestart: 1,
+            ndatablocks: 17,
+            bmapstart: 2,
+            datastart: 3,
+        };
+        let path = disk_prep_path("build_directory");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_LARGE).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        let built = my_fs.create(&mut iroot, "built", FType::TDir).unwrap();
+        let linked = my_fs.create(&mut iroot, "linked", FType::TDir).unwrap();
+        assert!(my_fs.dir_builder(&iroot).is_err());
+        let names: Vec<String> = (0..500).map(|i| format!("entry{}", i)).collect();
+
+        let writes = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&writes);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == Put {
+                *count.lock().unwrap() += 1;
+            }
+        })));
+        let mut builder = my_fs.dir_builder(&my_fs.i_get(built).unwrap()).unwrap();
+        for name in names.iter() {
+            builder.add(name, file).unwrap();
+        }
+        assert!(builder.add("entry7", file).is_err());
+        assert!(builder.add("free", 7).is_err());
+        builder.add("self", built).unwrap();
+        let ibuilt = builder.finish().unwrap();
+        let builder_writes = *writes.lock().unwrap();
+        *writes.lock().unwrap() = 0;
+        let mut ilinked = my_fs.i_get(linked).unwrap();
+        for name in names.iter() {
+            my_fs.dirlink(&mut ilinked, name, file).unwrap();
+        }
+        let dirlink_writes = *writes.lock().unwrap();
+        my_fs.set_io_hook(None);
+        // 6 blocks, each zeroed and marked in the bitmap when allocated and written once, plus 2 inodes
+        assert_eq!(builder_writes, 6 * 3 + 2);
+        assert!(builder_writes * 50 < dirlink_writes);
+
+        assert_eq!(ibuilt, my_fs.i_get(built).unwrap());
+        assert_eq!(ibuilt.get_size(), ilinked.get_size() + *DIRENTRY_SIZE);
+        assert_eq!(my_fs.dir_count_cached(&ibuilt), 501);
+        for name in names.iter() {
+            assert_eq!(my_fs.dirlookup(&ibuilt, name).unwrap().1, my_fs.dirlookup(&ilinked, name).unwrap().1);
+        }
+        assert_eq!(my_fs.dirlookup(&ibuilt, "self").unwrap().0.inum, built);
+        assert_eq!(my_fs.i_get(file).unwrap().get_nlink(), 1 + 2 * 500);
+        assert_eq!(my_fs.i_get(built).unwrap().get_nlink(), 1);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn drop_dir_builder() {
+        let path = disk_prep_path("drop_dir_builder");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        let stale = my_fs.i_get(dir).unwrap();
+        let free = my_fs.count_free_blocks().unwrap();
+
+        // a builder dropped without finishing gives its blocks back
+        let mut builder = my_fs.dir_builder(&stale).unwrap();
+        for i in 0..(2 * SUPERBLOCK_GOOD.block_size / *DIRENTRY_SIZE) {
+            builder.add(&format!("entry{}", i), file).unwrap();
+        }
+        drop(builder);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), free);
+        assert_eq!(my_fs.i_get(dir).unwrap(), stale);
+        assert_eq!(my_fs.i_get(file).unwrap().get_nlink(), 1);
+
+        // the directory is checked as it is on disk, not as the caller last saw it
+        my_fs.dirlink(&mut my_fs.i_get(dir).unwrap(), "file", file).unwrap();
+        assert!(matches!(my_fs.dir_builder(&stale), Err(CustomDirFileSystemError::DirectoryNotEmpty)));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn unlink_entries() {
+        let path = disk_prep_path("unlink_entries");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        let offset = my_fs.dirlink(&mut iroot, "other", file).unwrap();
+        assert_eq!(my_fs.i_get(file).unwrap().get_nlink(), 2);
+
+        // the inode stays in use as long as an entry refers to it
+        assert_eq!(my_fs.dirunlink(&mut iroot, "other").unwrap(), offset);
+        assert!(matches!(my_fs.dirlookup(&iroot, "other"), Err(CustomDirFileSystemError::NoEntryFoundForName)));
+        assert_eq!(my_fs.i_get(file).unwrap().get_nlink(), 1);
+        assert_eq!(my_fs.i_get(file).unwrap().get_ft(), FType::TFile);
+        assert_eq!(my_fs.dirlookup(&iroot, "file").unwrap().0.inum, file);
+
+        my_fs.dirunlink(&mut iroot, "file").unwrap();
+        assert!(matches!(my_fs.dirlookup(&iroot, "file"), Err(CustomDirFileSystemError::NoEntryFoundForName)));
+        assert_eq!(my_fs.i_get(file).unwrap().get_ft(), FType::TFree);
+        assert!(matches!(my_fs.dirunlink(&mut iroot, "file"), Err(CustomDirFileSystemError::NoEntryFoundForName)));
+
+        let dir = my_fs.create(&mut iroot, "dir", FType::TDir).unwrap();
+        my_fs.add_dot_entries(dir, 1).unwrap();
+        let mut idir = my_fs.i_get(dir).unwrap();
+        assert!(matches!(my_fs.dirunlink(&mut idir, "."), Err(CustomDirFileSystemError::CannotUnlinkDotEntry)));
+        assert!(matches!(my_fs.dirunlink(&mut idir, ".."), Err(CustomDirFileSystemError::CannotUnlinkDotEntry)));
+        assert_eq!(my_fs.dirlookup(&idir, "..").unwrap().0.inum, 1);
+
+        // an entry referring to an inode without links is corruption, and nothing is removed
+        let file = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        let mut ifile = my_fs.i_get(file).unwrap();
+        ifile.disk_node.nlink = 0;
+        my_fs.i_put(&ifile).unwrap();
+        assert!(matches!(my_fs.dirunlink(&mut iroot, "file"), Err(CustomDirFileSystemError::CorruptDirectory)));
+        assert_eq!(my_fs.dirlookup(&iroot, "file").unwrap().0.inum, file);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn rename_entry() {
+        let path = disk_prep_path("rename_entry");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let a = my_fs.create(&mut iroot, "a", FType::TFile).unwrap();
+        let b = my_fs.create(&mut iroot, "b", FType::TFile).unwrap();
+        let offset = my_fs.dirlookup(&iroot, "a").unwrap().1;
+        let size = iroot.get_size();
+
+        my_fs.dirrename(&mut iroot, "a", "renamed").unwrap();
+        assert!(matches!(my_fs.dirlookup(&iroot, "a"), Err(CustomDirFileSystemError::NoEntryFoundForName)));
+        let (inode, new_offset) = my_fs.dirlookup(&iroot, "renamed").unwrap();
+        assert_eq!(inode.inum, a);
+        assert_eq!(new_offset, offset);
+        assert_eq!(inode.get_nlink(), 1);
+        assert_eq!(my_fs.i_get(1).unwrap().get_size(), size);
+
+        assert!(matches!(my_fs.dirrename(&mut iroot, "a", "c"), Err(CustomDirFileSystemError::NoEntryFoundForName)));
+        assert!(matches!(my_fs.dirrename(&mut iroot, "renamed", "b"), Err(CustomDirFileSystemError::InvalidEntryName)));
+        assert!(matches!(my_fs.dirrename(&mut iroot, "renamed", "b c"), Err(CustomDirFileSystemError::InvalidEntryName)));
+        assert_eq!(my_fs.dirlookup(&iroot, "b").unwrap().0.inum, b);
+        assert_eq!(my_fs.dirlookup(&iroot, "renamed").unwrap().0.inum, a);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
     }
-}
 
+    #[test]
+    fn reuse_freed_slot() {
+        let path = disk_prep_path("reuse_freed_slot");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        my_fs.create(&mut iroot, "A", FType::TFile).unwrap();
+        my_fs.create(&mut iroot, "B", FType::TFile).unwrap();
+        my_fs.create(&mut iroot, "C", FType::TFile).unwrap();
+        let offset = my_fs.dirlookup(&iroot, "B").unwrap().1;
+        let size = iroot.get_size();
 
+        my_fs.dirunlink(&mut iroot, "B").unwrap();
+        let d = my_fs.i_alloc(FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlink(&mut iroot, "D", d).unwrap(), offset);
+        assert_eq!(iroot.get_size(), size);
+        assert_eq!(my_fs.i_get(1).unwrap().get_size(), size);
+        assert_eq!(my_fs.dirlookup(&iroot, "D").unwrap(), (my_fs.i_get(d).unwrap(), offset));
 
-#[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
-    use cplfs_api::{fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport}, types::{DIRENTRY_SIZE, FType, InodeLike, SuperBlock}};
+        // without free slots, the directory grows again
+        let e = my_fs.i_alloc(FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlink(&mut iroot, "E", e).unwrap(), size);
+        assert_eq!(iroot.get_size(), size + *DIRENTRY_SIZE);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn link_timestamps() {
+        let path = disk_prep_path("link_timestamps");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        my_fs.set_clock(Box::new(|| 10));
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
 
-    use super::CustomDirFileSystem;
+        my_fs.set_clock(Box::new(|| 20));
+        my_fs.dirlink(&mut iroot, "file", inum).unwrap();
+        let root = my_fs.i_get(1).unwrap();
+        assert_eq!((root.disk_node.mtime, root.disk_node.ctime), (20, 20));
+        // linking changes the nlink of the file, not its contents
+        let file = my_fs.i_get(inum).unwrap();
+        assert_eq!((file.disk_node.mtime, file.disk_node.ctime), (10, 20));
 
-    fn disk_prep_path(name: &str) -> PathBuf {
-        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
     }
 
-    #[path = "utils.rs"]
-    mod utils;
+    #[test]
+    fn read_only_mount() {
+        let path = disk_prep_path("read_only_mount");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "file", inum).unwrap();
+        let dev = my_fs.unmountfs();
 
-    static BLOCK_SIZE: u64 = 1000;
-    static NBLOCKS: u64 = 10;
-    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
-        block_size: BLOCK_SIZE,
-        nblocks: NBLOCKS,
-        ninodes: 8,
-        inodestart: 1,
-        ndatablocks: 5,
-        bmapstart: 4,
-        datastart: 5,
-    };
+        let mut my_fs = CustomDirFileSystem::mountfs_ro(dev).unwrap();
+        assert!(my_fs.is_readonly());
+        let checksum = my_fs.fs_checksum().unwrap();
+        let read_only = |e: CustomDirFileSystemError| matches!(e, CustomDirFileSystemError::GivenError(CustomInodeFileSystemError::GivenError(CustomBlockFileSystemError::ReadOnlyFilesystem)));
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let before = my_fs.i_get(1).unwrap();
+        assert!(read_only(my_fs.dirlink(&mut iroot, "other", inum).unwrap_err()));
+        assert_eq!(iroot, before);
+        assert!(read_only(my_fs.i_alloc(FType::TFile).unwrap_err()));
+        let mut file = my_fs.i_get(inum).unwrap();
+        file.disk_node.nlink = 0;
+        assert!(read_only(my_fs.i_put(&file).unwrap_err()));
+        assert!(read_only(my_fs.i_free(inum).unwrap_err()));
+        assert!(read_only(my_fs.b_alloc().unwrap_err()));
+        // reads still work, and nothing changed
+        assert_eq!(my_fs.dirlookup(&iroot, "file").unwrap().0.inum, inum);
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.nlink, 1);
+        assert_eq!(my_fs.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert_eq!(my_fs.fs_checksum().unwrap(), checksum);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
 
     #[test]
-    fn dirlookup_link_new_block() {
-        let path = disk_prep_path("lkup_link_new_block");
+    fn op_stats() {
+        let path = disk_prep_path("op_stats");
         let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
-        let mut i2 = <<CustomDirFileSystem as InodeSupport>::Inode as InodeLike>::new(
-            5,
-            &FType::TDir,
-            0,
-            (2.5 * (BLOCK_SIZE as f32)) as u64,
-            &[5, 6, 7], //All of these blocks are initially 0'ed -> free direntries
-        )
-        .unwrap();
-        my_fs.i_put(&i2).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "first", inum).unwrap();
 
-        //Allocate blocks 5-6-7
-        for i in 0..4 {
-            assert_eq!(my_fs.b_alloc().unwrap(), i);
-        }
-        //Allocate inodes 2,3,4
-        for i in 0..3 {
-            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i + 2);
+        my_fs.reset_stats();
+        assert_eq!(my_fs.stats(), OpStats::default());
+        my_fs.dirlink(&mut iroot, "second", inum).unwrap();
+        // the root directory already has a block with room for the entry:
+        // it is read to check the name is not taken, to find a free slot and to write the entry into,
+        // and the inode block is read and written to store both the directory and the linked inode (which comes from the inode cache)
+        assert_eq!(my_fs.stats(), OpStats { b_get: 5, b_put: 3, b_alloc: 0, b_free: 0, i_get: 1, i_put: 2 });
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn io_error_kinds() {
+        let path = disk_prep_path("io_error_kinds");
+        let my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let root = my_fs.i_get(1).unwrap();
+
+        let e: std::io::Error = my_fs.dirlookup(&root, "missing").unwrap_err().into();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(e.to_string(), "No directory entry was found for the provided name");
+        // errors of lower layers keep their kind when they are wrapped
+        let e: std::io::Error = CustomDirFileSystemError::from(CustomInodeFileSystemError::from(CustomBlockFileSystemError::DataIndexOutOfBounds)).into();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        let e: std::io::Error = CustomDirFileSystemError::from(CustomInodeFileSystemError::NoFreeInode).into();
+        assert_eq!(e.kind(), std::io::ErrorKind::StorageFull);
+        let e: std::io::Error = CustomBlockFileSystemError::NoFreeDataBlock.into();
+        assert_eq!(e.kind(), std::io::ErrorKind::StorageFull);
+        assert_eq!(e.to_string(), "There is no free data block");
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dir_is_empty() {
+        let path = disk_prep_path("dir_is_empty");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut root = my_fs.i_get(1).unwrap();
+        let inum = my_fs.create(&mut root, "dir", FType::TDir).unwrap();
+        my_fs.add_dot_entries(inum, 1).unwrap();
+        let dir = my_fs.i_get(inum).unwrap();
+        assert!(my_fs.dir_is_empty(&dir).unwrap());
+        assert!(!my_fs.dir_is_empty(&my_fs.i_get(1).unwrap()).unwrap());
+
+        let file = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut dir = my_fs.i_get(inum).unwrap();
+        my_fs.dirlink(&mut dir, "file", file).unwrap();
+        assert!(!my_fs.dir_is_empty(&dir).unwrap());
+        my_fs.dirunlink(&mut dir, "file").unwrap();
+        assert!(my_fs.dir_is_empty(&dir).unwrap());
+
+        assert!(matches!(my_fs.dir_is_empty(&my_fs.i_get(file).unwrap()), Err(CustomDirFileSystemError::InodeWrongType)));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn mkdir() {
+        let path = disk_prep_path("mkdir");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut root = my_fs.i_get(1).unwrap();
+        let root_links = root.disk_node.nlink;
+
+        let inum = my_fs.mkdir(&mut root, "dir").unwrap();
+        let dir = my_fs.i_get(inum).unwrap();
+        assert_eq!(dir.disk_node.ft, FType::TDir);
+        assert_eq!(my_fs.dirlookup(&root, "dir").unwrap().0.inum, inum);
+        assert_eq!(my_fs.dirlookup(&dir, ".").unwrap().0.inum, inum);
+        assert_eq!(my_fs.dirlookup(&dir, "..").unwrap().0.inum, 1);
+        assert!(my_fs.dir_is_empty(&dir).unwrap());
+        // one link from the parent; `.` does not count
+        assert_eq!(dir.disk_node.nlink, 1);
+        // `..` links back to the parent, and the caller's copy of the parent is up to date
+        assert_eq!(root.disk_node.nlink, root_links + 1);
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.nlink, root_links + 1);
+
+        let mut dir = dir;
+        let sub = my_fs.mkdir(&mut dir, "sub").unwrap();
+        assert_eq!(my_fs.parent_of(&my_fs.i_get(sub).unwrap()).unwrap(), inum);
+        assert_eq!(dir.disk_node.nlink, 2);
+        assert!(my_fs.fsck().unwrap().problems.is_empty());
+
+        // a name that is taken does not leak an inode
+        let free = my_fs.count_free_inodes().unwrap();
+        assert!(matches!(my_fs.mkdir(&mut root, "dir"), Err(CustomDirFileSystemError::InvalidEntryName)));
+        assert_eq!(my_fs.count_free_inodes().unwrap(), free);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn list_names() {
+        let path = disk_prep_path("list_names");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut root = my_fs.i_get(1).unwrap();
+        assert_eq!(my_fs.list_names(&root).unwrap(), Vec::<String>::new());
+        for name in ["delta", "alpha", "charlie", "bravo"].iter() {
+            my_fs.create(&mut root, name, FType::TFile).unwrap();
         }
+        // an unlinked entry leaves a zeroed slot behind
+        my_fs.dirunlink(&mut root, "charlie").unwrap();
+        assert_eq!(my_fs.list_names(&root).unwrap(), vec!["alpha", "bravo", "delta"]);
 
-        let dir_entries_block = BLOCK_SIZE / *DIRENTRY_SIZE;
-        // 45 direntries to fill a bloc, 3 blocks were assigned
-        for i in 0..(dir_entries_block *3) {
-            let mut string = i.to_string();
-            let front = "test";
-            string.push_str(front);
-            let _res = my_fs.dirlink(&mut i2, &string, 2).unwrap();
+        let (file, _) = my_fs.dirlookup(&root, "alpha").unwrap();
+        assert!(matches!(my_fs.list_names(&file), Err(CustomDirFileSystemError::InodeWrongType)));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn root_inode() {
+        let path = disk_prep_path("root_inode");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let root = my_fs.root_inode().unwrap();
+        assert_eq!(root.inum, 1);
+        assert_eq!(root.disk_node.ft, FType::TDir);
+        assert!(my_fs.is_root(&root));
+
+        // a root that is not a directory is refused
+        let mut broken = root;
+        broken.disk_node.ft = FType::TFile;
+        my_fs.i_put(&broken).unwrap();
+        assert!(matches!(my_fs.root_inode(), Err(CustomDirFileSystemError::InodeWrongType)));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn entry_count_not_reused() {
+        let path = disk_prep_path("entry_count_not_reused");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TDir).unwrap();
+        let mut dir = my_fs.i_get(inum).unwrap();
+        for name in ["a", "b", "c"].iter() {
+            let file = my_fs.i_alloc(FType::TFile).unwrap();
+            my_fs.dirlink(&mut dir, name, file).unwrap();
         }
-        // a new block should be allocated now, the dir entry should begin 
-        // at the start of the new block
-        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock", 2).unwrap(), 3000);
-        // DIRENTRY = 22
-        assert_eq!(i2.disk_node.size, 3000 + *DIRENTRY_SIZE);
-        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock2", 2).unwrap(), 3000 + *DIRENTRY_SIZE);
-        assert_eq!(i2.disk_node.size, 3000 + 2* *DIRENTRY_SIZE);
-        assert_eq!(my_fs.dirlink(&mut i2, "nieuweblock3", 2).unwrap(),  3000 + 2* *DIRENTRY_SIZE);
-        assert_eq!(i2.disk_node.size,  3000 + 3* *DIRENTRY_SIZE);
+        assert_eq!(my_fs.dir_count_cached(&dir), 3);
+        // nothing links to the directory, so freeing it releases the inode
+        my_fs.i_free(inum).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.entry_count, 0);
+        assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), inum);
+        assert_eq!(my_fs.dir_count_cached(&my_fs.i_get(inum).unwrap()), 0);
+
+        // a free inode that still holds a count, e.g. on an older image, is reset when allocated
+        my_fs.i_free(inum).unwrap();
+        let mut stale = my_fs.i_get(inum).unwrap();
+        stale.disk_node.entry_count = 3;
+        my_fs.i_put(&stale).unwrap();
+        assert_eq!(my_fs.i_alloc(FType::TDir).unwrap(), inum);
+        assert_eq!(my_fs.dir_count_cached(&my_fs.i_get(inum).unwrap()), 0);
+
         let dev = my_fs.unmountfs();
         utils::disk_destruct(dev);
     }
 
     #[test]
-    fn dirlookup_link_extend_block() {
-        let path = disk_prep_path("lkup_link_extend_block");
+    fn dirunlink_directory() {
+        let path = disk_prep_path("dirunlink_directory");
         let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut root = my_fs.i_get(1).unwrap();
+        let root_links = root.disk_node.nlink;
+        let inum = my_fs.mkdir(&mut root, "d").unwrap();
+        let mut dir = my_fs.i_get(inum).unwrap();
+        my_fs.create(&mut dir, "f", FType::TFile).unwrap();
 
-        let mut i2 = <<CustomDirFileSystem as InodeSupport>::Inode as InodeLike>::new(
-            5,
-            &FType::TDir,
-            0,
-            (2.5 * (BLOCK_SIZE as f32)) as u64,
-            &[5, 6, 7], //All of these blocks are initially 0'ed -> free direntries
-        )
-        .unwrap();
-        my_fs.i_put(&i2).unwrap();
-        //Allocate blocks 5-6-7
-        for i in 0..3 {
-            assert_eq!(my_fs.b_alloc().unwrap(), i);
+        // a directory that is not empty cannot be unlinked
+        assert!(matches!(my_fs.dirunlink(&mut root, "d"), Err(CustomDirFileSystemError::DirectoryNotEmpty)));
+        assert!(my_fs.dirlookup(&root, "d").is_ok());
+        assert!(my_fs.fsck().unwrap().is_clean());
+
+        my_fs.dirunlink(&mut dir, "f").unwrap();
+        my_fs.dirunlink(&mut root, "d").unwrap();
+        assert_eq!(root.disk_node.nlink, root_links);
+        assert_eq!(my_fs.i_get(1).unwrap().disk_node.nlink, root_links);
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.ft, FType::TFree);
+        let report = my_fs.fsck().unwrap();
+        assert!(report.is_clean(), "{:?}", report);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn drop_without_unmount() {
+        let path = disk_prep_path("drop_without_unmount");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        iroot.disk_node.ft = FType::TDir;
+        my_fs.i_put(&iroot).unwrap();
+        let inum = my_fs.create(&mut iroot, "kept", FType::TFile).unwrap();
+        drop(my_fs);
+
+        let dev = utils::disk_open(&path, BLOCK_SIZE, NBLOCKS);
+        let my_fs = CustomDirFileSystem::mountfs(dev).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        assert_eq!(my_fs.dirlookup(&mut iroot, "kept").unwrap().0.inum, inum);
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.ft, FType::TFile);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn lookup_prefix() {
+        let path = disk_prep_path("lookup_prefix");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let abc = my_fs.create(&mut iroot, "abc", FType::TFile).unwrap();
+        let abd = my_fs.create(&mut iroot, "abd", FType::TFile).unwrap();
+        let xyz = my_fs.create(&mut iroot, "xyz", FType::TFile).unwrap();
+
+        let found = my_fs.dir_lookup_prefix(&iroot, "ab").unwrap();
+        assert_eq!(found, vec![("abc".to_string(), abc), ("abd".to_string(), abd)]);
+        let all = my_fs.dir_lookup_prefix(&iroot, "").unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all.contains(&("xyz".to_string(), xyz)));
+        assert!(my_fs.dir_lookup_prefix(&iroot, "abz").unwrap().is_empty());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn cached_entry_count() {
+        let path = disk_prep_path("cached_entry_count");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let a = my_fs.create(&mut iroot, "a", FType::TFile).unwrap();
+        my_fs.create(&mut iroot, "b", FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "self", 1).unwrap();
+        assert_eq!(my_fs.dir_count_cached(&iroot), 3);
+
+        assert_eq!(my_fs.dirunlink(&mut iroot, "a").unwrap(), 0);
+        assert_eq!(my_fs.i_get(a).unwrap().get_nlink(), 0);
+        assert!(my_fs.dirunlink(&mut iroot, "a").is_err());
+        assert_eq!(my_fs.dir_count_cached(&iroot), 2);
+        // the freed slot is reused
+        let c = my_fs.i_alloc(FType::TFile).unwrap();
+        assert_eq!(my_fs.dirlink(&mut iroot, "c", c).unwrap(), 0);
+        my_fs.dirunlink(&mut iroot, "self").unwrap();
+        let scanned = my_fs.dir_lookup_prefix(&iroot, "").unwrap().len() as u64;
+        assert_eq!(my_fs.dir_count_cached(&iroot), scanned);
+        assert_eq!(my_fs.dir_count_cached(&my_fs.i_get(1).unwrap()), scanned);
+
+        // a count that is out of date, as written by older code, is fixed when mounting
+        iroot.disk_node.entry_count = 0;
+        my_fs.i_put(&iroot).unwrap();
+        let dev = my_fs.unmountfs();
+        let my_fs = CustomDirFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.dir_count_cached(&my_fs.i_get(1).unwrap()), scanned);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dangling_entries() {
+        let path = disk_prep_path("dangling_entries");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        my_fs.create(&mut iroot, "kept", FType::TFile).unwrap();
+        let gone = my_fs.create(&mut iroot, "gone", FType::TFile).unwrap();
+        assert!(my_fs.verify_directory(&iroot).unwrap().is_empty());
+
+        // free the inode behind the back of the directory
+        let mut igone = my_fs.i_get(gone).unwrap();
+        igone.disk_node.nlink = 0;
+        my_fs.i_put(&igone).unwrap();
+        my_fs.i_free(gone).unwrap();
+        assert_eq!(my_fs.verify_directory(&iroot).unwrap(), vec![("gone".to_string(), gone)]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dir_json() {
+        let path = disk_prep_path("dir_json");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        assert_eq!(my_fs.dir_to_json(&iroot).unwrap(), "[]");
+        my_fs.dirlink(&mut iroot, ".", 1).unwrap();
+        let file = my_fs.create(&mut iroot, "file1", FType::TFile).unwrap();
+
+        let expected = format!(
+            "[{{\"name\":\".\",\"inum\":1,\"ftype\":\"TDir\",\"size\":{}}},{{\"name\":\"file1\",\"inum\":{},\"ftype\":\"TFile\",\"size\":0}}]",
+            2 * *DIRENTRY_SIZE,
+            file
+        );
+        assert_eq!(my_fs.dir_to_json(&iroot).unwrap(), expected);
+        // entry names cannot contain these, but the quoting has to handle them regardless
+        assert_eq!(json_string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+
+        // write an entry with such a name behind the back of the directory layer, and check that its name parses back
+        let raw_name = "a\"b\\c\n\t\u{1}/é";
+        let (_, offset) = my_fs.dirlookup(&iroot, "file1").unwrap();
+        let mut dir_entry = DirEntry::default();
+        dir_entry.inum = file;
+        for (index, c) in raw_name.chars().enumerate() {
+            dir_entry.name[index] = c;
         }
-        //Allocate inodes 2,3,4
-        for i in 0..3 {
-            assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), i + 2);
+        let block_no = iroot.disk_node.direct_blocks[(offset / BLOCK_SIZE) as usize];
+        let mut block = my_fs.b_get(block_no).unwrap();
+        block.serialize_into(&dir_entry, offset % BLOCK_SIZE).unwrap();
+        my_fs.b_put(&block).unwrap();
+        let entries = parse_json(&my_fs.dir_to_json(&iroot).unwrap());
+        let names: Vec<(&str, u64)> = match &entries {
+            Json::Array(entries) => entries.iter().map(|entry| match entry {
+                Json::Object(fields) => match (&fields[0], &fields[1]) {
+                    ((name_key, Json::String(name)), (inum_key, Json::Number(inum))) if name_key == "name" && inum_key == "inum" => (name.as_str(), *inum),
+                    other => panic!("unexpected fields {:?}", other)
+                },
+                other => panic!("unexpected entry {:?}", other)
+            }).collect(),
+            other => panic!("unexpected JSON {:?}", other)
+        };
+        assert_eq!(names, vec![(".", 1), (raw_name, file)]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    /// The JSON values `dir_to_json` can produce
+    #[derive(Debug)]
+    enum Json {
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+        String(String),
+        Number(u64)
+    }
+
+    /// Parse the JSON `text`, panicking if it is malformed or not entirely consumed
+    fn parse_json(text: &str) -> Json {
+        let mut chars = text.chars().peekable();
+        let value = parse_json_value(&mut chars);
+        assert_eq!(chars.next(), None, "trailing characters in {}", text);
+        return value
+    }
+
+    fn parse_json_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Json {
+        match chars.peek() {
+            Some('[') => {
+                chars.next();
+                let mut items = Vec::new();
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Json::Array(items)
+                }
+                loop {
+                    items.push(parse_json_value(chars));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Json::Array(items),
+                        other => panic!("expected , or ] but found {:?}", other)
+                    }
+                }
+            }
+            Some('{') => {
+                chars.next();
+                let mut fields = Vec::new();
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Json::Object(fields)
+                }
+                loop {
+                    let key = match parse_json_value(chars) {
+                        Json::String(key) => key,
+                        other => panic!("expected a key but found {:?}", other)
+                    };
+                    assert_eq!(chars.next(), Some(':'));
+                    fields.push((key, parse_json_value(chars)));
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Json::Object(fields),
+                        other => panic!("expected , or }} but found {:?}", other)
+                    }
+                }
+            }
+            Some('"') => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => return Json::String(string),
+                        Some('\\') => match chars.next() {
+                            Some('"') => string.push('"'),
+                            Some('\\') => string.push('\\'),
+                            Some('/') => string.push('/'),
+                            Some('n') => string.push('\n'),
+                            Some('r') => string.push('\r'),
+                            Some('t') => string.push('\t'),
+                            Some('b') => string.push('\u{8}'),
+                            Some('f') => string.push('\u{c}'),
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                string.push(std::char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                            }
+                            other => panic!("invalid escape {:?}", other)
+                        },
+                        Some(c) if (c as u32) < 0x20 => panic!("unescaped control character {:?}", c),
+                        Some(c) => string.push(c),
+                        None => panic!("unterminated string")
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut number = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    number = number * 10 + digit as u64;
+                    chars.next();
+                }
+                return Json::Number(number)
+            }
+            other => panic!("unexpected {:?}", other)
         }
+    }
 
-        let nb_inodes = 2500 / *DIRENTRY_SIZE;
-        // fill up 2.5 block size
-        for i in 0..nb_inodes -1{
-            let mut string = i.to_string();
-            let front = "test";
-            string.push_str(front);
-            let _res = my_fs.dirlink(&mut i2, &string, 2).unwrap();
+    #[test]
+    fn dirlink_block_io() {
+        let path = disk_prep_path("dirlink_block_io");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&events);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| log.lock().unwrap().push((event.block, event.op)))));
+        my_fs.dirlink(&mut iroot, "file", inum).unwrap();
+        my_fs.set_io_hook(None);
+        my_fs.i_get(1).unwrap();
+
+        // all inodes live in block 1, the bitmap in block 4, and the new directory block is block 5
+        // the inode to link is still in the inode cache after allocating it
+        assert_eq!(*events.lock().unwrap(), vec![
+            (4, Get), (4, Put), (5, Put), // allocate and zero a block for the directory
+            (5, Get), // write the entry into it
+            (1, Get), (1, Put), // store the grown directory inode
+            (5, Put),
+            (1, Get), (1, Put) // increase nlink of the linked inode
+        ]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dirlink_single_inode_write() {
+        let path = disk_prep_path("dirlink_single_inode_write");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "first", inum).unwrap();
+
+        let inode_writes = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&inode_writes);
+        // all inodes live in block 1
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.block == 1 && event.op == Put {
+                *counter.lock().unwrap() += 1;
+            }
+        })));
+        // extend the directory within its existing block with a self-reference
+        assert_eq!(my_fs.dirlink(&mut iroot, "self", 1).unwrap(), *DIRENTRY_SIZE);
+        assert_eq!(*inode_writes.lock().unwrap(), 1);
+        // linking another inode also has to update that inode
+        assert_eq!(my_fs.dirlink(&mut iroot, "second", inum).unwrap(), 2 * *DIRENTRY_SIZE);
+        assert_eq!(*inode_writes.lock().unwrap(), 3);
+        my_fs.set_io_hook(None);
+        assert_eq!(my_fs.i_get(1).unwrap(), iroot);
+        assert_eq!(iroot.get_size(), 3 * *DIRENTRY_SIZE);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn corrupt_names() {
+        let mut de = CustomDirFileSystem::new_de(2, "file").unwrap();
+        assert_eq!(CustomDirFileSystem::try_get_name_str(&de), Ok("file".to_string()));
+
+        // leftovers of a longer name after the terminating NUL
+        de.name[5] = 'x';
+        assert_eq!(CustomDirFileSystem::try_get_name_str(&de), Err(NameError::InteriorNul));
+        assert_eq!(CustomDirFileSystem::get_name_str(&de), "file");
+
+        let mut de = CustomDirFileSystem::new_de(2, "file").unwrap();
+        de.name[1] = '\u{7}';
+        assert_eq!(CustomDirFileSystem::try_get_name_str(&de), Err(NameError::NonPrintable));
+
+        // a name filling up the whole array has no terminating NUL
+        let full = "a".repeat(DIRNAME_SIZE);
+        let de = CustomDirFileSystem::new_de(2, &full).unwrap();
+        assert_eq!(CustomDirFileSystem::try_get_name_str(&de), Ok(full));
+    }
+
+    #[test]
+    fn name_boundaries() {
+        // every length up to DIRNAME_SIZE round-trips, one more is rejected
+        for length in 1..=DIRNAME_SIZE {
+            let name = "a".repeat(length);
+            let de = CustomDirFileSystem::new_de(2, &name).unwrap();
+            assert_eq!(CustomDirFileSystem::get_name_str(&de), name);
         }
+        assert!(CustomDirFileSystem::new_de(2, &"a".repeat(DIRNAME_SIZE + 1)).is_none());
+
+        // the length is counted in characters, not in bytes
+        let wide = "é".repeat(DIRNAME_SIZE);
+        let de = CustomDirFileSystem::new_de(2, &wide).unwrap();
+        assert_eq!(CustomDirFileSystem::get_name_str(&de), wide);
+        assert!(CustomDirFileSystem::new_de(2, &"é".repeat(DIRNAME_SIZE + 1)).is_none());
+
+        // a shorter name overwrites a longer one completely
+        let mut de = CustomDirFileSystem::new_de(2, &"a".repeat(DIRNAME_SIZE)).unwrap();
+        CustomDirFileSystem::set_name_str(&mut de, "b").unwrap();
+        assert_eq!(CustomDirFileSystem::try_get_name_str(&de), Ok("b".to_string()));
+    }
+
+    #[test]
+    fn name_characters() {
+        let path = disk_prep_path("name_characters");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        for name in ["my_file", "log-2024", "notes.txt", ".hidden", ".", ".."].iter() {
+            let de = CustomDirFileSystem::new_de(2, name).unwrap();
+            assert_eq!(CustomDirFileSystem::get_name_str(&de), *name);
+        }
+        for name in ["a b", "a/b", "a*", ""].iter() {
+            assert!(CustomDirFileSystem::new_de(2, name).is_none());
+        }
+
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.create(&mut iroot, "my_file", FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "log-2024", inum).unwrap();
+        assert_eq!(my_fs.dirlookup(&iroot, "log-2024").unwrap().0.inum, inum);
+        assert!(matches!(my_fs.dirlink(&mut iroot, "my file", inum), Err(CustomDirFileSystemError::InvalidEntryName)));
 
-        // can start here if size is extended
-        assert_eq!(my_fs.dirlink(&mut i2, "extendblock", 2).unwrap(), 2484);
-        // size should be extended
-        assert_eq!(i2.disk_node.size, 2506);
-        assert_eq!(my_fs.dirlink(&mut i2, "extendblock2", 2).unwrap(), 2506);
-        assert_eq!(i2.disk_node.size, 2528);
-        assert_eq!(my_fs.dirlink(&mut i2, "extendblock3", 2).unwrap(), 2528);
-        assert_eq!(i2.disk_node.size, 2550);
         let dev = my_fs.unmountfs();
         utils::disk_destruct(dev);
     }
@@ -0,0 +1,96 @@
+//! Checksums over the contents of blocks
+//!
+//! Implemented by hand, bit by bit, to avoid pulling in an extra dependency.
+//! The file systems only checksum a handful of blocks at a time, so speed is not a concern.
+
+/// Reflected polynomial of CRC-64/XZ (the ECMA-182 polynomial)
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+
+/// Running CRC-64/XZ checksum, to checksum data that is not available in one piece
+#[derive(Debug, Clone, Copy)]
+pub struct Crc64 {
+    state: u64
+}
+
+impl Crc64 {
+    /// Start a new checksum
+    pub fn new() -> Crc64 {
+        Crc64 { state: !0 }
+    }
+
+    /// Add `data` to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state ^= *byte as u64;
+            for _ in 0..8 {
+                if self.state & 1 == 1 {
+                    self.state = (self.state >> 1) ^ CRC64_POLY;
+                } else {
+                    self.state >>= 1;
+                }
+            }
+        }
+    }
+
+    /// The checksum of all data added so far
+    pub fn finish(&self) -> u64 {
+        return !self.state
+    }
+}
+
+impl Default for Crc64 {
+    fn default() -> Crc64 {
+        Crc64::new()
+    }
+}
+
+/// CRC-64/XZ checksum of `data`
+pub fn crc64(data: &[u8]) -> u64 {
+    let mut crc = Crc64::new();
+    crc.update(data);
+    return crc.finish()
+}
+
+/// Reflected polynomial of the standard CRC-32 (as used by zlib and ethernet)
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/// CRC-32 checksum of `data`, small enough to store one per block
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut state: u32 = !0;
+    for byte in data {
+        state ^= *byte as u32;
+        for _ in 0..8 {
+            if state & 1 == 1 {
+                state = (state >> 1) ^ CRC32_POLY;
+            } else {
+                state >>= 1;
+            }
+        }
+    }
+    return !state
+}
+
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use super::{crc32, crc64, Crc64};
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(b"123456789"), 0x995D_C9BB_DF19_39FA);
+        assert_eq!(crc64(b""), 0);
+        let mut crc = Crc64::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), crc64(b"123456789"));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
@@ -17,13 +17,25 @@
 //! or you want to explain your approach, write it down after the comments
 //! section. If you had no major issues and everything works, there is no need to write any comments.
 //!
-//! COMPLETED: NO
+//! COMPLETED: PARTIAL
 //!
 //! COMMENTS:
 //!
-//! ...
+//! The file system below wraps the one of assignment c and can resolve paths through the directory entries,
+//! but does not keep track of a current working directory yet, so it does not implement `PathSupport`,
+//! and relative paths are resolved starting from the root directory.
+//! It also supports symbolic links, which store the path they refer to in their data blocks.
+//! As the directory layer does not build on the read and write layer of assignment e, these blocks are written directly.
 //!
 
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use cplfs_api::{controller::Device, error_given, fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport}, types::{Block, DIRECT_POINTERS, DIRNAME_SIZE, DirEntry, FType, Inode, ROOT_INUM, SuperBlock}};
+use thiserror::Error;
+
+use crate::c_dirs_support::{self, CustomDirFileSystem, CustomDirFileSystemError};
+use crate::a_block_support;
+
 /// You are free to choose the name for your file system. As we will use
 /// automated tests when grading your assignment, indicate here the name of
 /// your file system data type so we can just use `FSName` instead of
@@ -31,7 +43,537 @@
 /// **TODO**: replace the below type by the type of your file system
 pub type FSName = ();
 
-// **TODO** define your own tests here.
+/// Maximum number of symbolic links followed while resolving a single path
+pub const MAX_SYMLINK_DEPTH: u32 = 40;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+/// Reasons why a path string is rejected
+pub enum PathError {
+    #[error("The path is empty")]
+    /// The path does not contain any characters
+    EmptyPath,
+    #[error("The path contains an empty component")]
+    /// Two slashes follow each other somewhere in the path
+    EmptyComponent,
+    #[error("The path contains a component that is too long: {0}")]
+    /// A component of the path is longer than `DIRNAME_SIZE`
+    ComponentTooLong(String),
+    #[error("The path contains an invalid component: {0}")]
+    /// A component of the path contains characters that are not allowed in the name of a directory entry
+    InvalidComponent(String),
+}
+
+/// Split `path` into its components, and normalize these lexically, i.e. without looking at the file system:
+/// `.` components are dropped, and `..` components cancel out the component in front of them where there is one.
+/// A `..` at the root of an absolute path stays at the root, at the start of a relative path it is kept.
+/// Every component has to be a valid name for a directory entry (see `set_name_str`).
+pub fn normalize_path(path: &str) -> Result<Vec<String>, PathError> {
+    let (absolute, split) = split_path(path)?;
+    let mut components: Vec<String> = Vec::new();
+    for component in split {
+        match component {
+            "." => (),
+            ".." => match components.last().map(|c| c.as_str()) {
+                Some("..") | None => {
+                    if !absolute {
+                        components.push(component.to_string());
+                    }
+                },
+                Some(_) => {
+                    components.pop();
+                }
+            },
+            _ => components.push(component.to_string())
+        }
+    }
+    return Ok(components)
+}
+
+/// Split `path` into its components, without interpreting `.` and `..`, and tell whether the path is absolute.
+/// Fails if `path` is empty, or if any of its components is not a valid name for a directory entry.
+fn split_path(path: &str) -> Result<(bool, Vec<&str>), PathError> {
+    if path.is_empty() {
+        return Err(PathError::EmptyPath);
+    }
+    let absolute = path.starts_with('/');
+    // a leading slash marks an absolute path, a trailing one is allowed as well
+    let trimmed = path.strip_prefix('/').unwrap_or(path);
+    let trimmed = trimmed.strip_suffix('/').unwrap_or(trimmed);
+    let mut components = Vec::new();
+    if trimmed.is_empty() {
+        return Ok((absolute, components))
+    }
+    for component in trimmed.split('/') {
+        if component.is_empty() {
+            return Err(PathError::EmptyComponent);
+        }
+        if component.chars().count() > DIRNAME_SIZE {
+            return Err(PathError::ComponentTooLong(component.to_string()));
+        }
+        if CustomDirFileSystem::new_de(0, component).is_none() {
+            return Err(PathError::InvalidComponent(component.to_string()));
+        }
+        components.push(component);
+    }
+    return Ok((absolute, components))
+}
+
+// Custom type
+/// Custom file system data type
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
+pub struct CustomPathFileSystem {
+    dir_fs: CustomDirFileSystem,
+}
+
+impl CustomPathFileSystem {
+
+    /// Create a new CustomPathFileSystem given a CustomDirFileSystem
+    pub fn new(dirfs: CustomDirFileSystem) -> CustomPathFileSystem {
+        CustomPathFileSystem { dir_fs: dirfs }
+    }
+
+    /// Mount the file system on `dev` like `mountfs`, but read-only, see `CustomBlockFileSystem::mountfs_ro`
+    pub fn mountfs_ro(dev: Device) -> Result<Self, CustomPathFileSystemError> {
+        let dir_fs = CustomDirFileSystem::mountfs_ro(dev)?;
+        return Ok(CustomPathFileSystem::new(dir_fs));
+    }
+
+    /// Look up the inode at `path`, starting from the root directory, and return it together with its number.
+    /// Every component is looked up in the directory the previous ones led to, so `.` and `..` are followed through the
+    /// directory entries, rather than lexically. A directory without a `.` entry refers to itself, the root is its own parent.
+    /// As there is no current working directory yet, relative paths are resolved from the root as well.
+    ///
+    /// Symbolic links are followed wherever they occur in the path, including at its end. An absolute target is resolved from the root,
+    /// a relative one from the directory holding the link. At most `MAX_SYMLINK_DEPTH` links are followed.
+    ///
+    /// Errors if `path` is invalid, if some component is not found, if a component other than the last one is not a directory,
+    /// or if too many symbolic links are followed.
+    pub fn resolve_path(&self, path: &str) -> Result<(Inode, u64), CustomPathFileSystemError> {
+        let (_, components) = split_path(path)?;
+        let mut pending: VecDeque<String> = components.iter().map(|c| c.to_string()).collect();
+        let mut current = self.i_get(ROOT_INUM)?;
+        let mut links_followed = 0;
+        while let Some(component) = pending.pop_front() {
+            if !(current.disk_node.ft == FType::TDir) {
+                return Err(CustomPathFileSystemError::NotADirectory(component));
+            }
+            let next = match self.dir_fs.dirlookup(&current, &component) {
+                Ok((inode, _)) => inode,
+                Err(CustomDirFileSystemError::NoEntryFoundForName) => match component.as_str() {
+                    "." => self.i_get(current.inum)?,
+                    // a directory without a `..` entry (other than the root) has no parent to go to
+                    ".." => match self.dir_fs.parent_of(&current) {
+                        Ok(parent) => self.i_get(parent)?,
+                        Err(CustomDirFileSystemError::NoEntryFoundForName) => return Err(CustomPathFileSystemError::PathComponentNotFound(component)),
+                        Err(e) => return Err(e.into())
+                    },
+                    _ => return Err(CustomPathFileSystemError::PathComponentNotFound(component))
+                },
+                Err(e) => return Err(e.into())
+            };
+            if next.disk_node.ft == FType::TSymlink {
+                links_followed += 1;
+                if links_followed > MAX_SYMLINK_DEPTH {
+                    return Err(CustomPathFileSystemError::SymlinkLoop);
+                }
+                let target = self.symlink_target(&next)?;
+                let (absolute, target_components) = split_path(&target)?;
+                for target_component in target_components.iter().rev() {
+                    pending.push_front(target_component.to_string());
+                }
+                if absolute {
+                    current = self.i_get(ROOT_INUM)?;
+                }
+                continue
+            }
+            current = next;
+        }
+        let inum = current.inum;
+        return Ok((current, inum))
+    }
+
+    /// Create a symbolic link referring to the path `target`, and return its inode number.
+    /// The link is not entered in any directory yet, use `dirlink` for that. The target does not have to exist.
+    /// Errors if `target` is not a valid path, or if it does not fit in the direct blocks of an inode.
+    /// If the link cannot be created, e.g. because the disk is full, no blocks or inode are left allocated.
+    pub fn symlink_create(&mut self, target: &str) -> Result<u64, CustomPathFileSystemError> {
+        split_path(target)?;
+        let sb = self.sup_get()?;
+        let bytes = target.as_bytes();
+        if bytes.len() as u64 > DIRECT_POINTERS * sb.block_size {
+            return Err(CustomPathFileSystemError::SymlinkTargetTooLong);
+        }
+        // every block is allocated before the inode, so that running out of either is easy to undo
+        let mut blocks = Vec::new();
+        for _ in bytes.chunks(sb.block_size as usize) {
+            match self.b_alloc() {
+                Ok(index) => blocks.push(index),
+                Err(e) => {
+                    self.free_data_blocks(&blocks)?;
+                    return Err(e);
+                }
+            }
+        }
+        let inum = match self.i_alloc(FType::TSymlink) {
+            Ok(inum) => inum,
+            Err(e) => {
+                self.free_data_blocks(&blocks)?;
+                return Err(e);
+            }
+        };
+        let mut inode = self.i_get(inum)?;
+        for (index, block) in blocks.iter().enumerate() {
+            inode.disk_node.direct_blocks[index] = sb.datastart + block;
+        }
+        inode.disk_node.size = bytes.len() as u64;
+        // from here on the inode owns the blocks, so freeing it releases them as well
+        self.i_put(&inode)?;
+        for (index, chunk) in bytes.chunks(sb.block_size as usize).enumerate() {
+            let written = self.b_get(inode.disk_node.direct_blocks[index]).and_then(|mut block| {
+                block.write_data(chunk, 0)?;
+                return self.b_put(&block)
+            });
+            if let Err(e) = written {
+                self.i_free(inum)?;
+                return Err(e);
+            }
+        }
+        return Ok(inum)
+    }
+
+    /// Free the data blocks with the given indices, as handed out by `b_alloc`
+    fn free_data_blocks(&mut self, blocks: &[u64]) -> Result<(), CustomPathFileSystemError> {
+        for index in blocks {
+            self.b_free(*index)?;
+        }
+        return Ok(())
+    }
+
+    /// Path the symbolic link `inode` refers to. Errors if `inode` is not a symbolic link.
+    pub fn symlink_target(&self, inode: &Inode) -> Result<String, CustomPathFileSystemError> {
+        if !(inode.disk_node.ft == FType::TSymlink) {
+            return Err(CustomPathFileSystemError::NotASymlink);
+        }
+        let sb = self.sup_get()?;
+        let mut bytes = vec![0; inode.disk_node.size as usize];
+        for (index, chunk) in bytes.chunks_mut(sb.block_size as usize).enumerate() {
+            self.b_get(inode.disk_node.direct_blocks[index])?.read_data(chunk, 0)?;
+        }
+        return Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+#[derive(Error, Debug)]
+/// Custom type for errors in CustomPathFileSystem
+pub enum CustomPathFileSystemError {
+    /// An error occured in the directory layer
+    #[error("DirFileSystemError")]
+    GivenError(#[from] c_dirs_support::CustomDirFileSystemError),
+    #[error("API error")]
+    /// The input provided to some method in the controller layer was invalid
+    APIError(#[from] error_given::APIError),
+    #[error("Invalid path")]
+    /// The provided path is not a valid path
+    InvalidPath(#[from] PathError),
+    #[error("No directory entry was found for the path component {0}")]
+    /// A component of the path does not correspond to an entry in the directory it is looked up in
+    PathComponentNotFound(String),
+    #[error("The path component {0} is looked up in an inode that is not a directory")]
+    /// A component of the path follows a component that does not refer to a directory
+    NotADirectory(String),
+    #[error("Too many symbolic links were followed")]
+    /// Thrown when resolving a path follows more than `MAX_SYMLINK_DEPTH` symbolic links, which usually means they form a loop
+    SymlinkLoop,
+    #[error("The target of the symbolic link is too long")]
+    /// Thrown when the target of a new symbolic link does not fit in the direct blocks of an inode
+    SymlinkTargetTooLong,
+    #[error("The inode is not a symbolic link")]
+    /// Thrown when the target of an inode that is not a symbolic link is requested
+    NotASymlink,
+}
+
+impl CustomPathFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomPathFileSystemError::GivenError(e) => e.kind(),
+            CustomPathFileSystemError::APIError(e) => a_block_support::api_error_kind(e),
+            CustomPathFileSystemError::InvalidPath(_) => ErrorKind::InvalidFilename,
+            CustomPathFileSystemError::PathComponentNotFound(_) => ErrorKind::NotFound,
+            CustomPathFileSystemError::NotADirectory(_) => ErrorKind::NotADirectory,
+            CustomPathFileSystemError::SymlinkLoop => ErrorKind::Other,
+            CustomPathFileSystemError::SymlinkTargetTooLong | CustomPathFileSystemError::NotASymlink => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl From<CustomPathFileSystemError> for io::Error {
+    fn from(e: CustomPathFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
+}
+
+impl FileSysSupport for CustomPathFileSystem {
+    type Error = CustomPathFileSystemError;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return CustomDirFileSystem::sb_valid(sb);
+    }
+
+    fn mkfs<P: AsRef<std::path::Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        let dir_fs = CustomDirFileSystem::mkfs(path, sb)?;
+        return Ok(CustomPathFileSystem::new(dir_fs))
+    }
+
+    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+        let dir_fs = CustomDirFileSystem::mountfs(dev)?;
+        return Ok(CustomPathFileSystem::new(dir_fs));
+    }
+
+    fn unmountfs(self) -> Device {
+        return self.dir_fs.unmountfs();
+    }
+}
+
+impl BlockSupport for CustomPathFileSystem {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        let block = self.dir_fs.b_get(i)?;
+        return Ok(block);
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        let result = self.dir_fs.b_put(b)?;
+        return Ok(result);
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let res = self.dir_fs.b_free(i)?;
+        return Ok(res)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.dir_fs.b_zero(i)?;
+        return Ok(result);
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        let index = self.dir_fs.b_alloc()?;
+        return Ok(index);
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        let superblock = self.dir_fs.sup_get()?;
+        return Ok(superblock);
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        let result = self.dir_fs.sup_put(sup)?;
+        return Ok(result);
+    }
+}
+
+impl InodeSupport for CustomPathFileSystem {
+    type Inode = Inode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        let inode = self.dir_fs.i_get(i)?;
+        return Ok(inode);
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        let result = self.dir_fs.i_put(ino)?;
+        return Ok(result);
+    }
+
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.dir_fs.i_free(i)?;
+        return Ok(result);
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        let i = self.dir_fs.i_alloc(ft)?;
+        return Ok(i);
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        let result = self.dir_fs.i_trunc(inode)?;
+        return Ok(result);
+    }
+}
+
+impl DirectorySupport for CustomPathFileSystem {
+    fn new_de(inum: u64, name: &str) -> Option<DirEntry> {
+        return CustomDirFileSystem::new_de(inum, name);
+    }
+
+    fn get_name_str(de: &DirEntry) -> String {
+        return CustomDirFileSystem::get_name_str(de);
+    }
+
+    fn set_name_str(de: &mut DirEntry, name: &str) -> Option<()> {
+        return CustomDirFileSystem::set_name_str(de, name);
+    }
+
+    fn dirlookup(&self, inode: &Self::Inode, name: &str) -> Result<(Self::Inode, u64), Self::Error> {
+        let result = self.dir_fs.dirlookup(inode, name)?;
+        return Ok(result);
+    }
+
+    fn dirlink(&mut self, inode: &mut Self::Inode, name: &str, inum: u64) -> Result<u64, Self::Error> {
+        let offset = self.dir_fs.dirlink(inode, name, inum)?;
+        return Ok(offset);
+    }
+}
+
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::path::PathBuf;
+    use cplfs_api::fs::{BlockSupport, DirectorySupport, FileSysSupport, InodeSupport};
+    use cplfs_api::types::{FType, SuperBlock};
+
+    use super::{normalize_path, CustomPathFileSystem, CustomPathFileSystemError, PathError};
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomPathFileSystem>();
+    }
+
+    fn disk_prep_path(name: &str) -> PathBuf {
+        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    }
+
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: 300,
+        nblocks: 11,
+        ninodes: 6,
+        inodestart: 1,
+        ndatablocks: 6,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    fn components(names: &[&str]) -> Vec<String> {
+        return names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_path("/a/./b/../c").unwrap(), components(&["a", "c"]));
+        assert_eq!(normalize_path("/").unwrap(), components(&[]));
+        assert_eq!(normalize_path("/../a/").unwrap(), components(&["a"]));
+        assert_eq!(normalize_path("./../../a/b/..").unwrap(), components(&["..", "..", "a"]));
+        assert_eq!(normalize_path("a/b/../../..").unwrap(), components(&[".."]));
+
+        assert_eq!(normalize_path(""), Err(PathError::EmptyPath));
+        assert_eq!(normalize_path("/a//b"), Err(PathError::EmptyComponent));
+        assert_eq!(normalize_path("/a/b c"), Err(PathError::InvalidComponent("b c".to_string())));
+        let long = "a".repeat(15);
+        assert_eq!(normalize_path(&format!("/{}/x", long)), Err(PathError::ComponentTooLong(long)));
+    }
+
+    #[test]
+    fn resolve() {
+        let path = disk_prep_path("resolve");
+        let mut my_fs = CustomPathFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let a = my_fs.i_alloc(FType::TDir).unwrap();
+        my_fs.dirlink(&mut iroot, "a", a).unwrap();
+        let mut ia = my_fs.i_get(a).unwrap();
+        my_fs.dirlink(&mut ia, "..", 1).unwrap();
+        let b = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut ia, "b", b).unwrap();
+
+        let (ib, inum) = my_fs.resolve_path("/a/b").unwrap();
+        assert_eq!(inum, b);
+        assert_eq!(ib.inum, b);
+        assert_eq!(ib.disk_node.ft, FType::TFile);
+        assert_eq!(my_fs.resolve_path("/a/./b").unwrap().1, b);
+        assert_eq!(my_fs.resolve_path("/a/../a/b").unwrap().1, b);
+        assert_eq!(my_fs.resolve_path("/a/b/..").is_err(), true);
+        assert_eq!(my_fs.resolve_path("/a/..").unwrap().1, 1);
+        assert_eq!(my_fs.resolve_path("/..").unwrap().1, 1);
+        assert_eq!(my_fs.resolve_path("/").unwrap().1, 1);
+        assert_eq!(my_fs.resolve_path("a/b").unwrap().1, b);
+
+        assert!(matches!(my_fs.resolve_path("/a/x"), Err(CustomPathFileSystemError::PathComponentNotFound(name)) if name == "x"));
+        assert!(matches!(my_fs.resolve_path("/x/b"), Err(CustomPathFileSystemError::PathComponentNotFound(name)) if name == "x"));
+        assert!(matches!(my_fs.resolve_path("/a/b/c"), Err(CustomPathFileSystemError::NotADirectory(name)) if name == "c"));
+        assert!(matches!(my_fs.resolve_path("/a//b"), Err(CustomPathFileSystemError::InvalidPath(PathError::EmptyComponent))));
+        let c = my_fs.i_alloc(FType::TDir).unwrap();
+        my_fs.dirlink(&mut iroot, "c", c).unwrap();
+        assert!(matches!(my_fs.resolve_path("/c/.."), Err(CustomPathFileSystemError::PathComponentNotFound(name)) if name == ".."));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn symlinks() {
+        let path = disk_prep_path("symlinks");
+        // 2 inodes fit in a block
+        let sb = SuperBlock {
+            block_size: 300,
+            nblocks: 18,
+            ninodes: 12,
+            inodestart: 1,
+            ndatablocks: 10,
+            bmapstart: 7,
+            datastart: 8,
+        };
+        let mut my_fs = CustomPathFileSystem::mkfs(&path, &sb).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let a = my_fs.i_alloc(FType::TDir).unwrap();
+        my_fs.dirlink(&mut iroot, "a", a).unwrap();
+        let mut ia = my_fs.i_get(a).unwrap();
+        let b = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.dirlink(&mut ia, "b", b).unwrap();
+
+        // an absolute link to the file, and a relative one to the directory it is in
+        let to_b = my_fs.symlink_create("/a/b").unwrap();
+        assert_eq!(my_fs.symlink_target(&my_fs.i_get(to_b).unwrap()).unwrap(), "/a/b");
+        my_fs.dirlink(&mut iroot, "tob", to_b).unwrap();
+        let to_a = my_fs.symlink_create("a").unwrap();
+        my_fs.dirlink(&mut iroot, "toa", to_a).unwrap();
+        assert_eq!(my_fs.resolve_path("/tob").unwrap().1, b);
+        assert_eq!(my_fs.resolve_path("/toa/b").unwrap().1, b);
+        assert_eq!(my_fs.resolve_path("/toa").unwrap().1, a);
+        assert!(matches!(my_fs.symlink_target(&my_fs.i_get(b).unwrap()), Err(CustomPathFileSystemError::NotASymlink)));
+
+        // a link to itself, and two links referring to each other
+        let own = my_fs.symlink_create("/loop").unwrap();
+        my_fs.dirlink(&mut iroot, "loop", own).unwrap();
+        assert!(matches!(my_fs.resolve_path("/loop"), Err(CustomPathFileSystemError::SymlinkLoop)));
+        let ping = my_fs.symlink_create("/pong").unwrap();
+        my_fs.dirlink(&mut iroot, "ping", ping).unwrap();
+        let pong = my_fs.symlink_create("ping").unwrap();
+        my_fs.dirlink(&mut iroot, "pong", pong).unwrap();
+        assert!(matches!(my_fs.resolve_path("/ping/b"), Err(CustomPathFileSystemError::SymlinkLoop)));
+
+        assert!(matches!(my_fs.symlink_create("a//b"), Err(CustomPathFileSystemError::InvalidPath(PathError::EmptyComponent))));
+
+        // a target that needs more blocks than are free leaves nothing allocated
+        let mut taken = Vec::new();
+        while let Ok(index) = my_fs.b_alloc() {
+            taken.push(index);
+        }
+        my_fs.b_free(taken.pop().unwrap()).unwrap();
+        let next = my_fs.i_alloc(FType::TFile).unwrap();
+        my_fs.i_free(next).unwrap();
+        let long = "a/".repeat(150) + "a";
+        assert!(my_fs.symlink_create(&long).is_err());
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), next);
+        assert!(my_fs.b_alloc().is_ok());
+        assert!(my_fs.b_alloc().is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+}
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "d", feature = "all")))]
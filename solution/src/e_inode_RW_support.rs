@@ -19,16 +19,24 @@
 //! ...
 //!
 
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
 use thiserror::Error;
-use cplfs_api::{controller::Device, error_given::{self, APIError}, fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport}, types::{Block, Buffer, Inode, SuperBlock}};
+use cplfs_api::{controller::Device, error_given::{self, APIError}, fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport}, types::{Block, Buffer, DIRECT_POINTERS, FType, Inode, SuperBlock}};
 
-use crate::b_inode_support::{self, CustomInodeFileSystem};
+use crate::b_inode_support::{self, Clock, CustomInodeFileSystem, InodeGuard, InodeStat, INODE_FLAG_INLINE};
+use crate::a_block_support::{self, AllocMode, CacheStats, IoHook, OpStats};
 
 /// Type of my file system
 pub type FSName = CustomInodeRWFileSystem;
 
+/// Number of bytes an inline inode can hold, i.e. the space taken up by its block pointers
+pub const INLINE_CAPACITY: u64 = DIRECT_POINTERS * 8;
+
 // Custom type
 /// Custom file system data type
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
 pub struct CustomInodeRWFileSystem {
     inode_fs: CustomInodeFileSystem,
 }
@@ -38,7 +46,481 @@ impl CustomInodeRWFileSystem {
     /// Create a new InodeCustomFileSystem given a BlockCustomFileSystem
     pub fn new(inodefs: CustomInodeFileSystem) -> CustomInodeRWFileSystem {
         CustomInodeRWFileSystem {  inode_fs: inodefs }
-    }  
+    }
+
+    /// Cap the size of the given `inode` at `max` bytes, and persist this cap on disk.
+    /// Writes that would make the file grow beyond `max` are rejected by `i_write`, even if the file still has room for more blocks.
+    /// A `max` of 0 removes the cap again.
+    pub fn i_set_max_size(&mut self, inode: &mut Inode, max: u64) -> Result<(), CustomInodeRWFileSystemError> {
+        inode.disk_node.max_size = max;
+        self.i_put(inode)?;
+        return Ok(())
+    }
+
+    /// Variant of `i_write` that additionally returns how many bytes were written to each block,
+    /// as pairs of the block number on disk and the byte count, in the order the blocks were written.
+    pub fn i_write_detailed(&mut self, inode: &mut Inode, buf: &Buffer, off: u64, n: u64) -> Result<Vec<(u64, u64)>, CustomInodeRWFileSystemError> {
+        // The entries of a directory are only written by the directory layer, raw bytes would corrupt them
+        if inode.disk_node.ft == FType::TDir {
+            return Err(CustomInodeRWFileSystemError::CannotWriteDirectory);
+        }
+        // refuse before `inode` gets touched
+        self.inode_fs.check_writable()?;
+
+        // returns an error and does not read anything if index falls further outside of the file's bounds. 
+        if off > inode.disk_node.size {
+            return Err(CustomInodeRWFileSystemError::IndexOutOfBounds);
+        }
+
+        // Returns an error if buf cannot hold at least n bytes of data.
+        if buf.len() < n {
+            return Err(CustomInodeRWFileSystemError::BufTooSmall);
+        }
+
+        // If the write would make the inode exceed its maximum possible size, do nothing and return an error.
+        let sb = self.sup_get()?;
+        if off + n > inode.disk_node.direct_blocks.len() as u64 * sb.block_size {
+            return Err(CustomInodeRWFileSystemError::WriteTooLarge);
+        }
+        // The inode might be capped at a smaller size than what its blocks can hold
+        if inode.disk_node.max_size != 0 && off + n > inode.disk_node.max_size {
+            return Err(CustomInodeRWFileSystemError::QuotaExceeded);
+        }
+        // set before any of the paths below writes the inode back
+        self.inode_fs.touch(inode);
+
+        if inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+            let mut contents = inline_contents(inode);
+            if off + n <= INLINE_CAPACITY {
+                buf.read_data(&mut contents[off as usize..(off + n) as usize], 0)?;
+                set_inline_contents(inode, &contents);
+                if off + n > inode.disk_node.size {
+                    inode.disk_node.size = off + n;
+                }
+                self.i_put(inode)?;
+                return Ok(Vec::new())
+            }
+            // the file outgrows its inline storage, so move the current contents into data blocks first.
+            // This happens on a copy, so `inode` keeps its inline contents if it fails.
+            let size = inode.disk_node.size;
+            let mut converted = inode.clone();
+            converted.disk_node.flags &= !INODE_FLAG_INLINE;
+            converted.disk_node.direct_blocks = [0; DIRECT_POINTERS as usize];
+            converted.disk_node.size = 0;
+            if let Err(e) = self.i_write_detailed(&mut converted, &Buffer::new(contents.into_boxed_slice()), 0, size) {
+                // every block of the copy was allocated by the failed write
+                for &block in converted.disk_node.direct_blocks.iter().filter(|&&block| block != 0) {
+                    let _ = self.b_free(block - sb.datastart);
+                }
+                let _ = self.i_put(inode);
+                return Err(e)
+            }
+            *inode = converted;
+            self.i_put(inode)?;
+        }
+
+        // Check every block slot this write touches before changing anything.
+        // Slots owned by the file must be holes (0) or point at an allocated data block.
+        // Other slots (past the current blocks of the file, unless it kept them when truncated) are not owned by it,
+        // so anything but 0 there is a leftover of some other file, and writing through it would overwrite that file.
+        let current_amount_blocks = self.inode_fs.owned_block_slots(inode);
+        let needed_amount_blocks = (off + n).div_ceil(sb.block_size);
+        for index in (off / sb.block_size)..needed_amount_blocks {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if element == 0 {
+                continue
+            }
+            let in_range = element >= sb.datastart && element < sb.datastart + sb.ndatablocks;
+            if index >= current_amount_blocks || !in_range || !self.b_is_allocated(element - sb.datastart)? {
+                return Err(CustomInodeRWFileSystemError::StaleBlockPointer);
+            }
+        }
+
+        // if necessary, start allocating extra blocks to expand the file (or fill its holes) and continue writing into the new blocks.
+        for index in (off / sb.block_size)..needed_amount_blocks {
+            if inode.disk_node.direct_blocks[index as usize] == 0 {
+                let new_block_index = sb.datastart + self.b_alloc()?;
+                inode.disk_node.direct_blocks[index as usize] = new_block_index;
+            }
+        }
+        if off + n > inode.disk_node.size {
+            inode.disk_node.size = off + n;
+        }
+
+        // write changes back
+        self.i_put(inode)?;
+        let file_blocks = inode.disk_node.direct_blocks;
+        let nb_selected_blocks = (inode.disk_node.size as f64/sb.block_size as f64).ceil(); 
+        let mut buf_offset = 0;
+        let mut written = Vec::new();
+        for index in 0..(nb_selected_blocks as u64) {
+            // skip the blocks that don't contain bytes we need
+            if (index +1)*sb.block_size < off {
+                continue
+            }
+            // we only want to read n bytes, also stop if buf is full
+            if buf_offset >= n {
+                break
+            }
+            let element = file_blocks[index as usize];
+            if !(element == 0) {
+                // b-get: read the nth block of the entire disk and return it
+                let mut block = self.b_get(element)?;
+                let block_start = buf_offset;
+                for byte_index in 0..(sb.block_size)  {
+                    if buf_offset >= n  {
+                        break
+                    };
+                    // write only if we are over offset
+                    if index * sb.block_size + byte_index >= off {
+                        let mut byte: [u8;1] = [0];
+                        // read the info out of the buffer into a byte
+                        buf.read_data(&mut byte, buf_offset)?;
+                        // write the byte into the inode
+                        match block.write_data(&byte, byte_index) {
+                            // reached end of the buf, so stop adding
+                            Err(APIError::BlockInput("Trying to write beyond the bounds of the block",)) => break,
+                            // not specified what to do in other cases
+                            Err(_) => (),
+                            Ok(_) => ()
+                        }
+                        buf_offset += 1;
+                    }
+                }
+                // all bytes for this block are in place, so it is written to disk just once
+                if buf_offset > block_start {
+                    self.b_put(&block)?;
+                    written.push((element, buf_offset - block_start));
+                }
+            }
+        }
+        return Ok(written)
+    }
+
+    /// Write `n` bytes of `buf` into the given `inode` at offset `off` like `i_write`, but also allow `off` to lie past the end of the file.
+    /// The gap between the old end of the file and `off` reads back as zeroes: it is written explicitly,
+    /// so bytes left behind in a block the file kept (e.g. after `i_trunc_to`) do not show up in it.
+    pub fn i_write_sparse(&mut self, inode: &mut Inode, buf: &Buffer, off: u64, n: u64) -> Result<(), CustomInodeRWFileSystemError> {
+        let size = inode.disk_node.size;
+        if off > size {
+            // check the write as a whole first, so that a failing write does not leave a grown file behind
+            let sb = self.sup_get()?;
+            if off + n > inode.disk_node.direct_blocks.len() as u64 * sb.block_size {
+                return Err(CustomInodeRWFileSystemError::WriteTooLarge);
+            }
+            if inode.disk_node.max_size != 0 && off + n > inode.disk_node.max_size {
+                return Err(CustomInodeRWFileSystemError::QuotaExceeded);
+            }
+            if buf.len() < n {
+                return Err(CustomInodeRWFileSystemError::BufTooSmall);
+            }
+            self.i_write(inode, &Buffer::new_zero(off - size), size, off - size)?;
+        }
+        self.i_write(inode, buf, off, n)?;
+        return Ok(())
+    }
+
+    /// Replace the contents of the file `dst_inum` by those of the file `src_inum`, and return the number of bytes copied.
+    /// Both inodes have to be regular files.
+    /// Fails with `WriteTooLarge`, `QuotaExceeded` or `NoFreeDataBlock` before `dst_inum` is changed if the copy would not fit in it.
+    pub fn i_copy(&mut self, src_inum: u64, dst_inum: u64) -> Result<u64, CustomInodeRWFileSystemError> {
+        let src = self.i_get(src_inum)?;
+        let mut dst = self.i_get(dst_inum)?;
+        if !(src.disk_node.ft == FType::TFile && dst.disk_node.ft == FType::TFile) {
+            return Err(CustomInodeRWFileSystemError::InodeWrongType);
+        }
+        let contents = self.i_read_all(&src)?;
+        let size = contents.len() as u64;
+        let sb = self.sup_get()?;
+        if size > DIRECT_POINTERS * sb.block_size {
+            return Err(CustomInodeRWFileSystemError::WriteTooLarge);
+        }
+        if dst.disk_node.max_size != 0 && size > dst.disk_node.max_size {
+            return Err(CustomInodeRWFileSystemError::QuotaExceeded);
+        }
+        // an inline file stays inline as long as the copy fits, and the blocks of `dst` are freed by the truncate below
+        let needed = if dst.disk_node.flags & INODE_FLAG_INLINE != 0 && size <= INLINE_CAPACITY { 0 } else { size.div_ceil(sb.block_size) };
+        let owned = dst.disk_node.direct_blocks[..self.inode_fs.owned_block_slots(&dst) as usize].iter().filter(|&&b| b != 0).count() as u64;
+        if needed > self.count_free_blocks()? + owned {
+            return Err(no_free_data_block());
+        }
+        self.i_trunc(&mut dst)?;
+        self.i_write_slice(&mut dst, &contents, 0)?;
+        return Ok(contents.len() as u64)
+    }
+
+    /// Write all of `data` into the given `inode` at offset `off`, like `i_write` with a buffer holding `data`
+    pub fn i_write_slice(&mut self, inode: &mut Inode, data: &[u8], off: u64) -> Result<(), CustomInodeRWFileSystemError> {
+        self.i_write(inode, &Buffer::new(Box::from(data)), off, data.len() as u64)?;
+        return Ok(())
+    }
+
+    /// Write `n` bytes of `buf` at the end of the given `inode`, growing it like `i_write` does.
+    /// Returns the offset at which the bytes were written, i.e. the size of the file before the write.
+    pub fn i_append(&mut self, inode: &mut Inode, buf: &Buffer, n: u64) -> Result<u64, CustomInodeRWFileSystemError> {
+        let off = inode.disk_node.size;
+        self.i_write(inode, buf, off, n)?;
+        return Ok(off)
+    }
+
+    /// Switch the given empty regular file to inline storage: its contents are kept in the bytes of its block pointers,
+    /// as long as they fit in `INLINE_CAPACITY` bytes. Once a write makes the file outgrow this capacity,
+    /// the file is converted to regular block storage.
+    pub fn i_set_inline(&mut self, inode: &mut Inode) -> Result<(), CustomInodeRWFileSystemError> {
+        if !(inode.disk_node.ft == FType::TFile) {
+            return Err(CustomInodeRWFileSystemError::InlineNotSupported);
+        }
+        if inode.disk_node.size != 0 || inode.disk_node.direct_blocks.iter().any(|b| *b != 0) {
+            return Err(CustomInodeRWFileSystemError::InlineNotSupported);
+        }
+        inode.disk_node.flags |= INODE_FLAG_INLINE;
+        self.i_put(inode)?;
+        return Ok(())
+    }
+
+    /// Read the entire contents of the given `inode` into a new vector, using `i_read`
+    pub fn i_read_all(&self, inode: &Inode) -> Result<Vec<u8>, CustomInodeRWFileSystemError> {
+        return self.i_read_to_vec(inode, 0, inode.disk_node.size)
+    }
+
+    /// Read up to `n` bytes from the given `inode`, starting at offset `off`, into a new vector, using `i_read`.
+    /// The vector holds only the bytes that were actually read, so it is shorter than `n` when the end of the file is reached.
+    pub fn i_read_to_vec(&self, inode: &Inode, off: u64, n: u64) -> Result<Vec<u8>, CustomInodeRWFileSystemError> {
+        let mut buf = Buffer::new_zero(n);
+        let read = self.i_read(inode, &mut buf, off, n)?;
+        let mut contents = buf.contents_as_ref().to_vec();
+        contents.truncate(read as usize);
+        return Ok(contents)
+    }
+
+    /// Write the contents of inode `inum` to the file `dest` on the host, creating or truncating it.
+    /// Returns the number of bytes written. Directories and free inodes cannot be exported.
+    pub fn export_file<P: AsRef<Path>>(&self, inum: u64, dest: P) -> Result<u64, CustomInodeRWFileSystemError> {
+        let inode = self.i_get(inum)?;
+        if inode.disk_node.ft == FType::TDir || inode.disk_node.ft == FType::TFree {
+            return Err(CustomInodeRWFileSystemError::NotExportable);
+        }
+        let contents = self.i_read_all(&inode)?;
+        let mut file = std::fs::File::create(dest)?;
+        file.write_all(&contents)?;
+        return Ok(contents.len() as u64)
+    }
+
+    /// Copy the file `src` on the host into a newly allocated regular file, and return the inode number of that file.
+    /// Fails with `WriteTooLarge` without allocating anything if the host file does not fit in the direct blocks of an inode,
+    /// and with `NoFreeDataBlock` if there are not enough free blocks to hold it.
+    /// If writing the file fails anyway, its inode and blocks are released again.
+    pub fn import_file<P: AsRef<Path>>(&mut self, src: P) -> Result<u64, CustomInodeRWFileSystemError> {
+        let contents = std::fs::read(src)?;
+        let sb = self.sup_get()?;
+        let n = contents.len() as u64;
+        if n > DIRECT_POINTERS * sb.block_size {
+            return Err(CustomInodeRWFileSystemError::WriteTooLarge);
+        }
+        if n.div_ceil(sb.block_size) > self.count_free_blocks()? {
+            return Err(no_free_data_block());
+        }
+        let inum = self.i_alloc(FType::TFile)?;
+        let mut inode = self.i_get(inum)?;
+        if let Err(e) = self.i_write(&mut inode, &Buffer::new(contents.into_boxed_slice()), 0, n) {
+            // the blocks allocated before the write failed are not yet covered by the size of the new inode
+            inode.disk_node.size = n;
+            // best effort, so the error of the write is the one reported
+            let _ = self.i_trunc(&mut inode);
+            let _ = self.i_free(inum);
+            return Err(e);
+        }
+        return Ok(inum)
+    }
+
+    /// Read the entire contents of the given `inode` into `out`, replacing whatever `out` held before.
+    /// Unlike `i_read`, this copies the data blocks of the file as a whole, rather than byte per byte.
+    /// Blocks the file does not point to read as zeroes.
+    pub fn i_read_blocks_into(&self, inode: &Inode, out: &mut Vec<u8>) -> Result<(), CustomInodeRWFileSystemError> {
+        if inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+            *out = inline_contents(inode);
+            out.truncate(inode.disk_node.size as usize);
+            return Ok(())
+        }
+        let sb = self.sup_get()?;
+        check_direct_only(inode, &sb)?;
+        let nb_selected_blocks = (inode.disk_node.size as f64 / sb.block_size as f64).ceil() as u64;
+        out.clear();
+        out.reserve((nb_selected_blocks * sb.block_size) as usize);
+        for index in 0..nb_selected_blocks {
+            let element = inode.disk_node.direct_blocks[index as usize];
+            if element == 0 {
+                out.resize(out.len() + sb.block_size as usize, 0);
+            } else {
+                out.extend_from_slice(self.b_get(element)?.contents_as_ref());
+            }
+        }
+        out.truncate(inode.disk_node.size as usize);
+        return Ok(())
+    }
+
+    /// Truncate the given `inode` to size 0, but keep its blocks allocated for the next write to reuse
+    pub fn i_trunc_keep_blocks(&mut self, inode: &mut Inode) -> Result<(), CustomInodeRWFileSystemError> {
+        self.inode_fs.i_trunc_keep_blocks(inode)?;
+        return Ok(())
+    }
+
+    /// Check whether the block with index `i` in the data region is currently allocated
+    pub fn b_is_allocated(&self, i: u64) -> Result<bool, CustomInodeRWFileSystemError> {
+        let allocated = self.inode_fs.b_is_allocated(i)?;
+        return Ok(allocated)
+    }
+
+    /// Set the order in which both `i_alloc` and `b_alloc` scan for a free inode or data block
+    pub fn set_alloc_mode(&mut self, mode: AllocMode) {
+        self.inode_fs.set_alloc_mode(mode);
+    }
+
+    /// Read every allocated data block and return the numbers (on disk) of the blocks that cannot be read back intact.
+    pub fn scrub(&self) -> Result<Vec<u64>, CustomInodeRWFileSystemError> {
+        let failed = self.inode_fs.scrub()?;
+        return Ok(failed)
+    }
+
+    /// Install a callback that is invoked on every block read and write, or remove it again by passing `None`
+    pub fn set_io_hook(&mut self, hook: Option<IoHook>) {
+        self.inode_fs.set_io_hook(hook);
+    }
+
+    /// Checksum (CRC-64) over the contents of every block on the device, in order
+    pub fn fs_checksum(&self) -> Result<u64, CustomInodeRWFileSystemError> {
+        let checksum = self.inode_fs.fs_checksum()?;
+        return Ok(checksum)
+    }
+
+    /// Fetch the inode with number `inum` for editing. The returned guard writes it back when it goes out of scope.
+    pub fn i_edit(&mut self, inum: u64) -> Result<InodeGuard<'_>, CustomInodeRWFileSystemError> {
+        let guard = self.inode_fs.i_edit(inum)?;
+        return Ok(guard);
+    }
+
+    /// Check that every block pointer of every inode in use lies in the data region
+    pub fn validate_no_metadata_overlap(&self) -> Result<(), CustomInodeRWFileSystemError> {
+        self.inode_fs.validate_no_metadata_overlap()?;
+        return Ok(());
+    }
+
+    /// Read the block with number `i`, sharing a single cached copy between callers, see `CustomBlockFileSystem::b_get_ref`
+    pub fn b_get_ref(&self, i: u64) -> Result<Arc<Block>, CustomInodeRWFileSystemError> {
+        let block = self.inode_fs.b_get_ref(i)?;
+        return Ok(block);
+    }
+
+    /// Number of free data blocks following the highest allocated one, without changing the file system
+    pub fn trim_trailing_free(&self) -> Result<u64, CustomInodeRWFileSystemError> {
+        let free = self.inode_fs.trim_trailing_free()?;
+        return Ok(free);
+    }
+
+    /// Number of block reads served from the block cache and from the device
+    pub fn cache_stats(&self) -> CacheStats {
+        return self.inode_fs.cache_stats();
+    }
+
+    /// Change the number of blocks the block cache holds. 0 disables the cache.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.inode_fs.set_cache_capacity(capacity);
+    }
+
+    /// Number of calls to the block and inode operations, see `CustomInodeFileSystem::stats`
+    pub fn stats(&self) -> OpStats {
+        return self.inode_fs.stats();
+    }
+
+    /// Start counting the calls to the block and inode operations from 0 again
+    pub fn reset_stats(&mut self) {
+        self.inode_fs.reset_stats();
+    }
+
+    /// Number of free inodes, not counting inode 0 as it is never handed out
+    pub fn count_free_inodes(&self) -> Result<u64, CustomInodeRWFileSystemError> {
+        let free = self.inode_fs.count_free_inodes()?;
+        return Ok(free)
+    }
+
+    /// Number of data blocks that are free according to the bitmap
+    pub fn count_free_blocks(&self) -> Result<u64, CustomInodeRWFileSystemError> {
+        let free = self.inode_fs.count_free_blocks()?;
+        return Ok(free)
+    }
+
+    /// Truncate `inode` to `new_size` bytes, freeing only the blocks beyond it, see `CustomInodeFileSystem::i_trunc_to`
+    pub fn i_trunc_to(&mut self, inode: &mut Inode, new_size: u64) -> Result<(), CustomInodeRWFileSystemError> {
+        self.inode_fs.i_trunc_to(inode, new_size)?;
+        return Ok(())
+    }
+
+    /// Addresses held by the indirect block of `inode`, see `CustomInodeFileSystem::indirect_addresses`
+    pub fn indirect_addresses(&self, inode: &Inode) -> Result<Vec<u64>, CustomInodeRWFileSystemError> {
+        let addresses = self.inode_fs.indirect_addresses(inode)?;
+        return Ok(addresses)
+    }
+
+    /// Metadata of the inode with number `inum`, see `CustomInodeFileSystem::i_stat`
+    pub fn i_stat(&self, inum: u64) -> Result<InodeStat, CustomInodeRWFileSystemError> {
+        let stat = self.inode_fs.i_stat(inum)?;
+        return Ok(stat)
+    }
+
+    /// Replace the source of the current time used for the timestamps of inodes, see `CustomInodeFileSystem::set_clock`
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.inode_fs.set_clock(clock);
+    }
+
+    /// Set both the modification and the change time of `inode` to now, in memory only
+    pub fn touch(&self, inode: &mut Inode) {
+        self.inode_fs.touch(inode);
+    }
+
+    /// Mount the file system on `dev` like `mountfs`, but read-only, see `CustomBlockFileSystem::mountfs_ro`
+    pub fn mountfs_ro(dev: Device) -> Result<Self, CustomInodeRWFileSystemError> {
+        let inode_fs = CustomInodeFileSystem::mountfs_ro(dev)?;
+        return Ok(CustomInodeRWFileSystem::new(inode_fs));
+    }
+
+    /// Whether this file system was mounted read-only, through `mountfs_ro`
+    pub fn is_readonly(&self) -> bool {
+        return self.inode_fs.is_readonly();
+    }
+
+    /// Write back any state that is only kept in memory to the device, see `CustomBlockFileSystem::flush`
+    pub fn flush(&mut self) -> Result<(), CustomInodeRWFileSystemError> {
+        self.inode_fs.flush()?;
+        return Ok(())
+    }
+}
+
+/// The contents of an inline inode, i.e. the bytes of its block pointers
+fn inline_contents(inode: &Inode) -> Vec<u8> {
+    return inode.disk_node.direct_blocks.iter().flat_map(|b| b.to_le_bytes().to_vec()).collect()
+}
+
+/// Store `contents` (`INLINE_CAPACITY` bytes) in the block pointers of an inline inode
+fn set_inline_contents(inode: &mut Inode, contents: &[u8]) {
+    for (slot, chunk) in inode.disk_node.direct_blocks.iter_mut().zip(contents.chunks(8)) {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(chunk);
+        *slot = u64::from_le_bytes(bytes);
+    }
+}
+
+/// Fail with `NeedsIndirectBlocks` if the contents of `inode` do not all lie in its direct blocks,
+/// i.e. it has an indirect block or is larger than its direct blocks can hold
+fn check_direct_only(inode: &Inode, sb: &SuperBlock) -> Result<(), CustomInodeRWFileSystemError> {
+    if !(inode.disk_node.indirect_block == 0 && inode.disk_node.size <= DIRECT_POINTERS * sb.block_size) {
+        return Err(CustomInodeRWFileSystemError::NeedsIndirectBlocks);
+    }
+    return Ok(())
+}
+
+/// The error `b_alloc` fails with when there are no free data blocks left
+fn no_free_data_block() -> CustomInodeRWFileSystemError {
+    return b_inode_support::CustomInodeFileSystemError::from(a_block_support::CustomBlockFileSystemError::NoFreeDataBlock).into()
 }
 
 #[derive(Error, Debug)]
@@ -62,7 +544,60 @@ pub enum CustomInodeRWFileSystemError {
     WriteTooLarge,
     #[error("Inode has no room for extra block")]
     /// Inode has no room for extra block
-    InodeBlocksFull
+    InodeBlocksFull,
+    #[error("Writing the contents of the buffer at the given offset would make the inode exceed its maximum allowed size")]
+    /// Writing the contents of the provided buffer starting at
+    /// the given offset would make the inode grow beyond the cap set by `i_set_max_size`
+    QuotaExceeded,
+    #[error("The inode points at a block that does not belong to it")]
+    /// The inode has a block pointer that is out of range, points at a free block,
+    /// or lies beyond the size of the inode, so writing through it could overwrite another file
+    StaleBlockPointer,
+    #[error("Only empty regular files can be stored inline")]
+    /// The inode cannot be switched to inline storage, as it is not an empty regular file
+    InlineNotSupported,
+    #[error("Directories cannot be written to directly")]
+    /// The inode to write to is a directory, whose contents are managed by the directory layer
+    CannotWriteDirectory,
+    #[error("The file uses indirect blocks, which this layer cannot read")]
+    /// The inode has an indirect block or is larger than its direct blocks can hold, so it has to be read through the indirect layer
+    NeedsIndirectBlocks,
+    #[error("The inode is not a regular file")]
+    /// The inode is of a different type than the operation requires
+    InodeWrongType,
+    #[error("Only files can be exported to the host")]
+    /// The inode to export is a directory or a free inode
+    NotExportable,
+    #[error("Host I/O error")]
+    /// Reading or writing a file on the host failed
+    HostIoError(#[from] std::io::Error)
+}
+
+impl CustomInodeRWFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomInodeRWFileSystemError::GivenError(e) => e.kind(),
+            CustomInodeRWFileSystemError::APIError(e) => a_block_support::api_error_kind(e),
+            CustomInodeRWFileSystemError::IndexOutOfBounds
+            | CustomInodeRWFileSystemError::BufTooSmall
+            | CustomInodeRWFileSystemError::NotExportable
+            | CustomInodeRWFileSystemError::InodeWrongType => ErrorKind::InvalidInput,
+            CustomInodeRWFileSystemError::WriteTooLarge | CustomInodeRWFileSystemError::InodeBlocksFull => ErrorKind::FileTooLarge,
+            CustomInodeRWFileSystemError::QuotaExceeded => ErrorKind::QuotaExceeded,
+            CustomInodeRWFileSystemError::StaleBlockPointer => ErrorKind::InvalidData,
+            CustomInodeRWFileSystemError::InlineNotSupported
+            | CustomInodeRWFileSystemError::NeedsIndirectBlocks => ErrorKind::Unsupported,
+            CustomInodeRWFileSystemError::CannotWriteDirectory => ErrorKind::IsADirectory,
+            CustomInodeRWFileSystemError::HostIoError(e) => e.kind(),
+        }
+    }
+}
+
+impl From<CustomInodeRWFileSystemError> for io::Error {
+    fn from(e: CustomInodeRWFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
 }
 
 
@@ -163,146 +698,65 @@ impl InodeRWSupport for CustomInodeRWFileSystem {
         if off > inode.disk_node.size {
             return Err(CustomInodeRWFileSystemError::IndexOutOfBounds);
         }
+        if inode.disk_node.flags & INODE_FLAG_INLINE != 0 {
+            let count = n.min(buf.len()).min(inode.disk_node.size - off);
+            buf.write_data(&inline_contents(inode)[off as usize..(off + count) as usize], 0)?;
+            return Ok(count);
+        }
 
         let superblock = self.sup_get()?;
+        check_direct_only(inode, &superblock)?;
         let file_blocks = inode.disk_node.direct_blocks;
-        let nb_selected_blocks = (inode.disk_node.size as f64/superblock.block_size as f64).ceil(); 
+        let nb_selected_blocks = ((inode.disk_node.size as f64/superblock.block_size as f64).ceil() as u64).min(DIRECT_POINTERS);
         let mut buf_offset = 0;
-        for index in 0..(nb_selected_blocks as u64) {
-            // skip the blocks that don't contain bytes we need
-            if (index +1)*superblock.block_size < off {
+        for index in 0..nb_selected_blocks {
+            // skip the blocks that end at or before the offset
+            if (index +1)*superblock.block_size <= off {
                 continue
             }
             // we only want to read n bytes, also stop if buf is full
             if buf_offset >= n || buf_offset >= buf.len() {
                 break
             }
-            let element = file_blocks[index as usize];
-            if !(element == 0) {
-                // b-get: read the nth block of the entire disk and return it
-                let block = self.b_get(element)?;
-                //let mut offset = 0;
-                for byte_index in 0..(superblock.block_size) {
-                    // we only want to read n bytes and stop when end of file is reached
-                    if buf_offset >= n || buf_offset >= inode.disk_node.size {
-                        break
-                    };
-                    // start reading from byte offset off in the inode 
-                    if index * superblock.block_size + byte_index >= off {
-                        let mut byte: [u8;1] = [0];
-                        block.read_data(&mut byte, byte_index)?;
-                        // If buf cannot hold n bytes of data, reads until buf is full instead.
-                        match buf.write_data(&byte, buf_offset) {
-                            // reached end of the buf stop adding
-                            Err(APIError::BlockInput("Trying to write beyond the bounds of the block",)) => break,
-                            // not specified what to do in other cases
-                            Err(_) => (),
-                            Ok(_) => ()
-                        }
-                        buf_offset += 1;
-                    }               
+            // start reading from byte offset off in the inode
+            let start = off.saturating_sub(index * superblock.block_size).min(superblock.block_size);
+            // we only want to read n bytes, stop when end of file is reached,
+            // and if buf cannot hold n bytes of data, read until buf is full instead
+            let count = (superblock.block_size - start)
+                .min(n - buf_offset)
+                .min(inode.disk_node.size.saturating_sub(off + buf_offset))
+                .min(buf.len() - buf_offset);
+            if count > 0 {
+                let mut bytes = vec![0; count as usize];
+                // a hole in the file reads as zeroes
+                let element = file_blocks[index as usize];
+                if !(element == 0) {
+                    self.b_get_ref(element)?.read_data(&mut bytes, start)?;
                 }
+                buf.write_data(&bytes, buf_offset)?;
+                buf_offset += count;
             }
         }
         return Ok(buf_offset);
     }
 
     fn i_write(&mut self,inode: &mut Self::Inode,buf: &cplfs_api::types::Buffer,off: u64, n: u64) -> Result<(), Self::Error> {
-        // returns an error and does not read anything if index falls further outside of the file's bounds. 
-        if off > inode.disk_node.size {
-            return Err(CustomInodeRWFileSystemError::IndexOutOfBounds);
-        }
+        self.i_write_detailed(inode, buf, off, n)?;
+        return Ok(())
+    }
+}
 
-        // Returns an error if buf cannot hold at least n bytes of data.
-        if buf.len() < n {
-            return Err(CustomInodeRWFileSystemError::BufTooSmall);
-        }
 
-        // If the write would make the inode exceed its maximum possible size, do nothing and return an error.
-        let sb = self.sup_get()?;
-        if off + n > inode.disk_node.direct_blocks.len() as u64 * sb.block_size {
-            return Err(CustomInodeRWFileSystemError::WriteTooLarge);
-        }
 
-        // Check if the provided inode is large enough, otherwise extend it 
-        // if necessary, start allocating extra blocks to expand the file and continue writing into the new blocks.
-        let current_amount_blocks = (inode.disk_node.size as f64/sb.block_size as f64).ceil();
-        if off + n > (current_amount_blocks as u64 * sb.block_size) {
-            let remaining_bytes = (off + n) - inode.disk_node.size;
-            let amount_of_new_blocks = (remaining_bytes as f64 / sb.block_size as f64).ceil();
-            for i in 0..amount_of_new_blocks as u64 {
-                let index = current_amount_blocks + i as f64;
-                if index == inode.disk_node.direct_blocks.len() as f64{
-                    return Err(CustomInodeRWFileSystemError::InodeBlocksFull);
-                }
-                let new_block_index = sb.datastart + self.b_alloc()?;
-                inode.disk_node.direct_blocks[index as usize] = new_block_index;
-            }
-            inode.disk_node.size = off + n;
-            self.i_put(inode)?;
-        }
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::{path::PathBuf, sync::{Arc, Mutex, atomic::{AtomicU64, Ordering}}};
+    use cplfs_api::{fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport}, types::{Buffer, FType, InodeLike, SuperBlock}};
 
-        // if we have enough blocks but they are not all fully used yet
-        // this if is only entered when we already have a partly
-        // unused block assinged to an inode
-        if off + n <  (current_amount_blocks as u64 * sb.block_size) && (off + n) > inode.disk_node.size { 
-            inode.disk_node.size  = off + n;
-        }
-
-        // write changes back
-        self.i_put(inode)?;
-        let file_blocks = inode.disk_node.direct_blocks;
-        let nb_selected_blocks = (inode.disk_node.size as f64/sb.block_size as f64).ceil(); 
-        let mut buf_offset = 0;
-        for index in 0..(nb_selected_blocks as u64) {
-            // skip the blocks that don't contain bytes we need
-            if (index +1)*sb.block_size < off {
-                continue
-            }
-            // we only want to read n bytes, also stop if buf is full
-            if buf_offset >= n {
-                break
-            }
-            let element = file_blocks[index as usize];
-            if !(element == 0) {
-                // b-get: read the nth block of the entire disk and return it
-                let mut block = self.b_get(element)?;
-                for byte_index in 0..(sb.block_size)  {
-                    if buf_offset >= n  {
-                        break
-                    };
-                    // write only if we are over offset
-                    if index * sb.block_size + byte_index >= off {
-                        let mut byte: [u8;1] = [0];
-                        // read the info out of the buffer into a byte
-                        buf.read_data(&mut byte, buf_offset)?;
-                        // write the byte into the inode
-                        match block.write_data(&byte, byte_index) {
-                            // reached end of the buf, so stop adding
-                            Err(APIError::BlockInput("Trying to write beyond the bounds of the block",)) => break,
-                            // not specified what to do in other cases
-                            Err(_) => (),
-                            Ok(_) => ()
-                        }
-                        buf_offset += 1;
-                    }
-                    self.b_put(&block)?;
-                }
-            }
-        }
-        return Ok(())
-    }
-}
-
-
-
-#[cfg(test)]
-#[path = "../../api/fs-tests"]
-mod test_with_utils {
-    use std::path::PathBuf;
-    use cplfs_api::{fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport}, types::{Buffer, FType, InodeLike, SuperBlock}};
-
-    use super::CustomInodeRWFileSystem;
+    use super::{CustomInodeRWFileSystem, CustomInodeRWFileSystemError, INLINE_CAPACITY};
+    use crate::b_inode_support::CustomInodeFileSystemError;
+    use crate::a_block_support::{BlockIoEvent, BlockIoOp, CustomBlockFileSystemError};
 
     fn disk_prep_path(name: &str) -> PathBuf {
         utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
@@ -322,6 +776,12 @@ mod test_with_utils {
     #[path = "utils.rs"]
     mod utils;
 
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomInodeRWFileSystem>();
+    }
+
     #[test]
     fn readi_buff_small() {
         let path = disk_prep_path("readi_buff_small");
@@ -353,6 +813,757 @@ mod test_with_utils {
         let dev = my_fs.unmountfs();
         utils::disk_destruct(dev);
     }
+
+    #[test]
+    fn writei_max_size() {
+        let path = disk_prep_path("writei_max_size");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        // cap the file at 1.5 blocks
+        my_fs.i_set_max_size(&mut i1, BLOCK_SIZE + BLOCK_SIZE / 2).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap(), i1);
+
+        let buf = Buffer::new(vec![7; 500].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 400).unwrap();
+        my_fs.i_write(&mut i1, &buf, 400, 50).unwrap();
+        assert_eq!(i1.get_size(), 450);
+        // one byte past the cap
+        assert!(my_fs.i_write(&mut i1, &buf, 400, 51).is_err());
+        assert!(my_fs.i_write(&mut i1, &buf, 0, 500).is_err());
+        assert_eq!(my_fs.i_get(inum).unwrap().get_size(), 450);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn readi_blocks_into() {
+        let path = disk_prep_path("readi_blocks_into");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..700).map(|i| (i % 251) as u8).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 700).unwrap();
+
+        // whatever was in the vector before is dropped
+        let mut out = vec![9; 2000];
+        my_fs.i_read_blocks_into(&i1, &mut out).unwrap();
+        assert_eq!(out, my_fs.i_read_all(&i1).unwrap());
+        assert_eq!(out, contents);
+
+        let empty_inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let empty = my_fs.i_get(empty_inum).unwrap();
+        my_fs.i_read_blocks_into(&empty, &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn writei_stale_pointers() {
+        let path = disk_prep_path("writei_stale_pointers");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let a = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ia = my_fs.i_get(a).unwrap();
+        let buf = Buffer::new(vec![1; 100].into_boxed_slice());
+        my_fs.i_write(&mut ia, &buf, 0, 100).unwrap();
+        let block_a = ia.get_block(0);
+
+        // a reused inode that still points at the block of a
+        let b = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ib = <<CustomInodeRWFileSystem as InodeSupport>::Inode as InodeLike>::new(b, &FType::TFile, 0, 0, &[block_a]).unwrap();
+        my_fs.i_put(&ib).unwrap();
+        let buf2 = Buffer::new(vec![2; 100].into_boxed_slice());
+        assert!(my_fs.i_write(&mut ib, &buf2, 0, 100).is_err());
+        assert_eq!(my_fs.i_read_all(&ia).unwrap(), vec![1; 100]);
+
+        // pointers within the size have to point at allocated blocks in the data region
+        let mut ib = <<CustomInodeRWFileSystem as InodeSupport>::Inode as InodeLike>::new(b, &FType::TFile, 0, 10, &[block_a + 1]).unwrap();
+        assert!(my_fs.i_write(&mut ib, &buf2, 0, 10).is_err());
+        let mut ib = <<CustomInodeRWFileSystem as InodeSupport>::Inode as InodeLike>::new(b, &FType::TFile, 0, 10, &[2]).unwrap();
+        assert!(my_fs.i_write(&mut ib, &buf2, 0, 10).is_err());
+        assert_eq!(my_fs.i_read_all(&ia).unwrap(), vec![1; 100]);
+
+        // holes are filled in
+        let mut ib = <<CustomInodeRWFileSystem as InodeSupport>::Inode as InodeLike>::new(b, &FType::TFile, 0, 10, &[0]).unwrap();
+        my_fs.i_write(&mut ib, &buf2, 0, 100).unwrap();
+        assert_ne!(ib.get_block(0), 0);
+        assert_ne!(ib.get_block(0), block_a);
+        assert_eq!(my_fs.i_read_all(&ib).unwrap(), vec![2; 100]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn writei_directory() {
+        let path = disk_prep_path("writei_directory");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TDir).unwrap();
+        let mut dir = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new_zero(10);
+        assert!(my_fs.i_write(&mut dir, &buf, 0, 10).is_err());
+        assert_eq!(dir, my_fs.i_get(inum).unwrap());
+        assert_eq!(dir.get_size(), 0);
+        assert!(!my_fs.b_is_allocated(0).unwrap());
+
+        // reading is still allowed
+        let mut read_buf = Buffer::new_zero(10);
+        assert_eq!(my_fs.i_read(&dir, &mut read_buf, 0, 10).unwrap(), 0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn writei_one_put_per_block() {
+        let path = disk_prep_path("writei_one_put_per_block");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..(3 * BLOCK_SIZE)).map(|i| (i % 251) as u8).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+        my_fs.i_write(&mut i1, &Buffer::new_zero(3 * BLOCK_SIZE), 0, 3 * BLOCK_SIZE).unwrap();
+
+        let puts = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&puts);
+        my_fs.set_io_hook(Some(Box::new(move |event: BlockIoEvent| {
+            if event.op == BlockIoOp::Put {
+                log.lock().unwrap().push(event.block);
+            }
+        })));
+        my_fs.i_write(&mut i1, &buf, 0, 3 * BLOCK_SIZE).unwrap();
+        my_fs.set_io_hook(None);
+        // the inode block, and every data block exactly once
+        assert_eq!(*puts.lock().unwrap(), vec![1, i1.get_block(0), i1.get_block(1), i1.get_block(2)]);
+        assert_eq!(i1.get_size(), 3 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap(), contents);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn readi_near_end() {
+        let path = disk_prep_path("readi_near_end");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..300).map(|i| (i % 256) as u8).collect();
+        my_fs.i_write(&mut i1, &Buffer::new(contents.clone().into_boxed_slice()), 0, 300).unwrap();
+
+        // only the 50 bytes up to the end of the file are read, not fewer
+        let mut read_buf = Buffer::new_zero(100);
+        assert_eq!(my_fs.i_read(&i1, &mut read_buf, 250, 100).unwrap(), 50);
+        assert_eq!(read_buf.contents_as_ref()[..50], contents[250..]);
+        assert!(read_buf.contents_as_ref()[50..].iter().all(|byte| *byte == 0));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn stat() {
+        let path = disk_prep_path("stat");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        let size = 2 * BLOCK_SIZE + BLOCK_SIZE / 2;
+        let buf = Buffer::new(vec![7; size as usize].into_boxed_slice());
+        my_fs.i_write(&mut ino, &buf, 0, size).unwrap();
+
+        let stat = my_fs.i_stat(inum).unwrap();
+        assert_eq!(stat.inum, inum);
+        assert_eq!(stat.ft, FType::TFile);
+        assert_eq!(stat.size, size);
+        assert_eq!(stat.nlink, 0);
+        assert_eq!(stat.blocks_used, 3);
+
+        assert_eq!(my_fs.i_stat(2).unwrap().blocks_used, 0);
+        assert!(matches!(my_fs.i_stat(SUPERBLOCK_GOOD.ninodes), Err(CustomInodeRWFileSystemError::GivenError(CustomInodeFileSystemError::InodeIndexOutOfBounds))));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn timestamps() {
+        let path = disk_prep_path("timestamps");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let time = Arc::new(AtomicU64::new(100));
+        let clock = Arc::clone(&time);
+        my_fs.set_clock(Box::new(move || clock.load(Ordering::SeqCst)));
+
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        assert_eq!((ino.disk_node.mtime, ino.disk_node.ctime), (100, 100));
+
+        time.store(200, Ordering::SeqCst);
+        let buf = Buffer::new(vec![1; 10].into_boxed_slice());
+        my_fs.i_write(&mut ino, &buf, 0, 10).unwrap();
+        assert_eq!(ino.disk_node.mtime, 200);
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.mtime, 200);
+
+        // reading changes nothing, and neither do writes that fail
+        time.store(300, Ordering::SeqCst);
+        let mut read = Buffer::new_zero(10);
+        my_fs.i_read(&ino, &mut read, 0, 10).unwrap();
+        assert!(my_fs.i_write(&mut ino, &buf, 11, 10).is_err());
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.mtime, 200);
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.ctime, 200);
+
+        // `i_put` stores the inode as it is given
+        ino.disk_node.mtime = 5;
+        my_fs.i_put(&ino).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap(), ino);
+
+        my_fs.i_trunc(&mut ino).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.mtime, 300);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_only_mount() {
+        let path = disk_prep_path("read_only_mount");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        my_fs.i_write(&mut inode, &Buffer::new(Box::from(&b"hello"[..])), 0, 5).unwrap();
+        let dev = my_fs.unmountfs();
+
+        let mut my_fs = CustomInodeRWFileSystem::mountfs_ro(dev).unwrap();
+        assert!(my_fs.is_readonly());
+        let mut inode = my_fs.i_get(inum).unwrap();
+        let before = my_fs.i_get(inum).unwrap();
+        let result = my_fs.i_write(&mut inode, &Buffer::new(Box::from(&b"world"[..])), 0, 5);
+        assert!(matches!(result, Err(CustomInodeRWFileSystemError::GivenError(CustomInodeFileSystemError::GivenError(CustomBlockFileSystemError::ReadOnlyFilesystem)))));
+        assert_eq!(inode, before);
+        let mut buf = Buffer::new_zero(5);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 0, 5).unwrap(), 5);
+        assert_eq!(buf.contents_as_ref(), b"hello");
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn append() {
+        let path = disk_prep_path("append");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+
+        assert_eq!(my_fs.i_append(&mut inode, &Buffer::new(Box::from(&b"first,"[..])), 6).unwrap(), 0);
+        // the second chunk crosses into a new block
+        let second = vec![b'x'; BLOCK_SIZE as usize];
+        assert_eq!(my_fs.i_append(&mut inode, &Buffer::new(second.clone().into_boxed_slice()), BLOCK_SIZE).unwrap(), 6);
+        assert_eq!(my_fs.i_append(&mut inode, &Buffer::new(Box::from(&b",last"[..])), 5).unwrap(), 6 + BLOCK_SIZE);
+        assert_eq!(inode.disk_node.size, 11 + BLOCK_SIZE);
+
+        let mut expected = b"first,".to_vec();
+        expected.extend(second);
+        expected.extend(b",last");
+        assert_eq!(my_fs.i_read_all(&my_fs.i_get(inum).unwrap()).unwrap(), expected);
+
+        // appending beyond the maximal size fails and leaves the file alone
+        let rest = 12 * BLOCK_SIZE - inode.disk_node.size;
+        let too_much = Buffer::new_zero(rest + 1);
+        assert!(matches!(my_fs.i_append(&mut inode, &too_much, rest + 1), Err(CustomInodeRWFileSystemError::WriteTooLarge)));
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.size, 11 + BLOCK_SIZE);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_spanning_blocks() {
+        let path = disk_prep_path("read_spanning_blocks");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..1200).map(|i| (i * 7 % 251) as u8).collect();
+        my_fs.i_write(&mut inode, &Buffer::new(data.clone().into_boxed_slice()), 0, 1200).unwrap();
+
+        // reference: read the file byte by byte, one call per byte
+        let reference = |my_fs: &CustomInodeRWFileSystem, off: u64, n: u64| {
+            let mut bytes = Vec::new();
+            for i in off..(off + n).min(inode.disk_node.size) {
+                let mut byte = Buffer::new_zero(1);
+                assert_eq!(my_fs.i_read(&inode, &mut byte, i, 1).unwrap(), 1);
+                bytes.push(byte.contents_as_ref()[0]);
+            }
+            return bytes
+        };
+
+        // 1000 bytes starting in the middle of the first block, ending in the fourth
+        let mut buf = Buffer::new_zero(1000);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 150, 1000).unwrap(), 1000);
+        assert_eq!(buf.contents_as_ref(), &reference(&my_fs, 150, 1000)[..]);
+        assert_eq!(buf.contents_as_ref(), &data[150..1150]);
+        // stop at the end of the file
+        let mut buf = Buffer::new_zero(1000);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 500, 1000).unwrap(), 700);
+        assert_eq!(&buf.contents_as_ref()[..700], &reference(&my_fs, 500, 1000)[..]);
+        // stop when buf is full
+        let mut buf = Buffer::new_zero(450);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 299, 1000).unwrap(), 450);
+        assert_eq!(buf.contents_as_ref(), &data[299..749]);
+        // start exactly at a block boundary
+        let mut buf = Buffer::new_zero(300);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 600, 300).unwrap(), 300);
+        assert_eq!(buf.contents_as_ref(), &data[600..900]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn write_past_end() {
+        let path = disk_prep_path("write_past_end");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        // leave stale bytes behind in the first block, past the end of the file
+        my_fs.i_write(&mut inode, &Buffer::new(vec![0xFF; 100].into_boxed_slice()), 0, 100).unwrap();
+        my_fs.i_trunc_to(&mut inode, 3).unwrap();
+        my_fs.i_write(&mut inode, &Buffer::new(Box::from(&b"abc"[..])), 0, 3).unwrap();
+
+        // i_write itself does not allow gaps
+        let data = Buffer::new(Box::from(&b"xyz"[..]));
+        assert!(matches!(my_fs.i_write(&mut inode, &data, 4, 3), Err(CustomInodeRWFileSystemError::IndexOutOfBounds)));
+        // the gap crosses into a second block
+        my_fs.i_write_sparse(&mut inode, &data, BLOCK_SIZE + 20, 3).unwrap();
+        assert_eq!(inode.disk_node.size, BLOCK_SIZE + 23);
+        let contents = my_fs.i_read_all(&my_fs.i_get(inum).unwrap()).unwrap();
+        assert_eq!(&contents[..3], b"abc");
+        assert!(contents[3..(BLOCK_SIZE + 20) as usize].iter().all(|byte| *byte == 0));
+        assert_eq!(&contents[(BLOCK_SIZE + 20) as usize..], b"xyz");
+        // a write that is too large does not grow the file
+        assert!(matches!(my_fs.i_write_sparse(&mut inode, &data, 12 * BLOCK_SIZE - 2, 3), Err(CustomInodeRWFileSystemError::WriteTooLarge)));
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.size, BLOCK_SIZE + 23);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn export_file() {
+        let path = disk_prep_path("export_file");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..BLOCK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        my_fs.i_write(&mut inode, &Buffer::new(data.clone().into_boxed_slice()), 0, data.len() as u64).unwrap();
+
+        let dest = disk_prep_path("export_file_dest");
+        // directories and free inodes cannot be exported
+        let dir = my_fs.i_alloc(FType::TDir).unwrap();
+        assert!(matches!(my_fs.export_file(dir, &dest), Err(CustomInodeRWFileSystemError::NotExportable)));
+        assert!(matches!(my_fs.export_file(dir + 1, &dest), Err(CustomInodeRWFileSystemError::NotExportable)));
+        assert!(!dest.exists());
+        assert_eq!(my_fs.export_file(inum, &dest).unwrap(), data.len() as u64);
+        assert_eq!(std::fs::read(&dest).unwrap(), data);
+        utils::disk_unprep_path(&dest);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn import_file() {
+        let path = disk_prep_path("import_file");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let src = disk_prep_path("import_file_src");
+        let data: Vec<u8> = (0..BLOCK_SIZE + 42).map(|i| (i * 7 % 256) as u8).collect();
+        std::fs::write(&src, &data).unwrap();
+
+        let inum = my_fs.import_file(&src).unwrap();
+        let inode = my_fs.i_get(inum).unwrap();
+        assert_eq!(inode.disk_node.ft, FType::TFile);
+        assert_eq!(inode.disk_node.size, data.len() as u64);
+        let mut buf = Buffer::new_zero(data.len() as u64);
+        assert_eq!(my_fs.i_read(&inode, &mut buf, 0, data.len() as u64).unwrap(), data.len() as u64);
+        assert_eq!(buf.contents_as_ref(), &data[..]);
+
+        // too large for the direct blocks; no inode gets allocated
+        std::fs::write(&src, vec![1; (12 * BLOCK_SIZE + 1) as usize]).unwrap();
+        let free = my_fs.count_free_inodes().unwrap();
+        assert!(matches!(my_fs.import_file(&src), Err(CustomInodeRWFileSystemError::WriteTooLarge)));
+        assert_eq!(my_fs.count_free_inodes().unwrap(), free);
+
+        // more blocks than are free; nothing gets allocated either
+        let free_blocks = my_fs.count_free_blocks().unwrap();
+        std::fs::write(&src, vec![1; (free_blocks * BLOCK_SIZE + 1) as usize]).unwrap();
+        assert!(matches!(my_fs.import_file(&src),
+            Err(CustomInodeRWFileSystemError::GivenError(CustomInodeFileSystemError::GivenError(CustomBlockFileSystemError::NoFreeDataBlock)))));
+        assert_eq!(my_fs.count_free_inodes().unwrap(), free);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), free_blocks);
+        utils::disk_unprep_path(&src);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_to_vec() {
+        let path = disk_prep_path("read_to_vec");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        let data: Vec<u8> = (0..BLOCK_SIZE + 50).map(|i| (i % 256) as u8).collect();
+        my_fs.i_write(&mut inode, &Buffer::new(data.clone().into_boxed_slice()), 0, data.len() as u64).unwrap();
+
+        let mut buf = Buffer::new_zero(100);
+        let read = my_fs.i_read(&inode, &mut buf, BLOCK_SIZE - 20, 100).unwrap();
+        assert_eq!(my_fs.i_read_to_vec(&inode, BLOCK_SIZE - 20, 100).unwrap(), &buf.contents_as_ref()[..read as usize]);
+        // reading past the end of the file only returns the bytes that are there
+        assert_eq!(my_fs.i_read_to_vec(&inode, BLOCK_SIZE + 40, 100).unwrap(), &data[(BLOCK_SIZE + 40) as usize..]);
+        assert_eq!(my_fs.i_read_to_vec(&inode, BLOCK_SIZE + 50, 10).unwrap(), Vec::<u8>::new());
+        assert!(matches!(my_fs.i_read_to_vec(&inode, BLOCK_SIZE + 51, 10), Err(CustomInodeRWFileSystemError::IndexOutOfBounds)));
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_hole() {
+        let path = disk_prep_path("read_hole");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        let data = vec![7; (3 * BLOCK_SIZE) as usize];
+        my_fs.i_write(&mut inode, &Buffer::new(data.into_boxed_slice()), 0, 3 * BLOCK_SIZE).unwrap();
+        // punch a hole in the middle block
+        let middle = inode.disk_node.direct_blocks[1];
+        inode.disk_node.direct_blocks[1] = 0;
+        my_fs.i_put(&inode).unwrap();
+        my_fs.b_free(middle - SUPERBLOCK_GOOD.datastart).unwrap();
+
+        let contents = my_fs.i_read_to_vec(&inode, 10, 3 * BLOCK_SIZE).unwrap();
+        assert_eq!(contents.len() as u64, 3 * BLOCK_SIZE - 10);
+        assert!(contents[..(BLOCK_SIZE - 10) as usize].iter().all(|byte| *byte == 7));
+        assert!(contents[(BLOCK_SIZE - 10) as usize..(2 * BLOCK_SIZE - 10) as usize].iter().all(|byte| *byte == 0));
+        assert!(contents[(2 * BLOCK_SIZE - 10) as usize..].iter().all(|byte| *byte == 7));
+        // starting right at the end of a block
+        assert_eq!(my_fs.i_read_to_vec(&inode, BLOCK_SIZE, 5).unwrap(), vec![0; 5]);
+        assert_eq!(my_fs.i_read_to_vec(&inode, 2 * BLOCK_SIZE, 5).unwrap(), vec![7; 5]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn write_slice() {
+        let path = disk_prep_path("write_slice");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+
+        my_fs.i_write_slice(&mut inode, b"hello world", 0).unwrap();
+        my_fs.i_write_slice(&mut inode, b"there", 6).unwrap();
+        assert_eq!(inode.disk_node.size, 11);
+        assert_eq!(my_fs.i_read_to_vec(&inode, 0, 11).unwrap(), b"hello there");
+        let data = vec![3; BLOCK_SIZE as usize];
+        my_fs.i_write_slice(&mut inode, &data, 11).unwrap();
+        assert_eq!(my_fs.i_read_to_vec(&inode, 11, BLOCK_SIZE).unwrap(), data);
+
+        // the same checks as i_write
+        assert!(matches!(my_fs.i_write_slice(&mut inode, b"!", BLOCK_SIZE + 12), Err(CustomInodeRWFileSystemError::IndexOutOfBounds)));
+        let too_large = vec![1; (12 * BLOCK_SIZE) as usize];
+        assert!(matches!(my_fs.i_write_slice(&mut inode, &too_large, 1), Err(CustomInodeRWFileSystemError::WriteTooLarge)));
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.size, BLOCK_SIZE + 11);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn copy() {
+        let path = disk_prep_path("copy");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let src = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut src_inode = my_fs.i_get(src).unwrap();
+        let data: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i % 199) as u8).collect();
+        my_fs.i_write_slice(&mut src_inode, &data, 0).unwrap();
+        let dst = my_fs.i_alloc(FType::TFile).unwrap();
+
+        assert_eq!(my_fs.i_copy(src, dst).unwrap(), 2 * BLOCK_SIZE);
+        let dst_inode = my_fs.i_get(dst).unwrap();
+        assert_eq!(dst_inode.disk_node.size, 2 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read_all(&dst_inode).unwrap(), data);
+        // the copy has blocks of its own
+        assert!(dst_inode.disk_node.direct_blocks[..2].iter().all(|b| !src_inode.disk_node.direct_blocks.contains(b)));
+
+        // a longer destination is truncated first
+        let mut dst_inode = dst_inode;
+        my_fs.i_write_slice(&mut dst_inode, &[9; 50], 2 * BLOCK_SIZE).unwrap();
+        let free = my_fs.count_free_blocks().unwrap();
+        assert_eq!(my_fs.i_copy(src, dst).unwrap(), 2 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read_all(&my_fs.i_get(dst).unwrap()).unwrap(), data);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), free + 1);
+
+        let dir = my_fs.i_alloc(FType::TDir).unwrap();
+        assert!(matches!(my_fs.i_copy(src, dir), Err(CustomInodeRWFileSystemError::InodeWrongType)));
+        assert!(matches!(my_fs.i_copy(dir, dst), Err(CustomInodeRWFileSystemError::InodeWrongType)));
+
+        // a copy that does not fit leaves the destination as it is
+        let small = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut small = my_fs.i_get(small).unwrap();
+        my_fs.i_write_slice(&mut small, &[3; 10], 0).unwrap();
+        my_fs.i_set_max_size(&mut small, BLOCK_SIZE).unwrap();
+        assert!(matches!(my_fs.i_copy(src, small.inum), Err(CustomInodeRWFileSystemError::QuotaExceeded)));
+        assert_eq!(my_fs.i_read_all(&my_fs.i_get(small.inum).unwrap()).unwrap(), vec![3; 10]);
+        my_fs.i_set_max_size(&mut small, 0).unwrap();
+        // src and dst hold 2 blocks each, `small` and `filler` 1, so no blocks are free
+        let filler = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut filler = my_fs.i_get(filler).unwrap();
+        my_fs.i_write_slice(&mut filler, &[4; 10], 0).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 0);
+        assert!(matches!(my_fs.i_copy(src, small.inum),
+            Err(CustomInodeRWFileSystemError::GivenError(CustomInodeFileSystemError::GivenError(CustomBlockFileSystemError::NoFreeDataBlock)))));
+        assert_eq!(my_fs.i_read_all(&my_fs.i_get(small.inum).unwrap()).unwrap(), vec![3; 10]);
+        // the blocks of the destination are reused for the copy
+        assert_eq!(my_fs.i_copy(dst, src).unwrap(), 2 * BLOCK_SIZE);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 0);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn read_needs_indirect() {
+        let path = disk_prep_path("read_needs_indirect");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        my_fs.i_write_slice(&mut inode, &[5; 10], 0).unwrap();
+
+        // a file written by the indirect layer, longer than the direct blocks can hold
+        let mut long = my_fs.i_get(inum).unwrap();
+        long.disk_node.size = 13 * BLOCK_SIZE;
+        let mut buf = Buffer::new_zero(BLOCK_SIZE);
+        assert!(matches!(my_fs.i_read(&long, &mut buf, 12 * BLOCK_SIZE, BLOCK_SIZE), Err(CustomInodeRWFileSystemError::NeedsIndirectBlocks)));
+        assert!(matches!(my_fs.i_read_blocks_into(&long, &mut Vec::new()), Err(CustomInodeRWFileSystemError::NeedsIndirectBlocks)));
+        let mut indirect = my_fs.i_get(inum).unwrap();
+        indirect.disk_node.indirect_block = SUPERBLOCK_GOOD.datastart + 1;
+        assert!(matches!(my_fs.i_read(&indirect, &mut buf, 0, 10), Err(CustomInodeRWFileSystemError::NeedsIndirectBlocks)));
+        assert_eq!(my_fs.i_read_to_vec(&inode, 0, 10).unwrap(), vec![5; 10]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn quota_not_reused() {
+        let path = disk_prep_path("quota_not_reused");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut inode = my_fs.i_get(inum).unwrap();
+        my_fs.i_set_max_size(&mut inode, 10).unwrap();
+        my_fs.i_free(inum).unwrap();
+        assert_eq!(my_fs.i_get(inum).unwrap().disk_node.max_size, 0);
+
+        assert_eq!(my_fs.i_alloc(FType::TFile).unwrap(), inum);
+        let mut inode = my_fs.i_get(inum).unwrap();
+        my_fs.i_write_slice(&mut inode, &[1; 50], 0).unwrap();
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn readi_writei_boundaries() {
+        let path = disk_prep_path("readi_writei_boundaries");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..(2 * BLOCK_SIZE + 50)).map(|i| (i % 256) as u8).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+        // end the first write exactly on a block boundary, and append the rest
+        my_fs.i_write(&mut i1, &buf, 0, BLOCK_SIZE).unwrap();
+        assert_eq!(i1.get_size(), BLOCK_SIZE);
+        assert_eq!(i1.get_block(1), 0);
+        let rest = Buffer::new(contents[BLOCK_SIZE as usize..].to_vec().into_boxed_slice());
+        my_fs.i_write(&mut i1, &rest, BLOCK_SIZE, BLOCK_SIZE + 50).unwrap();
+        assert_eq!(i1.get_size(), 2 * BLOCK_SIZE + 50);
+
+        // read from the second block up to the end of the file
+        let mut read_buf = Buffer::new_zero(2 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut read_buf, BLOCK_SIZE, 2 * BLOCK_SIZE).unwrap(), BLOCK_SIZE + 50);
+        assert_eq!(read_buf.contents_as_ref()[..(BLOCK_SIZE + 50) as usize], contents[BLOCK_SIZE as usize..]);
+        // the last byte, at and past the end
+        let mut one = Buffer::new_zero(1);
+        assert_eq!(my_fs.i_read(&i1, &mut one, 2 * BLOCK_SIZE + 49, 10).unwrap(), 1);
+        assert_eq!(one.contents_as_ref()[0], contents[(2 * BLOCK_SIZE + 49) as usize]);
+        assert_eq!(my_fs.i_read(&i1, &mut one, 2 * BLOCK_SIZE + 50, 10).unwrap(), 0);
+        assert!(my_fs.i_read(&i1, &mut one, 2 * BLOCK_SIZE + 51, 10).is_err());
+        assert!(my_fs.i_write(&mut i1, &buf, 2 * BLOCK_SIZE + 51, 1).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn writei_detailed() {
+        let path = disk_prep_path("writei_detailed");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![3; 400].into_boxed_slice());
+        assert_eq!(my_fs.i_write_detailed(&mut i1, &buf, 0, 250).unwrap(), vec![(i1.get_block(0), 250)]);
+        // 50 bytes left in the first block, the rest goes to the second and third one
+        let written = my_fs.i_write_detailed(&mut i1, &buf, 250, 400).unwrap();
+        assert_eq!(written, vec![(i1.get_block(0), 50), (i1.get_block(1), 300), (i1.get_block(2), 50)]);
+        assert!(my_fs.i_write_detailed(&mut i1, &buf, 650, 0).unwrap().is_empty());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn itrunc_keep_blocks() {
+        let path = disk_prep_path("itrunc_keep_blocks");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![5; 500].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 500).unwrap();
+        let blocks = [i1.get_block(0), i1.get_block(1)];
+
+        my_fs.i_trunc_keep_blocks(&mut i1).unwrap();
+        assert_eq!(i1.get_size(), 0);
+        assert_eq!(my_fs.i_get(inum).unwrap(), i1);
+        for b in blocks.iter() {
+            assert!(my_fs.b_is_allocated(b - SUPERBLOCK_GOOD.datastart).unwrap());
+        }
+        assert_eq!(my_fs.b_alloc().unwrap(), 2);
+        my_fs.b_free(2).unwrap();
+
+        // the next write reuses the kept blocks
+        my_fs.i_write(&mut i1, &buf, 0, 400).unwrap();
+        assert_eq!([i1.get_block(0), i1.get_block(1)], blocks);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap(), vec![5; 400]);
+
+        // a real truncation frees them, also when they lie beyond the size
+        my_fs.i_trunc_keep_blocks(&mut i1).unwrap();
+        my_fs.i_write(&mut i1, &buf, 0, 10).unwrap();
+        my_fs.i_trunc(&mut i1).unwrap();
+        for b in blocks.iter() {
+            assert!(!my_fs.b_is_allocated(b - SUPERBLOCK_GOOD.datastart).unwrap());
+        }
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn inline_storage() {
+        let path = disk_prep_path("inline_storage");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        my_fs.i_set_inline(&mut i1).unwrap();
+        let contents: Vec<u8> = (1..=200).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+
+        my_fs.i_write(&mut i1, &buf, 0, 10).unwrap();
+        let rest = Buffer::new(contents[10..].to_vec().into_boxed_slice());
+        my_fs.i_write(&mut i1, &rest, 10, INLINE_CAPACITY - 10).unwrap();
+        assert_eq!(i1.get_size(), INLINE_CAPACITY);
+        // no data block was used
+        assert_eq!(my_fs.b_alloc().unwrap(), 0);
+        my_fs.b_free(0).unwrap();
+        let i1 = my_fs.i_get(inum).unwrap();
+        assert_eq!(my_fs.i_read_all(&i1).unwrap(), contents[..INLINE_CAPACITY as usize]);
+        let mut buf5 = Buffer::new_zero(5);
+        assert_eq!(my_fs.i_read(&i1, &mut buf5, 20, 10).unwrap(), 5);
+        assert_eq!(buf5.contents_as_ref(), &contents[20..25]);
+
+        // growing past the inline capacity moves the contents to a block
+        let mut i1 = i1;
+        let rest = Buffer::new(contents[INLINE_CAPACITY as usize..].to_vec().into_boxed_slice());
+        my_fs.i_write(&mut i1, &rest, INLINE_CAPACITY, 200 - INLINE_CAPACITY).unwrap();
+        assert_eq!(i1.get_size(), 200);
+        assert_eq!(i1.get_block(0), SUPERBLOCK_GOOD.datastart);
+        assert_eq!(i1.get_block(1), 0);
+        assert_eq!(my_fs.i_get(inum).unwrap(), i1);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap(), contents);
+        let mut out = Vec::new();
+        my_fs.i_read_blocks_into(&i1, &mut out).unwrap();
+        assert_eq!(out, contents);
+
+        // only empty files can switch to inline storage
+        assert!(my_fs.i_set_inline(&mut i1).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn inline_conversion_failure() {
+        let path = disk_prep_path("inline_conversion_failure");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        my_fs.i_set_inline(&mut i1).unwrap();
+        let contents: Vec<u8> = (1..=200).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 10).unwrap();
+        let before = i1.clone();
+
+        // no block is left to move the inline contents to
+        let taken: Vec<u64> = (0..SUPERBLOCK_GOOD.ndatablocks).map(|_| my_fs.b_alloc().unwrap()).collect();
+        assert!(my_fs.i_write(&mut i1, &buf, 10, 190).is_err());
+        // the inode still holds its inline contents, in memory and on disk
+        assert_eq!(i1.disk_node.flags, before.disk_node.flags);
+        assert_eq!(i1.disk_node.size, before.disk_node.size);
+        assert_eq!(i1.disk_node.direct_blocks, before.disk_node.direct_blocks);
+        assert_eq!(my_fs.i_get(inum).unwrap(), i1);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap(), contents[..10]);
+
+        // with room again, the same write moves the contents to a block
+        my_fs.b_free(taken[0]).unwrap();
+        my_fs.i_write(&mut i1, &buf, 10, 190).unwrap();
+        assert_eq!(i1.get_block(0), SUPERBLOCK_GOOD.datastart + taken[0]);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap()[10..], contents[..190]);
+        assert_eq!(my_fs.i_read_all(&i1).unwrap()[..10], contents[..10]);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn image_checksum() {
+        let path = disk_prep_path("image_checksum");
+        let path2 = disk_prep_path("image_checksum_2");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let my_fs2 = CustomInodeRWFileSystem::mkfs(&path2, &SUPERBLOCK_GOOD).unwrap();
+        let fresh = my_fs.fs_checksum().unwrap();
+        assert_eq!(my_fs.fs_checksum().unwrap(), fresh);
+        assert_eq!(my_fs2.fs_checksum().unwrap(), fresh);
+
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        my_fs.i_write(&mut i1, &Buffer::new(vec![1; 10].into_boxed_slice()), 0, 10).unwrap();
+        // unmounting writes the count of free blocks to block 0, so flush it before taking the checksum
+        my_fs.flush().unwrap();
+        let written = my_fs.fs_checksum().unwrap();
+        assert_ne!(written, fresh);
+
+        let dev = my_fs2.unmountfs();
+        utils::disk_destruct(dev);
+        let dev = my_fs.unmountfs();
+        let my_fs = CustomInodeRWFileSystem::mountfs(dev).unwrap();
+        assert_eq!(my_fs.fs_checksum().unwrap(), written);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
 }
 
 
@@ -31,27 +31,472 @@
 //!
 //! # Status
 //!
-//! **TODO**: Replace the question mark below with YES, NO, or PARTIAL to
-//! indicate the status of this assignment. If you want to tell something
-//! about this assignment to the grader, e.g., you have a bug you can't fix,
-//! or you want to explain your approach, write it down after the comments
-//! section. If you had no major issues and everything works, there is no need to write any comments.
 //!
-//! COMPLETED: NO
+//! COMPLETED: YES
 //!
 //! COMMENTS:
 //!
-//! ...
+//! Rather than defining a separate inode type, the `DInode` of the API got an `indirect_block` field, and its `InodeLike` implementation
+//! takes and returns the indirect block at index `DIRECT_POINTERS`. As long as no indirect block is allocated, this field stays 0,
+//! so the earlier layers keep working unchanged, and the inode layer frees indirect blocks when truncating or freeing an inode.
+//! The file system below wraps the one of assignment e, and only takes over reads and writes that reach past the direct blocks.
 //!
 
-/// You are free to choose the name for your file system. As we will use
-/// automated tests when grading your assignment, indicate here the name of
-/// your file system data type so we can just use `FSName` instead of
-/// having to manually figure out the name.
-/// **TODO**: replace the below type by the type of your file system
-pub type FSName = ();
+use std::io::{self, ErrorKind};
+use thiserror::Error;
+use cplfs_api::{controller::Device, error_given, fs::{BlockSupport, FileSysSupport, InodeRWSupport, InodeSupport}, types::{Block, Buffer, DIRECT_POINTERS, FType, Inode, SuperBlock}};
 
-// **TODO** define your own tests here.
+use crate::e_inode_RW_support::{self, CustomInodeRWFileSystem, CustomInodeRWFileSystemError};
+use crate::a_block_support::{self, CustomBlockFileSystemError};
+use crate::b_inode_support::CustomInodeFileSystemError;
+
+/// Type of my file system
+pub type FSName = CustomIndirectFileSystem;
+
+// Custom type
+/// Custom file system data type
+#[must_use = "release the file system through `unmountfs` once you are done with it"]
+pub struct CustomIndirectFileSystem {
+    rw_fs: CustomInodeRWFileSystem,
+}
+
+impl CustomIndirectFileSystem {
+
+    /// Create a new CustomIndirectFileSystem given a CustomInodeRWFileSystem
+    pub fn new(rwfs: CustomInodeRWFileSystem) -> CustomIndirectFileSystem {
+        CustomIndirectFileSystem { rw_fs: rwfs }
+    }
+
+    /// Mount the file system on `dev` like `mountfs`, but read-only, see `CustomBlockFileSystem::mountfs_ro`
+    pub fn mountfs_ro(dev: Device) -> Result<Self, CustomIndirectFileSystemError> {
+        let rw_fs = CustomInodeRWFileSystem::mountfs_ro(dev)?;
+        return Ok(CustomIndirectFileSystem::new(rw_fs));
+    }
+
+    /// Largest size in bytes a file can grow to, using all of its direct blocks and all blocks its indirect block can point to
+    pub fn max_file_size(&self) -> Result<u64, CustomIndirectFileSystemError> {
+        let sb = self.sup_get()?;
+        return Ok((DIRECT_POINTERS + sb.block_size / 8) * sb.block_size)
+    }
+
+    /// Addresses held by the indirect block of `inode`, one for every block of the file past the direct ones, 0 where there is none
+    pub fn indirect_addresses(&self, inode: &Inode) -> Result<Vec<u64>, CustomIndirectFileSystemError> {
+        let addresses = self.rw_fs.indirect_addresses(inode)?;
+        return Ok(addresses)
+    }
+
+    /// Truncate `inode` to `new_size` bytes, freeing only the blocks beyond it, and the indirect block once it is no longer needed
+    pub fn i_trunc_to(&mut self, inode: &mut Inode, new_size: u64) -> Result<(), CustomIndirectFileSystemError> {
+        self.rw_fs.i_trunc_to(inode, new_size)?;
+        return Ok(())
+    }
+
+    /// Number of data blocks that are free according to the bitmap
+    pub fn count_free_blocks(&self) -> Result<u64, CustomIndirectFileSystemError> {
+        let free = self.rw_fs.count_free_blocks()?;
+        return Ok(free)
+    }
+
+    /// Check whether the block with index `i` in the data region is currently allocated
+    pub fn b_is_allocated(&self, i: u64) -> Result<bool, CustomIndirectFileSystemError> {
+        let allocated = self.rw_fs.b_is_allocated(i)?;
+        return Ok(allocated)
+    }
+
+    /// Address of block `index` of the file, given the `addresses` held by its indirect block, or 0 if the file has no block there
+    fn file_block(inode: &Inode, addresses: &[u64], index: u64) -> u64 {
+        if index < DIRECT_POINTERS {
+            return inode.disk_node.direct_blocks[index as usize]
+        }
+        return addresses.get((index - DIRECT_POINTERS) as usize).copied().unwrap_or(0)
+    }
+
+    /// Number of blocks a write of the bytes from file offset `off` up to `end` has to allocate for `inode`:
+    /// the blocks in that range the file does not have yet, and the indirect block if the range reaches past the direct blocks.
+    fn blocks_needed(&self, inode: &Inode, off: u64, end: u64) -> Result<u64, CustomIndirectFileSystemError> {
+        let sb = self.sup_get()?;
+        let addresses = self.indirect_addresses(inode)?;
+        let last = end.div_ceil(sb.block_size);
+        let mut needed = (off / sb.block_size..last).filter(|index| Self::file_block(inode, &addresses, *index) == 0).count() as u64;
+        if last > DIRECT_POINTERS && inode.disk_node.indirect_block == 0 {
+            needed += 1;
+        }
+        return Ok(needed)
+    }
+
+    /// Write the bytes of `buf` that go past the direct blocks, i.e. from file offset `start` up to `off + n`,
+    /// allocating the indirect block and the blocks it points to where needed.
+    fn write_indirect(&mut self, inode: &mut Inode, buf: &Buffer, off: u64, n: u64, start: u64) -> Result<(), CustomIndirectFileSystemError> {
+        let sb = self.sup_get()?;
+        let mut indirect = if inode.disk_node.indirect_block == 0 {
+            // allocated blocks are zeroed, so all addresses start out as 0
+            inode.disk_node.indirect_block = sb.datastart + self.b_alloc()?;
+            Block::new_zero(inode.disk_node.indirect_block, sb.block_size)
+        } else {
+            self.b_get(inode.disk_node.indirect_block)?
+        };
+        let end = off + n;
+        for index in start / sb.block_size..end.div_ceil(sb.block_size) {
+            let slot_offset = (index - DIRECT_POINTERS) * 8;
+            let mut address = indirect.deserialize_from::<u64>(slot_offset)?;
+            if address == 0 {
+                address = sb.datastart + self.b_alloc()?;
+                indirect.serialize_into(&address, slot_offset)?;
+            }
+            let from = start.max(index * sb.block_size);
+            let to = end.min((index + 1) * sb.block_size);
+            let mut bytes = vec![0; (to - from) as usize];
+            buf.read_data(&mut bytes, from - off)?;
+            let mut block = self.b_get(address)?;
+            block.write_data(&bytes, from - index * sb.block_size)?;
+            self.b_put(&block)?;
+        }
+        self.b_put(&indirect)?;
+        if end > inode.disk_node.size {
+            inode.disk_node.size = end;
+        }
+        self.rw_fs.touch(inode);
+        self.i_put(inode)?;
+        return Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+/// Custom type for errors in CustomIndirectFileSystem
+pub enum CustomIndirectFileSystemError {
+    /// An error occured in the read and write layer
+    #[error("InodeRWFileSystemError")]
+    GivenError(#[from] e_inode_RW_support::CustomInodeRWFileSystemError),
+    #[error("API error")]
+    /// The input provided to some method in the controller layer was invalid
+    APIError(#[from] error_given::APIError),
+}
+
+impl CustomIndirectFileSystemError {
+    /// The kind of `io::Error` this error corresponds to
+    pub fn kind(&self) -> ErrorKind {
+        return match self {
+            CustomIndirectFileSystemError::GivenError(e) => e.kind(),
+            CustomIndirectFileSystemError::APIError(e) => a_block_support::api_error_kind(e),
+        }
+    }
+}
+
+impl From<CustomIndirectFileSystemError> for io::Error {
+    fn from(e: CustomIndirectFileSystemError) -> io::Error {
+        return io::Error::new(e.kind(), e)
+    }
+}
+
+impl FileSysSupport for CustomIndirectFileSystem {
+    type Error = CustomIndirectFileSystemError;
+
+    fn sb_valid(sb: &SuperBlock) -> bool {
+        return CustomInodeRWFileSystem::sb_valid(sb);
+    }
+
+    fn mkfs<P: AsRef<std::path::Path>>(path: P, sb: &SuperBlock) -> Result<Self, Self::Error> {
+        let rw_fs = CustomInodeRWFileSystem::mkfs(path, sb)?;
+        return Ok(CustomIndirectFileSystem::new(rw_fs))
+    }
+
+    fn mountfs(dev: Device) -> Result<Self, Self::Error> {
+        let rw_fs = CustomInodeRWFileSystem::mountfs(dev)?;
+        return Ok(CustomIndirectFileSystem::new(rw_fs));
+    }
+
+    fn unmountfs(self) -> Device {
+        return self.rw_fs.unmountfs();
+    }
+}
+
+impl BlockSupport for CustomIndirectFileSystem {
+    fn b_get(&self, i: u64) -> Result<Block, Self::Error> {
+        let block = self.rw_fs.b_get(i)?;
+        return Ok(block);
+    }
+
+    fn b_put(&mut self, b: &Block) -> Result<(), Self::Error> {
+        let result = self.rw_fs.b_put(b)?;
+        return Ok(result);
+    }
+
+    fn b_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let res = self.rw_fs.b_free(i)?;
+        return Ok(res)
+    }
+
+    fn b_zero(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.rw_fs.b_zero(i)?;
+        return Ok(result);
+    }
+
+    fn b_alloc(&mut self) -> Result<u64, Self::Error> {
+        let index = self.rw_fs.b_alloc()?;
+        return Ok(index);
+    }
+
+    fn sup_get(&self) -> Result<SuperBlock, Self::Error> {
+        let superblock = self.rw_fs.sup_get()?;
+        return Ok(superblock);
+    }
+
+    fn sup_put(&mut self, sup: &SuperBlock) -> Result<(), Self::Error> {
+        let result = self.rw_fs.sup_put(sup)?;
+        return Ok(result);
+    }
+}
+
+impl InodeSupport for CustomIndirectFileSystem {
+    type Inode = Inode;
+
+    fn i_get(&self, i: u64) -> Result<Self::Inode, Self::Error> {
+        let inode = self.rw_fs.i_get(i)?;
+        return Ok(inode);
+    }
+
+    fn i_put(&mut self, ino: &Self::Inode) -> Result<(), Self::Error> {
+        let result = self.rw_fs.i_put(ino)?;
+        return Ok(result);
+    }
+
+    // the inode layer frees the indirect block and the blocks it points to as well
+    fn i_free(&mut self, i: u64) -> Result<(), Self::Error> {
+        let result = self.rw_fs.i_free(i)?;
+        return Ok(result);
+    }
+
+    fn i_alloc(&mut self, ft: FType) -> Result<u64, Self::Error> {
+        let i = self.rw_fs.i_alloc(ft)?;
+        return Ok(i);
+    }
+
+    fn i_trunc(&mut self, inode: &mut Self::Inode) -> Result<(), Self::Error> {
+        let result = self.rw_fs.i_trunc(inode)?;
+        return Ok(result);
+    }
+}
+
+impl InodeRWSupport for CustomIndirectFileSystem {
+    fn i_read(&self, inode: &Self::Inode, buf: &mut Buffer, off: u64, n: u64) -> Result<u64, Self::Error> {
+        // without an indirect block, all contents are in the direct blocks (or inline)
+        if inode.disk_node.indirect_block == 0 {
+            let read = self.rw_fs.i_read(inode, buf, off, n)?;
+            return Ok(read)
+        }
+        if off > inode.disk_node.size {
+            return Err(CustomInodeRWFileSystemError::IndexOutOfBounds.into());
+        }
+        let sb = self.sup_get()?;
+        let addresses = self.indirect_addresses(inode)?;
+        // If buf cannot hold n bytes of data, reads until buf is full instead.
+        let count = n.min(buf.len()).min(inode.disk_node.size - off);
+        let mut pos = off;
+        while pos < off + count {
+            let index = pos / sb.block_size;
+            let to = (off + count).min((index + 1) * sb.block_size);
+            let mut bytes = vec![0; (to - pos) as usize];
+            let address = Self::file_block(inode, &addresses, index);
+            // holes read as zeroes
+            if !(address == 0) {
+                self.b_get(address)?.read_data(&mut bytes, pos - index * sb.block_size)?;
+            }
+            buf.write_data(&bytes, pos - off)?;
+            pos = to;
+        }
+        return Ok(count)
+    }
+
+    fn i_write(&mut self, inode: &mut Self::Inode, buf: &Buffer, off: u64, n: u64) -> Result<(), Self::Error> {
+        let sb = self.sup_get()?;
+        let direct_size = DIRECT_POINTERS * sb.block_size;
+        // writes that stay within the direct blocks are handled as before
+        if off + n <= direct_size {
+            self.rw_fs.i_write(inode, buf, off, n)?;
+            return Ok(())
+        }
+        // check everything the layer below would check before writing anything
+        if inode.disk_node.ft == FType::TDir {
+            return Err(CustomInodeRWFileSystemError::CannotWriteDirectory.into());
+        }
+        if off > inode.disk_node.size {
+            return Err(CustomInodeRWFileSystemError::IndexOutOfBounds.into());
+        }
+        if buf.len() < n {
+            return Err(CustomInodeRWFileSystemError::BufTooSmall.into());
+        }
+        if off + n > self.max_file_size()? {
+            return Err(CustomInodeRWFileSystemError::WriteTooLarge.into());
+        }
+        if inode.disk_node.max_size != 0 && off + n > inode.disk_node.max_size {
+            return Err(CustomInodeRWFileSystemError::QuotaExceeded.into());
+        }
+        // running out of blocks halfway would leave blocks allocated that no inode points to
+        if self.blocks_needed(inode, off, off + n)? > self.count_free_blocks()? {
+            return Err(CustomInodeRWFileSystemError::from(CustomInodeFileSystemError::from(CustomBlockFileSystemError::NoFreeDataBlock)).into());
+        }
+        if off < direct_size {
+            self.rw_fs.i_write(inode, buf, off, direct_size - off)?;
+        }
+        self.write_indirect(inode, buf, off, n, off.max(direct_size))?;
+        return Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::path::PathBuf;
+    use cplfs_api::{fs::{FileSysSupport, InodeRWSupport, InodeSupport}, types::{Buffer, DIRECT_POINTERS, FType, InodeLike, SuperBlock}};
+
+    use super::CustomIndirectFileSystem;
+
+    fn disk_prep_path(name: &str) -> PathBuf {
+        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    }
+    static BLOCK_SIZE: u64 = 300;
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: BLOCK_SIZE,
+        nblocks: 40,
+        ninodes: 6,
+        inodestart: 1,
+        ndatablocks: 30,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    #[test]
+    fn file_system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<CustomIndirectFileSystem>();
+    }
+
+    #[test]
+    fn write_read_indirect() {
+        let path = disk_prep_path("write_read_indirect");
+        let mut my_fs = CustomIndirectFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..20 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+        let buf = Buffer::new(contents.clone().into_boxed_slice());
+
+        // a first write crossing into the indirect blocks, and a second one only past the direct blocks
+        my_fs.i_write(&mut i1, &buf, 0, 14 * BLOCK_SIZE + 10).unwrap();
+        assert_ne!(i1.get_block(DIRECT_POINTERS), 0);
+        assert_eq!(my_fs.indirect_addresses(&i1).unwrap().iter().filter(|a| **a != 0).count(), 3);
+        let rest = Buffer::new(contents[(14 * BLOCK_SIZE + 10) as usize..].to_vec().into_boxed_slice());
+        my_fs.i_write(&mut i1, &rest, 14 * BLOCK_SIZE + 10, 6 * BLOCK_SIZE - 10).unwrap();
+        assert_eq!(i1, my_fs.i_get(inum).unwrap());
+        assert_eq!(i1.get_size(), 20 * BLOCK_SIZE);
+        // 20 data blocks and the indirect block
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks - 21);
+
+        let mut read_buf = Buffer::new_zero(20 * BLOCK_SIZE);
+        assert_eq!(my_fs.i_read(&i1, &mut read_buf, 0, 20 * BLOCK_SIZE).unwrap(), 20 * BLOCK_SIZE);
+        assert_eq!(read_buf.contents_as_ref(), &contents[..]);
+        // a read across the end of the direct blocks, running past the end of the file
+        let mut read_buf = Buffer::new_zero(10 * BLOCK_SIZE);
+        let start = 11 * BLOCK_SIZE + 7;
+        assert_eq!(my_fs.i_read(&i1, &mut read_buf, start, 10 * BLOCK_SIZE).unwrap(), 9 * BLOCK_SIZE - 7);
+        assert_eq!(read_buf.contents_as_ref()[..(9 * BLOCK_SIZE - 7) as usize], contents[start as usize..]);
+        assert!(my_fs.i_read(&i1, &mut read_buf, 20 * BLOCK_SIZE + 1, 1).is_err());
+
+        // overwrite a stretch in the middle
+        let ones = Buffer::new(vec![1; BLOCK_SIZE as usize].into_boxed_slice());
+        my_fs.i_write(&mut i1, &ones, 15 * BLOCK_SIZE + 100, BLOCK_SIZE).unwrap();
+        let mut read_buf = Buffer::new_zero(BLOCK_SIZE + 2);
+        my_fs.i_read(&i1, &mut read_buf, 15 * BLOCK_SIZE + 99, BLOCK_SIZE + 2).unwrap();
+        assert_eq!(read_buf.contents_as_ref()[0], contents[(15 * BLOCK_SIZE + 99) as usize]);
+        assert!(read_buf.contents_as_ref()[1..=BLOCK_SIZE as usize].iter().all(|byte| *byte == 1));
+        assert_eq!(i1.get_size(), 20 * BLOCK_SIZE);
+
+        // the disk runs out long before the indirect block does
+        let max = my_fs.max_file_size().unwrap();
+        assert_eq!(max, (DIRECT_POINTERS + BLOCK_SIZE / 8) * BLOCK_SIZE);
+        let big = Buffer::new_zero(max + 1);
+        assert!(my_fs.i_write(&mut i1, &big, 0, max + 1).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn trunc_indirect() {
+        let path = disk_prep_path("trunc_indirect");
+        let mut my_fs = CustomIndirectFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![6; 5000].into_boxed_slice());
+        my_fs.i_write(&mut i1, &buf, 0, 5000).unwrap();
+        let indirect = i1.get_block(DIRECT_POINTERS) - SUPERBLOCK_GOOD.datastart;
+        let addresses: Vec<u64> = my_fs.indirect_addresses(&i1).unwrap().into_iter().filter(|a| *a != 0).map(|a| a - SUPERBLOCK_GOOD.datastart).collect();
+        assert_eq!(addresses.len(), 5);
+
+        // shrinking within the indirect blocks keeps the indirect block
+        my_fs.i_trunc_to(&mut i1, DIRECT_POINTERS * BLOCK_SIZE + 1).unwrap();
+        assert!(my_fs.b_is_allocated(indirect).unwrap());
+        assert!(my_fs.b_is_allocated(addresses[0]).unwrap());
+        assert!(addresses[1..].iter().all(|a| !my_fs.b_is_allocated(*a).unwrap()));
+        // growing again reuses the indirect block
+        my_fs.i_write(&mut i1, &buf, 0, 5000).unwrap();
+        assert_eq!(i1.get_block(DIRECT_POINTERS) - SUPERBLOCK_GOOD.datastart, indirect);
+
+        my_fs.i_trunc(&mut i1).unwrap();
+        assert_eq!(i1.get_block(DIRECT_POINTERS), 0);
+        assert_eq!(my_fs.i_get(inum).unwrap().get_block(DIRECT_POINTERS), 0);
+        assert!(!my_fs.b_is_allocated(indirect).unwrap());
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks);
+
+        // shrinking into the direct blocks frees the indirect block as well
+        my_fs.i_write(&mut i1, &buf, 0, 5000).unwrap();
+        my_fs.i_trunc_to(&mut i1, DIRECT_POINTERS * BLOCK_SIZE).unwrap();
+        assert_eq!(i1.get_block(DIRECT_POINTERS), 0);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), SUPERBLOCK_GOOD.ndatablocks - DIRECT_POINTERS);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn write_out_of_space() {
+        static SUPERBLOCK_SMALL: SuperBlock = SuperBlock {
+            block_size: BLOCK_SIZE,
+            nblocks: 20,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 15,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("write_out_of_space");
+        let mut my_fs = CustomIndirectFileSystem::mkfs(&path, &SUPERBLOCK_SMALL).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut i1 = my_fs.i_get(inum).unwrap();
+        let buf = Buffer::new(vec![4; (20 * BLOCK_SIZE) as usize].into_boxed_slice());
+
+        // 20 data blocks and an indirect block do not fit, and nothing gets written
+        assert!(my_fs.i_write(&mut i1, &buf, 0, 20 * BLOCK_SIZE).is_err());
+        assert_eq!(i1.get_size(), 0);
+        assert_eq!(my_fs.i_get(inum).unwrap().get_size(), 0);
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 15);
+        // 14 data blocks and the indirect block fill the disk exactly
+        my_fs.i_write(&mut i1, &buf, 0, 14 * BLOCK_SIZE).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 0);
+        // overwriting needs no new blocks
+        my_fs.i_write(&mut i1, &buf, BLOCK_SIZE, 13 * BLOCK_SIZE).unwrap();
+        assert!(my_fs.i_write(&mut i1, &buf, 14 * BLOCK_SIZE, 1).is_err());
+        assert_eq!(i1.get_size(), 14 * BLOCK_SIZE);
+
+        my_fs.i_free(inum).unwrap();
+        assert_eq!(my_fs.count_free_blocks().unwrap(), 15);
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+}
 
 // WARNING: DO NOT TOUCH THE BELOW CODE -- IT IS REQUIRED FOR TESTING -- YOU WILL LOSE POINTS IF I MANUALLY HAVE TO FIX YOUR TESTS
 #[cfg(all(test, any(feature = "f", feature = "all")))]
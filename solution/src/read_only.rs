@@ -0,0 +1,229 @@
+//! Read-only access to the file systems through a trait object
+//!
+//! The traits in the API are not object safe (e.g. `mkfs` is generic, and every layer has its own error type),
+//! so tools that only inspect a file system cannot take any of the layers as a `&dyn` reference.
+//! [`ReadOnlyFs`] collects the read-only operations of all layers, with `anyhow` errors, and is implemented by every layer.
+//! Operations that a layer does not support return an error.
+//! As its methods share their names with those of the API traits, the trait is best used as `&dyn ReadOnlyFs`, without importing it
+//! where the API traits are in scope as well.
+//!
+//! [`ReadOnlyFs`]: trait.ReadOnlyFs.html
+
+use anyhow::{anyhow, Result};
+use cplfs_api::fs::{BlockSupport, DirectorySupport, InodeRWSupport, InodeSupport};
+use cplfs_api::types::{Block, Buffer, Inode, SuperBlock};
+
+use crate::a_block_support::CustomBlockFileSystem;
+use crate::b_inode_support::CustomInodeFileSystem;
+use crate::c_dirs_support::CustomDirFileSystem;
+use crate::e_inode_RW_support::CustomInodeRWFileSystem;
+use crate::f_indirect_inodes::CustomIndirectFileSystem;
+
+/// Object safe collection of the read-only operations of the file system layers
+pub trait ReadOnlyFs {
+    /// Read the superblock, see `BlockSupport::sup_get`
+    fn sup_get(&self) -> Result<SuperBlock>;
+
+    /// Read the block with number `i`, see `BlockSupport::b_get`
+    fn b_get(&self, i: u64) -> Result<Block>;
+
+    /// Read the inode with number `i`, see `InodeSupport::i_get`
+    fn i_get(&self, _i: u64) -> Result<Inode> {
+        return Err(anyhow!("this file system does not support inodes"));
+    }
+
+    /// Look up the entry `name` in the directory `inode`, see `DirectorySupport::dirlookup`
+    fn dirlookup(&self, _inode: &Inode, _name: &str) -> Result<(Inode, u64)> {
+        return Err(anyhow!("this file system does not support directories"));
+    }
+
+    /// Read `n` bytes from `inode`, starting at offset `off`, into `buf`, see `InodeRWSupport::i_read`
+    fn i_read(&self, _inode: &Inode, _buf: &mut Buffer, _off: u64, _n: u64) -> Result<u64> {
+        return Err(anyhow!("this file system does not support reading inodes"));
+    }
+}
+
+impl ReadOnlyFs for CustomBlockFileSystem {
+    fn sup_get(&self) -> Result<SuperBlock> {
+        return Ok(<Self as BlockSupport>::sup_get(self)?);
+    }
+
+    fn b_get(&self, i: u64) -> Result<Block> {
+        return Ok(<Self as BlockSupport>::b_get(self, i)?);
+    }
+}
+
+impl ReadOnlyFs for CustomInodeFileSystem {
+    fn sup_get(&self) -> Result<SuperBlock> {
+        return Ok(<Self as BlockSupport>::sup_get(self)?);
+    }
+
+    fn b_get(&self, i: u64) -> Result<Block> {
+        return Ok(<Self as BlockSupport>::b_get(self, i)?);
+    }
+
+    fn i_get(&self, i: u64) -> Result<Inode> {
+        return Ok(<Self as InodeSupport>::i_get(self, i)?);
+    }
+}
+
+impl ReadOnlyFs for CustomDirFileSystem {
+    fn sup_get(&self) -> Result<SuperBlock> {
+        return Ok(<Self as BlockSupport>::sup_get(self)?);
+    }
+
+    fn b_get(&self, i: u64) -> Result<Block> {
+        return Ok(<Self as BlockSupport>::b_get(self, i)?);
+    }
+
+    fn i_get(&self, i: u64) -> Result<Inode> {
+        return Ok(<Self as InodeSupport>::i_get(self, i)?);
+    }
+
+    fn dirlookup(&self, inode: &Inode, name: &str) -> Result<(Inode, u64)> {
+        return Ok(<Self as DirectorySupport>::dirlookup(self, inode, name)?);
+    }
+}
+
+impl ReadOnlyFs for CustomInodeRWFileSystem {
+    fn sup_get(&self) -> Result<SuperBlock> {
+        return Ok(<Self as BlockSupport>::sup_get(self)?);
+    }
+
+    fn b_get(&self, i: u64) -> Result<Block> {
+        return Ok(<Self as BlockSupport>::b_get(self, i)?);
+    }
+
+    fn i_get(&self, i: u64) -> Result<Inode> {
+        return Ok(<Self as InodeSupport>::i_get(self, i)?);
+    }
+
+    fn i_read(&self, inode: &Inode, buf: &mut Buffer, off: u64, n: u64) -> Result<u64> {
+        return Ok(<Self as InodeRWSupport>::i_read(self, inode, buf, off, n)?);
+    }
+}
+
+// The operations of the wrapped read/write layer, except for `i_read`, which also has to follow the indirect block
+impl ReadOnlyFs for CustomIndirectFileSystem {
+    fn sup_get(&self) -> Result<SuperBlock> {
+        return Ok(<Self as BlockSupport>::sup_get(self)?);
+    }
+
+    fn b_get(&self, i: u64) -> Result<Block> {
+        return Ok(<Self as BlockSupport>::b_get(self, i)?);
+    }
+
+    fn i_get(&self, i: u64) -> Result<Inode> {
+        return Ok(<Self as InodeSupport>::i_get(self, i)?);
+    }
+
+    fn i_read(&self, inode: &Inode, buf: &mut Buffer, off: u64, n: u64) -> Result<u64> {
+        return Ok(<Self as InodeRWSupport>::i_read(self, inode, buf, off, n)?);
+    }
+}
+
+#[cfg(test)]
+#[path = "../../api/fs-tests"]
+mod test_with_utils {
+    use std::path::PathBuf;
+    use cplfs_api::fs::{DirectorySupport, FileSysSupport, InodeRWSupport, InodeSupport};
+    use cplfs_api::types::{Buffer, FType, SuperBlock};
+
+    use crate::c_dirs_support::CustomDirFileSystem;
+    use crate::e_inode_RW_support::CustomInodeRWFileSystem;
+use crate::f_indirect_inodes::CustomIndirectFileSystem;
+
+    #[path = "utils.rs"]
+    mod utils;
+
+    fn disk_prep_path(name: &str) -> PathBuf {
+        utils::disk_prep_path(&("fs-images-a-".to_string() + name), "img")
+    }
+
+    static SUPERBLOCK_GOOD: SuperBlock = SuperBlock {
+        block_size: 300,
+        nblocks: 11,
+        ninodes: 6,
+        inodestart: 1,
+        ndatablocks: 6,
+        bmapstart: 4,
+        datastart: 5,
+    };
+
+    // `ReadOnlyFs` is not imported, so calling the methods of the file systems themselves is not ambiguous
+
+    /// Find `name` in the root directory, only through the trait object
+    fn lookup_in_root(fs: &dyn super::ReadOnlyFs, name: &str) -> anyhow::Result<u64> {
+        let root = fs.i_get(1)?;
+        let (inode, _) = fs.dirlookup(&root, name)?;
+        return Ok(inode.inum)
+    }
+
+    #[test]
+    fn dyn_lookup() {
+        let path = disk_prep_path("dyn_lookup");
+        let mut my_fs = CustomDirFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let mut iroot = my_fs.i_get(1).unwrap();
+        let inum = my_fs.create(&mut iroot, "file", FType::TFile).unwrap();
+        my_fs.dirlink(&mut iroot, "other", inum).unwrap();
+
+        let ro: &dyn super::ReadOnlyFs = &my_fs;
+        assert_eq!(ro.sup_get().unwrap(), SUPERBLOCK_GOOD);
+        assert_eq!(lookup_in_root(ro, "file").unwrap(), inum);
+        assert_eq!(lookup_in_root(ro, "other").unwrap(), inum);
+        assert!(lookup_in_root(ro, "missing").is_err());
+        let mut buf = Buffer::new_zero(1);
+        assert!(ro.i_read(&iroot, &mut buf, 0, 1).is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dyn_read() {
+        let path = disk_prep_path("dyn_read");
+        let mut my_fs = CustomInodeRWFileSystem::mkfs(&path, &SUPERBLOCK_GOOD).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        my_fs.i_write(&mut ino, &Buffer::new(vec![1, 2, 3].into_boxed_slice()), 0, 3).unwrap();
+
+        let ro: &dyn super::ReadOnlyFs = &my_fs;
+        let mut buf = Buffer::new_zero(3);
+        assert_eq!(ro.i_read(&ro.i_get(inum).unwrap(), &mut buf, 0, 3).unwrap(), 3);
+        assert_eq!(buf.contents_as_ref(), &[1, 2, 3]);
+        assert!(lookup_in_root(ro, "file").is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+
+    #[test]
+    fn dyn_read_indirect() {
+        static SUPERBLOCK_LARGE: SuperBlock = SuperBlock {
+            block_size: 300,
+            nblocks: 30,
+            ninodes: 6,
+            inodestart: 1,
+            ndatablocks: 20,
+            bmapstart: 4,
+            datastart: 5,
+        };
+        let path = disk_prep_path("dyn_read_indirect");
+        let mut my_fs = CustomIndirectFileSystem::mkfs(&path, &SUPERBLOCK_LARGE).unwrap();
+        let inum = my_fs.i_alloc(FType::TFile).unwrap();
+        let mut ino = my_fs.i_get(inum).unwrap();
+        let contents: Vec<u8> = (0..14 * 300).map(|i| (i % 251) as u8).collect();
+        my_fs.i_write(&mut ino, &Buffer::new(contents.clone().into_boxed_slice()), 0, 14 * 300).unwrap();
+
+        // the last blocks of the file are only reachable through the indirect block
+        let ro: &dyn super::ReadOnlyFs = &my_fs;
+        assert_eq!(ro.sup_get().unwrap(), SUPERBLOCK_LARGE);
+        let mut buf = Buffer::new_zero(600);
+        assert_eq!(ro.i_read(&ro.i_get(inum).unwrap(), &mut buf, 12 * 300, 600).unwrap(), 600);
+        assert_eq!(buf.contents_as_ref(), &contents[12 * 300..]);
+        assert!(lookup_in_root(ro, "file").is_err());
+
+        let dev = my_fs.unmountfs();
+        utils::disk_destruct(dev);
+    }
+}